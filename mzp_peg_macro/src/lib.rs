@@ -25,7 +25,7 @@ enum Term {
     AnyChar,
     Capture(String, Box<Term>),
     Choice(Vec<Term>),
-    EOI,
+    Eoi,
     Literal(String, bool),
     NegLookahead(Box<Term>),
     Optional(Box<Term>),
@@ -91,7 +91,7 @@ impl Parse for Term {
                 if input.parse::<ANY>().is_ok() {
                     Ok(Term::AnyChar)
                 } else if input.parse::<EOI>().is_ok() {
-                    Ok(Term::EOI)
+                    Ok(Term::Eoi)
                 } else {
                     input.parse().map(Term::Rule)
                 }
@@ -177,7 +177,7 @@ impl Term {
                 p.any()
             },
             Term::Capture(name, pat) => {
-                let tag = Ident::new(&name, Span::call_site());
+                let tag = Ident::new(name, Span::call_site());
                 let code = pat.generate_code();
                 quote! {
                     {
@@ -192,7 +192,7 @@ impl Term {
                     }
                 }
             }
-            Term::EOI => quote! {
+            Term::Eoi => quote! {
                 p.eoi()
             },
             Term::Rule(ident) => quote! {
@@ -320,14 +320,14 @@ impl Term {
             | Term::Star(term) => {
                 term.set_icase();
             }
-            Term::AnyChar | Term::EOI | Term::Rule(_) => {}
+            Term::AnyChar | Term::Eoi | Term::Rule(_) => {}
         }
     }
 
     fn get_capture_names(&self) -> Vec<&str> {
         let mut result = vec![];
         match self {
-            Term::AnyChar | Term::EOI | Term::Literal(_, _) | Term::Range(_, _) | Term::Rule(_) => {
+            Term::AnyChar | Term::Eoi | Term::Literal(_, _) | Term::Range(_, _) | Term::Rule(_) => {
             }
             Term::Capture(name, term) => {
                 result.push(name.as_str());
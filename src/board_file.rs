@@ -0,0 +1,177 @@
+//! Move boards between worlds and standalone board files (`.BRD`).
+//!
+//! Board files are how KevEdit imports and exports single boards. They only
+//! hold ZZT boards; Super ZZT has no equivalent.
+
+use std::{fs, path::Path};
+
+use anyhow::{Result, anyhow};
+
+use crate::world::{Board, World};
+
+/// ZZT can't load a world with more boards than this, counting the title screen.
+const MAX_BOARDS: usize = 101;
+
+/// Copy a board out of a world into a board file. Returns a status message.
+pub fn extract(world_path: &Path, query: &str, output_path: &Path) -> Result<String> {
+    let world = load_world(world_path)?;
+    let index = find_board(&world, query).map_err(|e| anyhow!("Couldn't extract board: {e}"))?;
+    let board = &world.boards[index];
+    let bytes = board
+        .to_brd()
+        .map_err(|e| anyhow!("Couldn't extract board: {e}"))?;
+    fs::write(output_path, bytes)?;
+    Ok(format!(
+        "Extracted board {index} ({:?}) to {}",
+        board.name,
+        output_path.display()
+    ))
+}
+
+/// Read a board file into a world, either replacing one of its boards or
+/// adding it to the end, and write the resulting world. Returns a status
+/// message.
+pub fn insert(
+    world_path: &Path,
+    board_path: &Path,
+    replace: Option<&str>,
+    output_path: &Path,
+) -> Result<String> {
+    let mut world = load_world(world_path)?;
+    let bytes = fs::read(board_path)
+        .map_err(|e| anyhow!("Couldn't read {}: {}", board_path.display(), e))?;
+    let board = Board::from_brd(&bytes)
+        .map_err(|e| anyhow!("Couldn't load {}: {}", board_path.display(), e))?;
+    let replace = replace
+        .map(|query| find_board(&world, query))
+        .transpose()
+        .map_err(|e| anyhow!("Couldn't insert board: {e}"))?;
+    let name = board.name.clone();
+    let index = insert_board(&mut world, board, replace)
+        .map_err(|e| anyhow!("Couldn't insert board: {e}"))?;
+    let world_bytes = world
+        .to_bytes()
+        .map_err(|e| anyhow!("Couldn't serialize world, {}", e))?;
+    fs::write(output_path, world_bytes)?;
+    let verb = if replace.is_some() {
+        "Replaced"
+    } else {
+        "Added"
+    };
+    Ok(format!(
+        "{verb} board {index} ({name:?}) in {}",
+        output_path.display()
+    ))
+}
+
+fn load_world(path: &Path) -> Result<World> {
+    let bytes = fs::read(path).map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))?;
+    World::from_bytes(&bytes).map_err(|e| anyhow!("Couldn't load {}: {}", path.display(), e))
+}
+
+/// Find a board by its number, or by its name if that's unique.
+pub fn find_board(world: &World, query: &str) -> Result<usize, String> {
+    if let Ok(index) = query.parse::<usize>() {
+        let last = world.boards.len().saturating_sub(1);
+        if index > last {
+            return Err(format!(
+                "there's no board {index}, since the last board is {last}"
+            ));
+        }
+        return Ok(index);
+    }
+    let matches: Vec<_> = (0..world.boards.len())
+        .filter(|&i| world.boards[i].name.eq_ignore_ascii_case(query))
+        .collect();
+    match matches[..] {
+        [] => Err(format!("there's no board named {query:?}")),
+        [index] => Ok(index),
+        _ => {
+            let numbers: Vec<_> = matches.iter().map(|x| x.to_string()).collect();
+            Err(format!(
+                "boards {} are named {query:?}, so use a number instead",
+                numbers.join(", ")
+            ))
+        }
+    }
+}
+
+/// Put a board into a world, replacing the board at the given index or adding
+/// it to the end. Returns where the board ended up.
+///
+/// Boards refer to each other by number, for passages and exits, so adding a
+/// board anywhere but the end would break those links.
+pub fn insert_board(
+    world: &mut World,
+    board: Board,
+    replace: Option<usize>,
+) -> Result<usize, String> {
+    if board.format != world.format {
+        return Err(format!(
+            "a {} board can't go in a {} world",
+            board.format.name(),
+            world.format.name()
+        ));
+    }
+    match replace {
+        Some(index) => {
+            world.boards[index] = board;
+            Ok(index)
+        }
+        None if world.boards.len() >= MAX_BOARDS => Err(format!(
+            "the world already has {MAX_BOARDS} boards, which is as many as it can hold"
+        )),
+        None => {
+            world.boards.push(board);
+            Ok(world.boards.len() - 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::world::Format;
+
+    use super::*;
+
+    fn named(name: &str) -> Board {
+        Board {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_board() {
+        let mut world = World::default();
+        for name in ["Title", "Armory", "Hallway", "hallway"] {
+            world.boards.push(named(name));
+        }
+        assert_eq!(find_board(&world, "1"), Ok(1));
+        assert_eq!(find_board(&world, "armory"), Ok(1));
+        assert_eq!(
+            find_board(&world, "4"),
+            Err("there's no board 4, since the last board is 3".into())
+        );
+        assert_eq!(
+            find_board(&world, "Hallway"),
+            Err("boards 2, 3 are named \"Hallway\", so use a number instead".into())
+        );
+        assert!(find_board(&world, "Cellar").is_err());
+    }
+
+    #[test]
+    fn test_insert_board() {
+        let mut world = World::default();
+        world.boards.push(named("Title"));
+        assert_eq!(insert_board(&mut world, named("Armory"), None), Ok(1));
+        assert_eq!(insert_board(&mut world, named("Cellar"), Some(0)), Ok(0));
+        let names: Vec<_> = world.boards.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["Cellar", "Armory"]);
+
+        assert!(insert_board(&mut world, Board::new(Format::SuperZzt), None).is_err());
+        world.boards.resize_with(MAX_BOARDS, Board::default);
+        assert!(insert_board(&mut world, named("Attic"), None).is_err());
+        assert_eq!(world.boards.len(), MAX_BOARDS);
+    }
+}
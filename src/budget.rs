@@ -0,0 +1,237 @@
+//! Checks that compiled boards fit within ZZT 3.2's (or Super ZZT's) limits.
+//!
+//! A world file can hold boards of up to 64 KB, but ZZT itself refuses or
+//! corrupts boards long before that, and too many stats will crash it.
+
+use crate::{
+    codes::{BOARD_TOO_LARGE, TOO_MANY_STATS},
+    error::{CompileMessage, Context, Level},
+    world::{Board, Format},
+};
+
+/// The largest board ZZT 3.2 or Super ZZT can handle, in bytes.
+pub const MAX_BOARD_SIZE: usize = 20000;
+
+/// Boards bigger than this get a warning. Boards can grow while the game is
+/// running (e.g., when objects `#put` tiles), so it's good to leave some room.
+pub const WARN_BOARD_SIZE: usize = 18000;
+
+/// The most stats ZZT 3.2 supports on a board, not counting the player.
+pub const MAX_STATS: usize = 150;
+
+/// The most stats Super ZZT supports on a board, not counting the player.
+pub const MAX_SUPER_ZZT_STATS: usize = 128;
+
+fn max_stats(format: Format) -> usize {
+    match format {
+        Format::Zzt => MAX_STATS,
+        Format::SuperZzt => MAX_SUPER_ZZT_STATS,
+    }
+}
+
+/// Size of a serialized board's fixed fields: the length prefix, title,
+/// board info, and stat count.
+fn fixed_size(format: Format) -> usize {
+    match format {
+        Format::Zzt => 2 + 51 + 86 + 2,
+        Format::SuperZzt => 2 + 61 + 28 + 2,
+    }
+}
+
+/// Size of a serialized stat, not counting its code.
+fn stat_size(format: Format) -> usize {
+    match format {
+        Format::Zzt => 33,
+        Format::SuperZzt => 25,
+    }
+}
+
+/// How a serialized board's bytes are spent.
+pub struct BoardSize {
+    pub format: Format,
+    /// Size of the run-length encoded terrain
+    pub terrain: usize,
+    /// Size of each stat's code, which is zero for bound stats
+    pub code: Vec<usize>,
+}
+
+impl BoardSize {
+    pub fn measure(board: &Board) -> Self {
+        let code = board
+            .stats
+            .iter()
+            .map(|stat| {
+                if stat.bound_to.is_some() {
+                    0
+                } else {
+                    stat.code.chars().count()
+                }
+            })
+            .collect();
+        BoardSize {
+            format: board.format,
+            terrain: board.encode_terrain().len(),
+            code,
+        }
+    }
+
+    /// Size of all the stats, not counting their code.
+    pub fn stats(&self) -> usize {
+        self.code.len() * stat_size(self.format)
+    }
+
+    pub fn fixed(&self) -> usize {
+        fixed_size(self.format)
+    }
+
+    pub fn total(&self) -> usize {
+        self.fixed() + self.terrain + self.stats() + self.code.iter().sum::<usize>()
+    }
+
+    /// List where the bytes are going, largest objects first.
+    pub fn breakdown(&self, board: &Board) -> String {
+        let code_total: usize = self.code.iter().sum();
+        let mut lines = vec![
+            format!("  board info: {} bytes", self.fixed()),
+            format!("  terrain: {} bytes", self.terrain),
+            format!(
+                "  stats: {} bytes ({} stats)",
+                self.stats(),
+                self.code.len()
+            ),
+            format!("  code: {code_total} bytes"),
+        ];
+
+        // Only the biggest objects are worth trimming
+        let max_objects = 10;
+        let mut objects: Vec<_> = (0..self.code.len()).filter(|&i| self.code[i] > 0).collect();
+        objects.sort_by_key(|&i| std::cmp::Reverse(self.code[i]));
+        for &i in objects.iter().take(max_objects) {
+            let name = board.stats[i].describe();
+            lines.push(format!("    {name}: {} bytes", self.code[i]));
+        }
+        if objects.len() > max_objects {
+            lines.push(format!("    ...and {} more", objects.len() - max_objects));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Report boards that are too big or have too many stats for ZZT.
+pub fn check_board_budget(board: &Board, ctx: &Context) {
+    let game = board.format.name();
+    let num_stats = board.stats.len().saturating_sub(1);
+    let max_stats = max_stats(board.format);
+    if num_stats > max_stats {
+        ctx.error(
+            &TOO_MANY_STATS,
+            &format!(
+                "board has {num_stats} stats, but {game} only supports {max_stats} (not counting the player)"
+            ),
+        );
+    }
+
+    let size = BoardSize::measure(board);
+    let total = size.total();
+    let (level, message) = if total > MAX_BOARD_SIZE {
+        (
+            Level::Error,
+            format!("board is {total} bytes, which is over {game}'s limit of {MAX_BOARD_SIZE}"),
+        )
+    } else if total > WARN_BOARD_SIZE {
+        (
+            Level::Warning,
+            format!("board is {total} bytes, which is close to {game}'s limit of {MAX_BOARD_SIZE}"),
+        )
+    } else {
+        return;
+    };
+    let breakdown = format!("here's where the bytes go:\n{}", size.breakdown(board));
+    ctx.report(CompileMessage::new(level, &BOARD_TOO_LARGE, &message).with_note(&breakdown));
+}
+
+#[cfg(test)]
+mod test {
+    use insta::assert_snapshot;
+
+    use crate::{
+        error::Context,
+        world::{Board, Format, Stat, World, test::board_with_objects},
+    };
+
+    use super::*;
+
+    fn diagnostics(board: Board) -> String {
+        let base_ctx = Context::new();
+        check_board_budget(&board, &base_ctx.with_board(0));
+        let mut world = World::default();
+        world.boards.push(board);
+        let messages: Vec<String> = base_ctx
+            .into_messages()
+            .iter()
+            .map(|x| x.rich_format(&world, false))
+            .collect();
+        messages.join("\n\n")
+    }
+
+    #[test]
+    fn test_measure() {
+        let mut board = board_with_objects(&["@One\n#end\n", "@Two\n"]);
+        board.terrain[100] = [21, 0x0f];
+        let mut bound = board.stats[1].clone();
+        bound.bound_to = Some(1);
+        bound.code.clear();
+        board.stats.push(bound);
+
+        let size = BoardSize::measure(&board);
+        assert_eq!(size.total(), board.to_bytes().unwrap().len());
+
+        let mut board = Board::new(Format::SuperZzt);
+        let stat = Stat {
+            code: "@Three\n#end\n".into(),
+            ..Default::default()
+        };
+        let bound = Stat {
+            bound_to: Some(1),
+            ..Default::default()
+        };
+        board.stats = vec![Stat::default(), stat, bound];
+        let size = BoardSize::measure(&board);
+        assert_eq!(size.total(), board.to_bytes().unwrap().len());
+    }
+
+    #[test]
+    fn test_small_board() {
+        let board = board_with_objects(&["@Small\n#end\n"]);
+        assert_eq!(diagnostics(board), "");
+    }
+
+    #[test]
+    fn test_board_size() {
+        let codes: Vec<String> = (0..12)
+            .map(|i| format!("@Object{i}\n{}", "#end\n".repeat(300 + i * 20)))
+            .collect();
+        assert_snapshot!(diagnostics(board_with_objects(&codes)));
+
+        let codes: Vec<String> = (0..12)
+            .map(|i| format!("@Object{i}\n{}", "#end\n".repeat(300 + i)))
+            .collect();
+        assert_snapshot!(diagnostics(board_with_objects(&codes)));
+    }
+
+    #[test]
+    fn test_stat_count() {
+        let codes = vec![String::new(); MAX_STATS];
+        assert_eq!(diagnostics(board_with_objects(&codes)), "");
+
+        let codes = vec![String::new(); MAX_STATS + 1];
+        assert_snapshot!(diagnostics(board_with_objects(&codes)));
+
+        let mut board = Board::new(Format::SuperZzt);
+        board.stats = vec![Stat::default(); MAX_SUPER_ZZT_STATS + 2];
+        assert_snapshot!(diagnostics(board), @r###"
+        error[E0011]: board has 129 stats, but Super ZZT only supports 128 (not counting the player)
+         =>
+        "###);
+    }
+}
@@ -0,0 +1,288 @@
+//! Stable codes for each kind of diagnostic.
+//!
+//! Every message Marzipan reports has a code like `E0004`, which stays the same
+//! even if the message's wording changes. `marzipan explain E0004` prints the
+//! longer explanation here.
+
+/// A kind of diagnostic.
+#[derive(Debug, PartialEq)]
+pub struct Code {
+    /// The code itself, like `E0004`
+    pub code: &'static str,
+    /// A short name for the kind, like `undefined-label`
+    pub name: &'static str,
+    /// Whether this is a lint, whose level can be changed with `-A`, `-W`, or
+    /// `--deny`, or turned off with an `'mzp:allow` comment. Other problems
+    /// would leave the world broken if they were ignored.
+    pub lint: bool,
+    /// What the diagnostic means and how to fix it, for `marzipan explain`
+    pub explanation: &'static str,
+}
+
+pub const MACRO_SYNTAX: Code = Code {
+    code: "E0001",
+    name: "macro-syntax",
+    lint: false,
+    explanation: "\
+A macro couldn't be parsed.
+
+Lines starting with `%`, and `${...}` interpolations, are read as macro code.
+Common causes are a missing `%end` or `%endif`, an unterminated string, or a
+stray character in an expression:
+
+    %if x == 1
+    #give gems 1
+    (no %endif)
+
+If a line of ZZT-OOP needs to start with `%`, write `${\"%\"}` instead.",
+};
+
+pub const MACRO_ERROR: Code = Code {
+    code: "E0002",
+    name: "macro-error",
+    lint: false,
+    explanation: "\
+A macro couldn't be evaluated.
+
+The macro code parsed fine, but something went wrong while running it: an
+unknown macro or variable, a macro called with the wrong number of args,
+arithmetic on a string, a loop that runs too many times, and so on.
+
+    %define greet(name)
+    Hello, ${name}!
+    %end
+    %greet          (greet takes 1 arg)
+
+If the problem happened inside a macro or loop, the notes say which one.",
+};
+
+pub const INCLUDE_ERROR: Code = Code {
+    code: "E0003",
+    name: "include-error",
+    lint: false,
+    explanation: "\
+A file couldn't be included.
+
+`%include \"name.txt\"` looks for the file in the including file's directory
+(or the world's directory, for an object's own code), and then in each include
+path from `-I` and `marzipan.toml`. This error means the file wasn't found in
+any of them, couldn't be read, or would include itself:
+
+    (a.txt)  %include \"b.txt\"
+    (b.txt)  %include \"a.txt\"
+
+Shared code that might be included more than once can use `%include_once`.",
+};
+
+pub const UNDEFINED_LABEL: Code = Code {
+    code: "E0004",
+    name: "undefined-label",
+    lint: true,
+    explanation: "\
+A message won't be received by any object.
+
+ZZT silently ignores messages to labels that don't exist, which usually means
+a typo:
+
+    @Door
+    #send opne      (there's no :opne label)
+    :open
+
+For messages to other objects, like `#send door:open`, this checks that an
+object named `door` exists on the board and that it has an `:open` label.
+
+Use `--warn-undefined-labels` (or `-W undefined-label`) to report these as
+warnings instead.",
+};
+
+pub const UNUSED_LABEL: Code = Code {
+    code: "E0005",
+    name: "unused-label",
+    lint: true,
+    explanation: "\
+A label never receives any messages.
+
+No object on the board sends a message to this label, and it isn't one of the
+labels that ZZT sends on its own, like `:touch` or `:shot`. The code after it
+only runs if execution falls through from the line above.
+
+    #end
+    :opne           (nothing sends opne)
+    #become empty",
+};
+
+pub const LOCAL_LABEL_DEFINITION: Code = Code {
+    code: "E0006",
+    name: "local-label-definition",
+    lint: false,
+    explanation: "\
+A local label definition names its section.
+
+Local labels belong to the section of the label before them, so their
+definitions can't say which section they're in:
+
+    :touch
+    :touch.again    (write :.again instead)
+
+References can name a section, though: `#send touch.again` works from
+anywhere in the object.",
+};
+
+pub const LOCAL_LABEL_RECIPIENT: Code = Code {
+    code: "E0007",
+    name: "local-label-recipient",
+    lint: false,
+    explanation: "\
+A message to another object's local label doesn't name a section.
+
+Marzipan works out which section `.label` means from where it's used, which
+doesn't work for another object's code:
+
+    #send door:.open        (which section of door's code?)
+    #send door:touch.open   (this works)",
+};
+
+pub const MISSING_ANONYMOUS_LABEL: Code = Code {
+    code: "E0008",
+    name: "missing-anonymous-label",
+    lint: false,
+    explanation: "\
+An anonymous label reference has nothing to jump to.
+
+`@f` jumps forward to the next `:@` label, and `@b` jumps back to the previous
+one. This error means there isn't one in that direction:
+
+    #if blocked n @f
+    #end
+    (no :@ after this)",
+};
+
+pub const ANONYMOUS_LABEL_RECIPIENT: Code = Code {
+    code: "E0009",
+    name: "anonymous-label-recipient",
+    lint: false,
+    explanation: "\
+A message to an anonymous label names a recipient.
+
+Anonymous labels are only meaningful within the object that defines them, so
+they can't be sent to other objects:
+
+    #send door:@f",
+};
+
+pub const TRAILING_CHARACTERS: Code = Code {
+    code: "E0010",
+    name: "trailing-characters",
+    lint: true,
+    explanation: "\
+A command has extra text at the end of its line.
+
+ZZT stops reading a command once it has all its arguments, and ignores
+whatever comes after. This is usually a mistake:
+
+    #give gems 5 10     (the 10 is ignored)",
+};
+
+pub const TOO_MANY_STATS: Code = Code {
+    code: "E0011",
+    name: "too-many-stats",
+    lint: false,
+    explanation: "\
+A board has more stats than ZZT supports.
+
+ZZT allows 150 stats per board, not counting the player, and Super ZZT allows
+128. Stats include
+objects, scrolls, passages, creatures, and other elements with behavior.",
+};
+
+pub const BOARD_TOO_LARGE: Code = Code {
+    code: "E0012",
+    name: "board-too-large",
+    lint: false,
+    explanation: "\
+A board is too big, or close to it.
+
+ZZT can't load boards over 20,000 bytes, and boards near the limit can cause
+trouble while playing, because the board grows as the game is saved. The note
+shows which parts of the board take up the most space. `--minify` can shrink
+object code.",
+};
+
+pub const INVALID_ALLOW: Code = Code {
+    code: "E0013",
+    name: "invalid-allow",
+    lint: true,
+    explanation: "\
+An `'mzp:allow` comment names something that can't be allowed.
+
+`'mzp:allow` takes the names or codes of lints, separated by spaces. The
+lints are `undefined-label`, `unused-label`, `trailing-characters`, and
+`invalid-allow` itself. A name can be shortened to its first word, like
+`trailing`. Other problems, like macro errors, would leave the world broken
+if they were ignored.
+
+    #give gems 5 10 'mzp:allow trailing
+    'mzp:allow macro-error      (not a lint)",
+};
+
+/// Every code, in order.
+pub const ALL_CODES: [&Code; 13] = [
+    &MACRO_SYNTAX,
+    &MACRO_ERROR,
+    &INCLUDE_ERROR,
+    &UNDEFINED_LABEL,
+    &UNUSED_LABEL,
+    &LOCAL_LABEL_DEFINITION,
+    &LOCAL_LABEL_RECIPIENT,
+    &MISSING_ANONYMOUS_LABEL,
+    &ANONYMOUS_LABEL_RECIPIENT,
+    &TRAILING_CHARACTERS,
+    &TOO_MANY_STATS,
+    &BOARD_TOO_LARGE,
+    &INVALID_ALLOW,
+];
+
+/// Find a code by its code (`E0004`, or just `4`) or its name (`undefined-label`).
+/// Names can be shortened to their first word(s), like `undefined`, as long as
+/// that only matches one name.
+pub fn lookup(query: &str) -> Option<&'static Code> {
+    let number = query
+        .strip_prefix(['E', 'e'])
+        .unwrap_or(query)
+        .parse::<usize>()
+        .ok();
+    let exact = ALL_CODES.into_iter().find(|code| {
+        code.name.eq_ignore_ascii_case(query)
+            || number.is_some_and(|x| code.code[1..].parse() == Ok(x))
+    });
+    if exact.is_some() {
+        return exact;
+    }
+    let prefix = format!("{}-", query.to_ascii_lowercase());
+    let mut matches = ALL_CODES
+        .into_iter()
+        .filter(|code| code.name.starts_with(&prefix));
+    match (matches.next(), matches.next()) {
+        (Some(code), None) => Some(code),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("E0004"), Some(&UNDEFINED_LABEL));
+        assert_eq!(lookup("e4"), Some(&UNDEFINED_LABEL));
+        assert_eq!(lookup("Unused-Label"), Some(&UNUSED_LABEL));
+        assert_eq!(lookup("E9999"), None);
+        assert_eq!(lookup("trailing"), Some(&TRAILING_CHARACTERS));
+        assert_eq!(lookup("local"), None);
+        assert_eq!(lookup("trail"), None);
+        for (i, code) in ALL_CODES.iter().enumerate() {
+            assert_eq!(code.code, format!("E{:04}", i + 1));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Compile a world file: evaluate macros, process labels, and check the result.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use rustc_hash::FxHashMap;
+use serde_json::json;
+
+use crate::{
+    budget::check_board_budget,
+    config::{CONFIG_FILENAME, Config},
+    error::{CompileMessage, Context as ErrContext, Level},
+    labels::{Options as LabelOptions, process_labels},
+    lints::{LintLevel, Suppressions, strip_suppressions},
+    minify::minify_board,
+    preprocess::{
+        eval::{Context, Value},
+        source_map::resolve_origins,
+    },
+    world::{Board, World},
+};
+
+/// Everything that controls how a world gets compiled.
+#[derive(Default)]
+pub struct Options {
+    pub input_file: String,
+    pub label_options: LabelOptions,
+    pub minify: bool,
+    /// Variables to set for every stat, from `-D`
+    pub variables: Vec<(String, Value)>,
+    /// Directories to search for included files, from `-I`
+    pub include_paths: Vec<PathBuf>,
+    /// Lint levels from `-A`, `-W`, and `--deny`, in order
+    pub lints: Vec<(String, LintLevel)>,
+}
+
+/// How to print diagnostics.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    /// Rich text, with a snippet of the code, optionally in color
+    Human { color: bool },
+    /// One JSON object per line, for tools. See `CompileMessage::to_json`.
+    Json,
+}
+
+impl MessageFormat {
+    /// Print a line of status for the user. JSON output is meant for tools,
+    /// so in that case, this goes to stderr instead of stdout.
+    pub fn status(self, text: &str) {
+        match self {
+            MessageFormat::Human { .. } => println!("{text}"),
+            MessageFormat::Json => eprintln!("{text}"),
+        }
+    }
+}
+
+pub struct Compilation {
+    pub world: World,
+    pub messages: Vec<CompileMessage>,
+    /// Things worth telling the user that aren't problems, like how much
+    /// minifying saved
+    pub notes: Vec<String>,
+    /// Every file that the result depends on, including files that were looked
+    /// for but don't exist (yet)
+    pub dependencies: Vec<PathBuf>,
+    /// Whether the input was a standalone board file, which gets written back
+    /// out as one
+    pub is_board_file: bool,
+}
+
+/// Check whether a path is a standalone board file (`.BRD`), going by its
+/// extension.
+pub fn is_board_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("brd"))
+}
+
+/// Read and compile a world file.
+///
+/// A standalone board file gets compiled as a world with just that board.
+/// Problems with the world's code end up in the returned messages; an `Err`
+/// means the world couldn't be compiled at all, e.g., because it's missing.
+pub fn compile(options: &Options) -> Result<Compilation> {
+    let input_filename = &options.input_file;
+    let bytes = fs::read(input_filename)?;
+    let is_board_file = is_board_file(Path::new(input_filename));
+    let mut world = if is_board_file {
+        let mut world = World::default();
+        world.boards.push(Board::from_brd(&bytes)?);
+        world
+    } else {
+        World::from_bytes(&bytes)?
+    };
+
+    // Prepare to evaluate macros from the world file's directory.
+    // This isn't canonicalized, so included files get short names in diagnostics.
+    let world_dir = Path::new(input_filename)
+        .parent()
+        .ok_or(anyhow!("Couldn't get world's directory"))?;
+    let config = Config::load(world_dir)?;
+    let mut eval_context = Context::new(world_dir);
+    for path in options.include_paths.iter().chain(&config.include_paths) {
+        eval_context.add_include_path(path);
+    }
+    for (name, value) in &options.variables {
+        eval_context.set_variable(name, value.clone());
+    }
+    let mut lints = config.lints()?;
+    for (name, level) in &options.lints {
+        lints.set(name, *level).map_err(|e| anyhow!("{e}"))?;
+    }
+
+    // Codegen: Evaluate all macros.
+    // Stats with errors keep their original code, so diagnostics can point into it.
+    let base_ctx = ErrContext::new();
+    let lint_filter = |message: &mut CompileMessage| lints.apply(message);
+    let ctx = base_ctx.with_filter(&lint_filter);
+    let ctx = ctx.with_file_path(input_filename);
+    let mut source_maps = FxHashMap::default();
+    for (i, board) in world.boards.iter_mut().enumerate() {
+        let ctx = ctx.with_board(i);
+        for (j, stat) in board.stats.iter_mut().enumerate() {
+            // Bound stats run another stat's code, which is compiled on its own
+            if stat.bound_to.is_some() {
+                continue;
+            }
+            if let Some(output) = eval_context.eval_program(&stat.code, &ctx.with_stat(j)) {
+                stat.code = output.text;
+                source_maps.insert((i, j), output.map);
+            }
+        }
+    }
+
+    // Labels can't be resolved in code that still has macros in it
+    let mut notes = vec![];
+    if !ctx.any_errors() {
+        // Resolve labels to proper ZZT-OOP.
+        // Lints can be allowed by comments, which aren't needed after this.
+        for (i, board) in world.boards.iter_mut().enumerate() {
+            let ctx = ctx.with_board(i);
+            let suppressions: Vec<_> = board
+                .stats
+                .iter()
+                .enumerate()
+                .map(|(j, stat)| Suppressions::parse(&stat.code, &ctx.with_stat(j)))
+                .collect();
+            let filter = |message: &mut CompileMessage| {
+                (message.location.stat).is_none_or(|j| !suppressions[j].allows(message))
+            };
+            let ctx = ctx.with_filter(&filter);
+            if let Some(mut processed_board) = process_labels(board, &options.label_options, &ctx) {
+                for stat in &mut processed_board.stats {
+                    stat.code = strip_suppressions(&stat.code);
+                }
+                *board = processed_board;
+            }
+        }
+
+        // Shrink code, now that it's vanilla ZZT-OOP
+        if options.minify && !ctx.any_errors() {
+            for (i, board) in world.boards.iter_mut().enumerate() {
+                let saved = minify_board(board);
+                if saved > 0 {
+                    let name = &board.name;
+                    notes.push(format!(
+                        "Minified board {i} ({name:?}): saved {saved} bytes"
+                    ));
+                }
+            }
+        }
+
+        // Make sure the boards will still fit in ZZT
+        for (i, board) in world.boards.iter().enumerate() {
+            check_board_budget(board, &ctx.with_board(i));
+        }
+    }
+
+    let mut messages = base_ctx.into_messages();
+    resolve_origins(&mut messages, &source_maps);
+    let mut dependencies = vec![input_filename.into(), world_dir.join(CONFIG_FILENAME)];
+    dependencies.extend(eval_context.dependencies());
+    Ok(Compilation {
+        world,
+        messages,
+        notes,
+        dependencies,
+        is_board_file,
+    })
+}
+
+impl Compilation {
+    pub fn succeeded(&self) -> bool {
+        !self.messages.iter().any(|x| x.level == Level::Error)
+    }
+
+    /// Print all the diagnostics, followed by a summary.
+    ///
+    /// For humans, the summary is skipped if there was nothing to report.
+    pub fn print_diagnostics(&self, format: MessageFormat) {
+        for note in &self.notes {
+            format.status(note);
+        }
+        let mut warnings = 0;
+        let mut errors = 0;
+        for message in &self.messages {
+            match message.level {
+                Level::Error => errors += 1,
+                Level::Warning => warnings += 1,
+            }
+        }
+
+        let color = match format {
+            MessageFormat::Human { color } => color,
+            MessageFormat::Json => {
+                for message in &self.messages {
+                    println!("{}", message.to_json(&self.world));
+                }
+                let summary = json!({
+                    "type": "summary",
+                    "success": errors == 0,
+                    "errors": errors,
+                    "warnings": warnings,
+                });
+                println!("{summary}");
+                return;
+            }
+        };
+        for message in &self.messages {
+            println!("{}\n", message.rich_format(&self.world, color));
+        }
+        if self.messages.is_empty() {
+            return;
+        }
+        let plural = |x| if x == 1 { "" } else { "s" };
+        if errors == 0 {
+            println!(
+                "Compilation succeeded with {warnings} warning{}.",
+                plural(warnings)
+            )
+        } else {
+            println!(
+                "Compilation failed with {warnings} warning{} and {errors} error{}.",
+                plural(warnings),
+                plural(errors)
+            );
+        }
+    }
+
+    /// Write the compiled world to a file, or just its board if it came from
+    /// a board file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let bytes = if self.is_board_file {
+            self.world.boards[0]
+                .to_brd()
+                .map_err(|e| anyhow!("Couldn't serialize board, {}", e))?
+        } else {
+            self.world
+                .to_bytes()
+                .map_err(|e| anyhow!("Couldn't serialize world, {}", e))?
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Project settings, read from a `marzipan.toml` next to the world file.
+//!
+//! Anything that can be set in the config file can also be set on the command
+//! line, so the config file is just a way to avoid typing the same flags on
+//! every build.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+use crate::lints::{LintLevel, Lints};
+
+pub const CONFIG_FILENAME: &str = "marzipan.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Directories to search for `%include` files, after the including file's
+    /// own directory. Relative paths are relative to the config file.
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    /// Levels for lints, like `trailing-characters = "allow"`. Flags like
+    /// `-A` override these.
+    #[serde(default)]
+    pub lints: BTreeMap<String, LintLevel>,
+}
+
+impl Config {
+    /// Load the config file from a world's directory, if there is one.
+    pub fn load(world_dir: &Path) -> Result<Config> {
+        let path = world_dir.join(CONFIG_FILENAME);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))?;
+        let mut config = Config::parse(&text)
+            .map_err(|e| anyhow!("Couldn't parse {}: {}", path.display(), e))?;
+        for include_path in &mut config.include_paths {
+            *include_path = world_dir.join(&include_path);
+        }
+        Ok(config)
+    }
+
+    /// Load the nearest config file in a directory or any of its parents.
+    ///
+    /// This is for tools that start from an included file rather than a
+    /// world, like the language server.
+    pub fn find(dir: &Path) -> Result<Config> {
+        for dir in dir.ancestors() {
+            if dir.join(CONFIG_FILENAME).exists() {
+                return Config::load(dir);
+            }
+        }
+        Ok(Config::default())
+    }
+
+    /// Get the lint levels from the `[lints]` table.
+    pub fn lints(&self) -> Result<Lints> {
+        let mut lints = Lints::default();
+        for (name, level) in &self.lints {
+            lints
+                .set(name, *level)
+                .map_err(|e| anyhow!("In {CONFIG_FILENAME}: {e}"))?;
+        }
+        Ok(lints)
+    }
+
+    fn parse(text: &str) -> Result<Config> {
+        Ok(toml::from_str(text)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse("include_paths = [\"lib\", \"../shared\"]").unwrap();
+        assert_eq!(
+            config.include_paths,
+            vec![PathBuf::from("lib"), PathBuf::from("../shared")]
+        );
+        assert!(Config::parse("").unwrap().include_paths.is_empty());
+        assert!(Config::parse("include_path = \"lib\"").is_err());
+
+        let config = Config::parse("[lints]\ntrailing-characters = \"allow\"").unwrap();
+        assert_eq!(
+            config.lints.get("trailing-characters"),
+            Some(&LintLevel::Allow)
+        );
+        assert!(Config::parse("[lints]\nunused = \"ignore\"").is_err());
+    }
+}
@@ -8,7 +8,7 @@ pub fn encode_multiline(input: &str) -> Result<Vec<u8>> {
         .chars()
         .map(|c| {
             if c == '\n' {
-                Ok('\r' as u8)
+                Ok(b'\r')
             } else {
                 CP437_WINGDINGS
                     .encode(c)
@@ -23,7 +23,7 @@ pub fn decode_multiline(input: &[u8]) -> String {
     input
         .iter()
         .map(|&x| {
-            if x == '\r' as u8 {
+            if x == b'\r' {
                 '\n'
             } else {
                 CP437_WINGDINGS.decode(x)
@@ -1,6 +1,8 @@
-use std::{cell::RefCell, collections::VecDeque, error::Error, fmt::Display, ops::Range};
+use std::{cell::RefCell, collections::VecDeque, error::Error, fmt::Display, ops::Range, rc::Rc};
 
-use crate::world::World;
+use serde_json::json;
+
+use crate::{codes::Code, world::World};
 
 pub enum Context<'a> {
     Base(Box<RefCell<Vec<CompileMessage>>>),
@@ -12,6 +14,8 @@ pub enum ContextInfo<'a> {
     Board(usize),
     Stat(usize),
     Span(Range<usize>),
+    /// Adjust messages, or drop them by returning false
+    Filter(&'a dyn Fn(&mut CompileMessage) -> bool),
 }
 
 impl<'a> Context<'a> {
@@ -35,6 +39,13 @@ impl<'a> Context<'a> {
         Self::With(self, ContextInfo::Span(r))
     }
 
+    /// Pass every message through a filter, which can change its level or
+    /// drop it. Filters run after the contexts inside them have filled in the
+    /// message's location.
+    pub fn with_filter(&'a self, f: &'a dyn Fn(&mut CompileMessage) -> bool) -> Self {
+        Self::With(self, ContextInfo::Filter(f))
+    }
+
     fn store(&self, mut message: CompileMessage) {
         match self {
             Context::Base(refcell) => refcell.borrow_mut().push(message),
@@ -53,26 +64,32 @@ impl<'a> Context<'a> {
                     ContextInfo::Span(r) => {
                         location.span.get_or_insert(r.clone());
                     }
+                    ContextInfo::Filter(f) => {
+                        if !f(&mut message) {
+                            return;
+                        }
+                    }
                 };
                 parent.store(message);
             }
         }
     }
 
-    pub fn error(&self, message: &str) {
-        self.store(CompileMessage {
-            level: Level::Error,
-            message: message.into(),
-            location: Location::default(),
-        });
+    pub fn message(&self, level: Level, code: &'static Code, message: &str) {
+        self.report(CompileMessage::new(level, code, message));
     }
 
-    pub fn warning(&self, message: &str) {
-        self.store(CompileMessage {
-            level: Level::Warning,
-            message: message.into(),
-            location: Location::default(),
-        });
+    pub fn error(&self, code: &'static Code, message: &str) {
+        self.message(Level::Error, code, message);
+    }
+
+    pub fn warning(&self, code: &'static Code, message: &str) {
+        self.message(Level::Warning, code, message);
+    }
+
+    /// Report a message that has labels or notes.
+    pub fn report(&self, message: CompileMessage) {
+        self.store(message);
     }
 
     pub fn any_errors(&self) -> bool {
@@ -89,7 +106,7 @@ impl<'a> Context<'a> {
                 messages.sort_by_key(|msg| {
                     let loc = &msg.location;
                     (
-                        loc.file_path.as_ref().map(|x| x.clone()),
+                        loc.file_path.clone(),
                         loc.board,
                         loc.stat,
                         loc.span.as_ref().map(|s| s.start),
@@ -105,40 +122,159 @@ impl<'a> Context<'a> {
 #[derive(Debug)]
 pub struct CompileMessage {
     pub level: Level,
+    pub code: &'static Code,
     pub message: String,
     pub location: Location,
+    /// Other spans worth pointing out, like a previous definition
+    pub labels: Vec<SpanLabel>,
+    /// Extra information shown after the code
+    pub notes: Vec<Note>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Level {
     Error,
     Warning,
 }
 
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Location {
     pub file_path: Option<String>,
     pub board: Option<usize>,
     pub stat: Option<usize>,
     pub span: Option<Range<usize>>,
+    /// Where the span came from before macros were expanded
+    pub origin: Option<Origin>,
+}
+
+/// A span of the code that a stat's code was generated from: either the
+/// stat's code before macros were expanded, or a file it included.
+#[derive(Clone, Debug)]
+pub struct Origin {
+    /// The included file, if the code came from one
+    pub file_path: Option<String>,
+    /// The full text of the file, or of the stat's original code
+    pub text: Rc<str>,
+    pub span: Range<usize>,
+    /// The `%include` lines that led to the file, innermost first, like
+    /// `lib.txt line 3` or `line 2` (in the stat's code)
+    pub included_from: Vec<String>,
+}
+
+/// A secondary span of a message, like "previous definition here". It's in the
+/// same stat as the message's own span.
+#[derive(Clone, Debug)]
+pub struct SpanLabel {
+    pub span: Range<usize>,
+    /// Where the span came from before macros were expanded
+    pub origin: Option<Origin>,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NoteKind {
+    /// Background on why something happened
+    Note,
+    /// A suggestion for fixing the problem
+    Help,
+}
+
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub kind: NoteKind,
+    pub message: String,
+}
+
+impl NoteKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteKind::Note => "note",
+            NoteKind::Help => "help",
+        }
+    }
 }
 
 impl Display for CompileMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let level = match self.level {
-            Level::Error => "error",
-            Level::Warning => "warning",
-        };
-        write!(f, "{}: {}", level, self.message)
+        write!(
+            f,
+            "{}[{}]: {}",
+            self.level.name(),
+            self.code.code,
+            self.message
+        )
     }
 }
 
 impl Error for CompileMessage {}
 
 impl CompileMessage {
-    pub fn rich_format(&self, world: &World) -> String {
-        // Get base error message
-        let message = self.to_string();
+    pub fn new(level: Level, code: &'static Code, message: &str) -> Self {
+        CompileMessage {
+            level,
+            code,
+            message: message.into(),
+            location: Location::default(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn error(code: &'static Code, message: &str) -> Self {
+        Self::new(Level::Error, code, message)
+    }
+
+    /// Point out another span of the stat's code.
+    pub fn with_label(mut self, span: Range<usize>, message: &str) -> Self {
+        self.labels.push(SpanLabel {
+            span,
+            origin: None,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, message: &str) -> Self {
+        self.notes.push(Note {
+            kind: NoteKind::Note,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, message: &str) -> Self {
+        self.notes.push(Note {
+            kind: NoteKind::Help,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Format the message for a terminal, with a snippet of the code. With
+    /// `color`, this includes ANSI escape codes.
+    pub fn rich_format(&self, world: &World, color: bool) -> String {
+        let paint = Paint(color);
+        let level_style = match self.level {
+            Level::Error => RED,
+            Level::Warning => YELLOW,
+        };
+        let heading = paint.apply(
+            level_style,
+            &format!("{}[{}]", self.level.name(), self.code.code),
+        );
+        let message = format!(
+            "{heading}{}",
+            paint.apply(BOLD, &format!(": {}", self.message))
+        );
 
         // Build hierarchy string: world -> board -> stat -> span of code
         let mut breadcrumbs = vec![];
@@ -152,46 +288,45 @@ impl CompileMessage {
         }
         let stat = board.and_then(|board| location.stat.map(|i| &board.stats[i]));
         if let Some(stat) = stat {
-            let first_line = stat.code.lines().next();
-            let name = first_line.filter(|x| x.starts_with("@")).unwrap_or("stat");
-            let (x, y) = (stat.x, stat.y);
-            breadcrumbs.push(format!("{name} ({x},{y})"));
+            breadcrumbs.push(stat.describe());
         }
-        let span = stat.and_then(|stat| {
-            location
-                .span
-                .as_ref()
-                .map(|span| RichSpan::new(&span, &stat.code))
-        });
+        let code = stat.map(|x| x.code.as_str());
+        let span = RichSpan::locate(code, location.span.as_ref(), location.origin.as_ref());
         if let Some(ref span) = span {
-            let line = span.line_number;
-            let col = span.line_span.start + 1;
-            breadcrumbs.push(format!("line {line}:{col}"))
+            breadcrumbs.push(span.describe());
+        }
+        let arrow = paint.apply(BLUE, "=>");
+        let mut breadcrumbs = format!(" {arrow} {}", breadcrumbs.join(" -> "));
+        if let Some(origin) = &location.origin {
+            for include in &origin.included_from {
+                breadcrumbs.push_str(&format!("\n    included from {include}"));
+            }
         }
-        let breadcrumbs = format!(" => {}", breadcrumbs.join(" -> "));
 
         // Build context block
+        let number_width = span.as_ref().map_or(0, |span| {
+            span.nearby_lines.last().unwrap().0.to_string().len()
+        });
+        let gutter = |number: &str| paint.apply(BLUE, &format!(" {number:>number_width$} |"));
         let context = span.map(|span| {
             let mut block = vec![];
 
             // Add padding line at start
-            let last_line_number = span.nearby_lines.last().unwrap().0;
-            let number_width = last_line_number.to_string().len();
-            let prefix = format!(" {:number_width$} |", "");
-            block.push(prefix.clone());
+            block.push(gutter(""));
 
             // Add each of the context lines
             let mut needs_end_padding = false;
             for (line_number, line) in &span.nearby_lines {
-                block.push(format!(" {line_number:>number_width$} | {line}"));
+                block.push(format!("{} {line}", gutter(&line_number.to_string())));
                 needs_end_padding = true;
 
                 // Add highlight
                 if line_number == &span.line_number {
                     block.push(format!(
-                        "{prefix} {}{}",
+                        "{} {}{}",
+                        gutter(""),
                         " ".repeat(span.line_span.start),
-                        "^".repeat(span.line_span.len())
+                        paint.apply(level_style, &"^".repeat(span.line_span.len()))
                     ));
                     needs_end_padding = false;
                 }
@@ -199,7 +334,7 @@ impl CompileMessage {
 
             // Add padding line at end
             if needs_end_padding {
-                block.push(prefix);
+                block.push(gutter(""));
             }
 
             block.join("\n")
@@ -209,17 +344,192 @@ impl CompileMessage {
         if let Some(context) = context {
             parts.push(context);
         }
+
+        // Show each label's line of code on its own
+        for label in &self.labels {
+            let Some(span) = RichSpan::locate(code, Some(&label.span), label.origin.as_ref())
+            else {
+                continue;
+            };
+            let (line_number, line) = span
+                .nearby_lines
+                .iter()
+                .find(|x| x.0 == span.line_number)
+                .unwrap();
+            let width = line_number.to_string().len();
+            let gutter = |number: &str| paint.apply(BLUE, &format!(" {number:>width$} |"));
+            let marker = "-".repeat(span.line_span.len().max(1));
+            parts.push(
+                [
+                    format!(" {} {}", paint.apply(BLUE, ":::"), span.describe()),
+                    gutter(""),
+                    format!("{} {line}", gutter(&line_number.to_string())),
+                    format!(
+                        "{} {}{}",
+                        gutter(""),
+                        " ".repeat(span.line_span.start),
+                        paint.apply(BLUE, &format!("{marker} {}", label.message))
+                    ),
+                ]
+                .join("\n"),
+            );
+        }
+
+        // Notes go last, lined up with the code's gutter
+        for note in &self.notes {
+            let kind = note.kind.name();
+            let indent = " ".repeat(number_width + kind.len() + 6);
+            let text = note.message.replace('\n', &format!("\n{indent}"));
+            let kind = paint.apply(BOLD, &format!("{kind}:"));
+            parts.push(format!(
+                " {:number_width$} {} {kind} {text}",
+                "",
+                paint.apply(BLUE, "=")
+            ));
+        }
         parts.join("\n")
     }
 }
 
+impl CompileMessage {
+    /// Describe this message as JSON, for editor plugins and other tools.
+    ///
+    /// `line` and `column` count from 1, with columns in characters. `span` is
+    /// in bytes, and like `line`, it's relative to `source_file` if there is
+    /// one, or else the stat's code. Labels' positions work the same way.
+    pub fn to_json(&self, world: &World) -> serde_json::Value {
+        let location = &self.location;
+        let board = location.board.map(|i| &world.boards[i]);
+        let stat = board.and_then(|board| location.stat.map(|i| &board.stats[i]));
+        let code = stat.map(|x| x.code.as_str());
+        let origin = location.origin.as_ref();
+        let position = json_position(code, location.span.as_ref(), origin);
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let mut result = json!({"message": label.message});
+                let position = json_position(code, Some(&label.span), label.origin.as_ref());
+                result.as_object_mut().unwrap().extend(position);
+                result
+            })
+            .collect();
+        let notes: Vec<_> = self
+            .notes
+            .iter()
+            .map(|note| json!({"kind": note.kind.name(), "message": note.message}))
+            .collect();
+        let mut result = json!({
+            "type": "diagnostic",
+            "level": self.level.name(),
+            "code": self.code.code,
+            "message": self.message,
+            "file": location.file_path,
+            "board": location.board,
+            "board_name": board.map(|x| &x.name),
+            "stat": location.stat,
+            "x": stat.map(|x| x.x),
+            "y": stat.map(|x| x.y),
+            "object": stat.and_then(|x| x.name()),
+            "included_from": origin.map(|x| &x.included_from[..]).unwrap_or_default(),
+        });
+        let fields = result.as_object_mut().unwrap();
+        fields.extend(position);
+        fields.insert("labels".into(), labels.into());
+        fields.insert("notes".into(), notes.into());
+        result
+    }
+}
+
+/// Get the JSON fields for where a span is: `source_file`, `line`, `column`,
+/// and `span`.
+fn json_position(
+    code: Option<&str>,
+    span: Option<&Range<usize>>,
+    origin: Option<&Origin>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let source = match origin {
+        Some(origin) => Some((&*origin.text, &origin.span)),
+        None => code.zip(span),
+    };
+    let (line, column) = source
+        .map(|(text, span)| line_and_column(text, span.start))
+        .unzip();
+    let result = json!({
+        "source_file": origin.and_then(|x| x.file_path.as_ref()),
+        "line": line,
+        "column": column,
+        "span": source.map(|(_, span)| json!({"start": span.start, "end": span.end})),
+    });
+    match result {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+/// Find the line and column of a byte offset, both counting from 1.
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Styles text with ANSI escape codes, if color is turned on.
+#[derive(Clone, Copy)]
+struct Paint(bool);
+
+const BOLD: &str = "1";
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+
+impl Paint {
+    fn apply(self, style: &str, text: &str) -> String {
+        match self.0 {
+            true => format!("\x1b[{style}m{text}\x1b[0m"),
+            false => text.into(),
+        }
+    }
+}
+
 struct RichSpan<'a> {
+    /// The included file that the span is in, if any
+    path: Option<&'a str>,
     line_number: usize,
     line_span: Range<usize>,
     nearby_lines: Vec<(usize, &'a str)>,
 }
 
 impl<'a> RichSpan<'a> {
+    /// Find a span of a stat's code, or the code it came from if `origin` is
+    /// set. There's nothing to show for messages that aren't about a stat.
+    fn locate(
+        code: Option<&'a str>,
+        span: Option<&Range<usize>>,
+        origin: Option<&'a Origin>,
+    ) -> Option<Self> {
+        let code = code?;
+        match origin {
+            Some(origin) => {
+                let mut result = RichSpan::new(&origin.span, &origin.text);
+                result.path = origin.file_path.as_deref();
+                Some(result)
+            }
+            None => span.map(|span| RichSpan::new(span, code)),
+        }
+    }
+
+    /// Describe where the span starts, like `lib.txt line 3:5`.
+    fn describe(&self) -> String {
+        let line = self.line_number;
+        let col = self.line_span.start + 1;
+        match self.path {
+            Some(path) => format!("{path} line {line}:{col}"),
+            None => format!("line {line}:{col}"),
+        }
+    }
+
     fn new(span: &Range<usize>, code: &'a str) -> Self {
         // Track byte ranges and line numbers for each line
         let mut offset = 0;
@@ -264,9 +574,86 @@ impl<'a> RichSpan<'a> {
         recent.make_contiguous();
 
         Self {
+            path: None,
             line_number: found_line_number,
             line_span: found_line_span,
             nearby_lines: recent.into_iter().collect(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use insta::assert_snapshot;
+
+    use crate::{
+        codes::{TOO_MANY_STATS, TRAILING_CHARACTERS, UNDEFINED_LABEL},
+        world::{Board, Format},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        board.stats[0].code = "@Guard\n#end\n:touch\n#send ☺nowhere".into();
+        let mut world = World::default();
+        world.boards.push(board);
+
+        let base_ctx = Context::new();
+        let file_ctx = base_ctx.with_file_path("test.zzt");
+        let board_ctx = file_ctx.with_board(0);
+        let stat_ctx = board_ctx.with_stat(0);
+        stat_ctx.with_span(25..35).report(
+            CompileMessage::error(&UNDEFINED_LABEL, "label `☺nowhere` is not defined")
+                .with_label(13..18, "similar label here")
+                .with_help("did you mean `touch`?"),
+        );
+        let mut message = CompileMessage::new(Level::Warning, &TRAILING_CHARACTERS, "from a file");
+        message.location.origin = Some(Origin {
+            file_path: Some("lib.txt".into()),
+            text: "'lib\n  #end".into(),
+            span: 7..11,
+            included_from: vec!["line 2".into()],
+        });
+        stat_ctx.with_span(7..11).report(message);
+        base_ctx
+            .with_file_path("test.zzt")
+            .warning(&TOO_MANY_STATS, "world-level problem");
+        let messages: Vec<_> = base_ctx
+            .into_messages()
+            .iter()
+            .map(|x| serde_json::to_string_pretty(&x.to_json(&world)).unwrap())
+            .collect();
+        assert_snapshot!(messages.join("\n"));
+    }
+
+    #[test]
+    fn test_color() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut world = World::default();
+        world
+            .boards
+            .push(Board::from_bytes(&blank, Format::Zzt).unwrap());
+        world.boards[0].stats[0].code = "#send nowhere".into();
+
+        let base_ctx = Context::new();
+        base_ctx
+            .with_board(0)
+            .with_stat(0)
+            .with_span(6..13)
+            .error(&UNDEFINED_LABEL, "label `nowhere` is not defined");
+        let message = &base_ctx.into_messages()[0];
+        assert_snapshot!(message.rich_format(&world, true).replace('\x1b', "ESC"), @r###"
+        ESC[1;31merror[E0004]ESC[0mESC[1m: label `nowhere` is not definedESC[0m
+         ESC[1;34m=>ESC[0m Title screen -> stat (1,1) -> line 1:7
+        ESC[1;34m   |ESC[0m
+        ESC[1;34m 1 |ESC[0m #send nowhere
+        ESC[1;34m   |ESC[0m       ESC[1;31m^^^^^^^ESC[0m
+        "###);
+        assert!(!message.rich_format(&world, false).contains('\x1b'));
+    }
+}
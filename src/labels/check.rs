@@ -0,0 +1,301 @@
+use compact_str::CompactString;
+use rustc_hash::FxHashSet;
+
+use crate::{
+    codes::{UNDEFINED_LABEL, UNUSED_LABEL},
+    error::{CompileMessage, Context, Level},
+    oop::ast::{Program, StatementKind},
+    world::Board,
+};
+
+use super::{
+    parse::{Chunk, LabelName, ParsedStat, Recipient},
+    sanitize::BUILTIN_LABELS,
+};
+
+/// Check that label references and definitions line up across a board.
+///
+/// - A message that no object will receive gets reported at `undefined_level`,
+///   whether that's because its recipient doesn't name any object, or because
+///   none of the receiving objects define the label. ZZT silently ignores
+///   messages like these.
+/// - A label that no message can reach gets a warning, unless it's one of the
+///   built-in event labels like `touch`.
+///
+/// Recipients are resolved the way ZZT does it: `self`, `others` and `all`
+/// are special, and any other name matches the objects whose code starts with
+/// `@name`.
+///
+/// This expects local labels to be resolved, but not yet sanitized.
+pub fn check_label_usage(
+    board: &Board,
+    stats: &[ParsedStat],
+    undefined_level: Level,
+    ctx: &Context,
+) {
+    // Bound objects run another stat's code, so look through the binding
+    let code_indices: Vec<usize> = (0..stats.len()).map(|i| board.code_index(i)).collect();
+    let programs: Vec<_> = board
+        .stats
+        .iter()
+        .map(|stat| Program::parse(&stat.code))
+        .collect();
+    let names: Vec<_> = code_indices
+        .iter()
+        .map(|&i| object_name(&programs[i]))
+        .collect();
+
+    // Gather each object's label definitions.
+    // A zapped label like `'foo` counts as a definition too, because it
+    // can be brought back with `#restore foo`.
+    let defined: Vec<FxHashSet<CompactString>> = code_indices
+        .iter()
+        .map(|&i| {
+            let mut result = zapped_label_keys(&programs[i]);
+            for chunk in &stats[i] {
+                if let Chunk::Label {
+                    is_ref: false,
+                    is_anon: false,
+                    name,
+                } = chunk
+                {
+                    result.insert(label_key(name));
+                }
+            }
+            result
+        })
+        .collect();
+
+    // Deliver each message, making sure at least one object can receive it
+    let mut received = vec![FxHashSet::default(); stats.len()];
+    for (sender, parsed) in stats.iter().enumerate() {
+        let ctx = ctx.with_stat(sender);
+        let code = &board.stats[sender].code;
+        for chunk in parsed {
+            let Chunk::Label {
+                is_ref: true,
+                is_anon: false,
+                name,
+            } = chunk
+            else {
+                continue;
+            };
+            if name.name.is_empty() && name.recipient.as_ref().is_some_and(|r| !r.is_self()) {
+                // Unresolved local label like `door:.foo`; already reported during parsing
+                continue;
+            }
+
+            let key = label_key(name);
+            let text = &code[name.span.clone()];
+            let targets = resolve_recipient(&names, sender, name.recipient.as_ref());
+            let receivers: Vec<_> = targets
+                .iter()
+                .filter(|&&i| defined[i].contains(&key))
+                .collect();
+            for &i in &receivers {
+                received[code_indices[*i]].insert(key.clone());
+            }
+            if !receivers.is_empty() {
+                continue;
+            }
+
+            // Nobody will receive this message; figure out how to explain why
+            let (span, message) = match &name.recipient {
+                None => (
+                    name.span.clone(),
+                    format!("label `{text}` is not defined in this object"),
+                ),
+                Some(recipient) => {
+                    let recipient_text = &code[recipient.span.clone()];
+                    match recipient.name.to_ascii_lowercase().as_str() {
+                        "self" => (
+                            name.span.clone(),
+                            format!("label `{text}` is not defined in this object"),
+                        ),
+                        "all" => (
+                            name.span.clone(),
+                            format!("no object on this board has label `{text}`"),
+                        ),
+                        "others" => (
+                            name.span.clone(),
+                            format!("no other object on this board has label `{text}`"),
+                        ),
+                        _ if targets.is_empty() => (
+                            recipient.span.clone(),
+                            format!("no object named `{recipient_text}` on this board"),
+                        ),
+                        _ => (
+                            name.span.clone(),
+                            format!("object `{recipient_text}` has no label `{text}`"),
+                        ),
+                    }
+                }
+            };
+            let mut message = CompileMessage::new(undefined_level, &UNDEFINED_LABEL, &message);
+
+            // Typos are the most likely cause, so look for something close
+            let suggestion = if targets.is_empty() {
+                let names = names.iter().flatten().map(|x| x.as_str());
+                similar_word(&name_text(name.recipient.as_ref()), names)
+            } else {
+                // Disambiguated local labels like `touch$1.foo` aren't worth suggesting
+                let labels = targets.iter().flat_map(|&i| &defined[i]);
+                similar_word(
+                    &key,
+                    labels.map(|x| x.as_str()).filter(|x| !x.contains('$')),
+                )
+            };
+            if let Some(suggestion) = suggestion {
+                message = message.with_help(&format!("did you mean `{suggestion}`?"));
+
+                // Point out the label, if it's in the same object
+                let definition = parsed.iter().find_map(|chunk| match chunk {
+                    Chunk::Label {
+                        is_ref: false,
+                        is_anon: false,
+                        name,
+                    } if label_key(name) == suggestion => Some(name.span.clone()),
+                    _ => None,
+                });
+                if let Some(definition) = definition {
+                    message = message.with_label(definition, "similar label defined here");
+                }
+            }
+            ctx.with_span(span).report(message);
+        }
+    }
+
+    // Find labels that never receive any messages
+    for (stat_index, parsed) in stats.iter().enumerate() {
+        let ctx = ctx.with_stat(stat_index);
+        let code = &board.stats[stat_index].code;
+        for chunk in parsed {
+            if let Chunk::Label {
+                is_ref: false,
+                is_anon: false,
+                name,
+            } = chunk
+            {
+                let key = label_key(name);
+                if !received[stat_index].contains(&key) && !BUILTIN_LABELS.contains(&key.as_str()) {
+                    let text = &code[name.span.clone()];
+                    ctx.with_span(name.span.clone())
+                        .warning(&UNUSED_LABEL, &format!("label `{text}` is never used"));
+                }
+            }
+        }
+    }
+}
+
+/// Get the indices of the stats that a message could be delivered to.
+fn resolve_recipient(
+    names: &[Option<CompactString>],
+    sender: usize,
+    recipient: Option<&Recipient>,
+) -> Vec<usize> {
+    let Some(recipient) = recipient else {
+        return vec![sender];
+    };
+    let recipient = recipient.name.to_ascii_lowercase();
+    match recipient.as_str() {
+        "self" => vec![sender],
+        "all" => (0..names.len()).collect(),
+        "others" => (0..names.len()).filter(|&i| i != sender).collect(),
+        _ => (0..names.len())
+            .filter(|&i| names[i].as_deref() == Some(&recipient))
+            .collect(),
+    }
+}
+
+/// Get the name other objects use to send messages to this object.
+///
+/// ZZT only looks at the first word of an `@name` line, so an object called
+/// `@Guard dog` can be reached with `#send guard:bark`.
+pub fn object_name(program: &Program) -> Option<CompactString> {
+    let first_line = program.lines.first()?;
+    match first_line.statements.first()?.kind {
+        StatementKind::Name(name) => leading_word_key(name),
+        _ => None,
+    }
+}
+
+/// Get the lowercased name of a recipient.
+fn name_text(recipient: Option<&Recipient>) -> CompactString {
+    name_key(recipient.map_or("", |x| &x.name))
+}
+
+/// Find the candidate closest to a word, if any are close enough to be a typo.
+fn similar_word<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (word.len() / 3).max(1);
+    candidates
+        .map(|x| (edit_distance(word, x), x))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, x)| x)
+}
+
+/// Count the insertions, deletions, substitutions, and swaps of neighboring
+/// characters it takes to turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // distances[i][j] is the distance between a[..i] and b[..j]
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = best;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Get a key for comparing labels. Like ZZT, this ignores case.
+fn label_key(name: &LabelName) -> CompactString {
+    name_key(&name.full_name())
+}
+
+/// Get a key for comparing the names of labels or objects, which ZZT matches
+/// without regard to case.
+pub fn name_key(name: &str) -> CompactString {
+    let mut result = CompactString::from(name);
+    result.make_ascii_lowercase();
+    result
+}
+
+/// Get the keys for all of the zapped labels (comments) in an object.
+fn zapped_label_keys(program: &Program) -> FxHashSet<CompactString> {
+    program
+        .lines
+        .iter()
+        .flat_map(|line| &line.statements)
+        .filter_map(|statement| match statement.kind {
+            StatementKind::Comment(text) => leading_word_key(text),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Get the lowercased word at the start of a string, if there is one.
+///
+/// ZZT finds names like `'foo` or `@foo` by reading a word and ignoring any
+/// text that follows.
+pub fn leading_word_key(s: &str) -> Option<CompactString> {
+    let len = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(s.len());
+    (len > 0).then(|| name_key(&s[..len]))
+}
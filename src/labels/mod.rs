@@ -1,5 +1,6 @@
+pub mod check;
 pub mod parse;
 pub mod process;
 pub mod sanitize;
 
-pub use process::process_labels;
+pub use process::{Options, process_labels};
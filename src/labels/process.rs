@@ -1,14 +1,33 @@
 use compact_str::CompactString;
 use rustc_hash::FxHashMap;
 
-use crate::{error::Context, world::Board};
+use crate::{
+    codes::{LOCAL_LABEL_DEFINITION, MISSING_ANONYMOUS_LABEL},
+    error::{CompileMessage, Context, Level},
+    world::Board,
+};
 
 use super::{
+    check::check_label_usage,
     parse::{Chunk, ParsedStat, parse_stat_labels},
     sanitize::Registry,
 };
 
-pub fn process_labels(board: &Board, ctx: &Context) -> Option<Board> {
+/// Settings for how labels get checked.
+pub struct Options {
+    /// How to report references to labels that aren't defined.
+    pub undefined_label_level: Level,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            undefined_label_level: Level::Error,
+        }
+    }
+}
+
+pub fn process_labels(board: &Board, options: &Options, ctx: &Context) -> Option<Board> {
     let mut board = board.clone();
 
     // Parse stats into chunks
@@ -16,13 +35,17 @@ pub fn process_labels(board: &Board, ctx: &Context) -> Option<Board> {
         .stats
         .iter()
         .enumerate()
-        .map(|(index, stat)| parse_stat_labels(&stat, &ctx.with_stat(index)))
+        .map(|(index, stat)| parse_stat_labels(stat, &ctx.with_stat(index)))
         .collect();
     let mut registry = Registry::new();
 
     // Expand ".local" labels to full "section.local" form.
     resolve_local_labels(&mut stats, ctx);
 
+    // Look for undefined and unused labels, while we still have their full names.
+    // This checks messages between objects, too.
+    check_label_usage(&board, &stats, options.undefined_label_level, ctx);
+
     // Sanitize all non-anonymous labels.
     // This condenses name strings like "namespace~name$1.local" down to
     // something short and valid for ZZT-OOP, e.g., "local_".
@@ -35,7 +58,7 @@ pub fn process_labels(board: &Board, ctx: &Context) -> Option<Board> {
     anonymous_backward_pass(&mut stats, ctx);
 
     // Join chunks together and replace old stats' code
-    for (old_stat, parsed_stat) in board.stats.iter_mut().zip(stats.into_iter()) {
+    for (old_stat, parsed_stat) in board.stats.iter_mut().zip(stats) {
         let new_code = parsed_stat
             .into_iter()
             .map(|chunk| match chunk {
@@ -54,7 +77,7 @@ pub fn process_labels(board: &Board, ctx: &Context) -> Option<Board> {
 }
 
 /// Resolve ".local" labels to "name.local" form.
-fn resolve_local_labels(stats: &mut [ParsedStat], ctx: &Context) {
+pub fn resolve_local_labels(stats: &mut [ParsedStat], ctx: &Context) {
     for (i, stat) in stats.iter_mut().enumerate() {
         let ctx = ctx.with_stat(i);
 
@@ -63,31 +86,40 @@ fn resolve_local_labels(stats: &mut [ParsedStat], ctx: &Context) {
             FxHashMap::default();
 
         for chunk in stat.iter_mut() {
-            match chunk {
-                Chunk::Label {
-                    is_ref,
-                    is_anon: false,
-                    name: label,
-                } => {
-                    let resolver = resolvers.entry(label.namespace.clone()).or_default();
-                    let is_definition = !*is_ref;
-                    if let Some(local) = &label.local {
-                        if label.name.is_empty() {
-                            // Local that needs to be resolved, such as ":.foo" or "#send .foo"
-                            label.name = resolver.get_section_prefix(&local)
-                        } else if is_definition {
-                            // Illegal local label definition, such as ":touch.foo"
-                            // _References_ to local labels may specify a section name: "#send touch.foo".
-                            // But when a local is _defined_, the section name must always be inferred.
-                            ctx.with_span(label.span.clone())
-                                .error("local label definitions cannot specify a section name");
+            if let Chunk::Label {
+                is_ref,
+                is_anon: false,
+                name: label,
+            } = chunk
+            {
+                let resolver = resolvers.entry(label.namespace.clone()).or_default();
+                let is_definition = !*is_ref;
+                if let Some(local) = &label.local {
+                    if label.name.is_empty() {
+                        if label.recipient.as_ref().is_some_and(|r| !r.is_self()) {
+                            // Can't resolve "#send door:.foo"; this was reported during parsing
+                            continue;
                         }
+                        // Local that needs to be resolved, such as ":.foo" or "#send .foo"
+                        label.name = resolver.get_section_prefix(local)
                     } else if is_definition {
-                        // Top-level label definition, such as ":touch"
-                        resolver.start_new_section(&label.name);
+                        // Illegal local label definition, such as ":touch.foo"
+                        // _References_ to local labels may specify a section name: "#send touch.foo".
+                        // But when a local is _defined_, the section name must always be inferred.
+                        let message = CompileMessage::error(
+                            &LOCAL_LABEL_DEFINITION,
+                            "local label definitions cannot specify a section name",
+                        )
+                        .with_help(&format!(
+                            "write `:.{local}` here; messages can still use `{}.{local}`",
+                            label.name
+                        ));
+                        ctx.with_span(label.span.clone()).report(message);
                     }
+                } else if is_definition {
+                    // Top-level label definition, such as ":touch"
+                    resolver.start_new_section(&label.name);
                 }
-                _ => {}
             }
         }
     }
@@ -105,7 +137,7 @@ struct LocalLabelInfo {
     num_sections: usize,
 }
 
-impl<'a> LocalLabelResolver {
+impl LocalLabelResolver {
     /// Record the start of a new section, e.g., `touch`.
     ///
     /// This is called for each occurence of a top-level label; if the same
@@ -121,9 +153,9 @@ impl<'a> LocalLabelResolver {
     /// the number of previous sections that have had that section-local combo:
     ///
     /// - The first time `.foo` appears in a `touch` section, all instances of
-    ///     `.foo` within that section resolve to `touch.foo`.
+    ///   `.foo` within that section resolve to `touch.foo`.
     /// - But if there's a _second_ `touch` label, any instances of `.foo` within
-    ///     _that_ section must resolve to a distinct name: `touch$1.foo`.
+    ///   _that_ section must resolve to a distinct name: `touch$1.foo`.
     fn get_section_prefix(&mut self, local: &str) -> CompactString {
         let key = (self.current_section.clone(), local.into());
         let info = self.pair_info.entry(key).or_insert_with(|| LocalLabelInfo {
@@ -148,25 +180,13 @@ impl<'a> LocalLabelResolver {
 fn sanitize_named_labels(stats: &mut [ParsedStat], registry: &mut Registry) {
     for stat in stats.iter_mut() {
         for chunk in stat.iter_mut() {
-            match chunk {
-                Chunk::Label {
-                    name,
-                    is_ref: _,
-                    is_anon: false,
-                } => {
-                    let mut full_name = CompactString::const_new("");
-                    if let Some(namespace) = &name.namespace {
-                        full_name.push_str(&namespace);
-                        full_name.push('~');
-                    }
-                    full_name.push_str(&name.name);
-                    if let Some(local) = &name.local {
-                        full_name.push('.');
-                        full_name.push_str(&local);
-                    }
-                    name.name = registry.sanitize(&full_name).into();
-                }
-                _ => {}
+            if let Chunk::Label {
+                name,
+                is_ref: _,
+                is_anon: false,
+            } = chunk
+            {
+                name.name = registry.sanitize(&name.full_name());
             }
         }
     }
@@ -212,14 +232,16 @@ fn anonymous_forward_pass(stats: &mut [ParsedStat], registry: &mut Registry, ctx
                     is_ref: true,
                     is_anon: true,
                     name,
-                } => {
-                    if name.name == "@b" {
-                        if let Some(backward) = namespace_to_latest.get(&name.namespace) {
-                            name.name = backward.clone();
-                        } else {
-                            ctx.with_span(name.span.clone())
-                                .error("backward reference needs an anonymous label");
-                        }
+                } if name.name == "@b" => {
+                    if let Some(backward) = namespace_to_latest.get(&name.namespace) {
+                        name.name = backward.clone();
+                    } else {
+                        let message = CompileMessage::error(
+                            &MISSING_ANONYMOUS_LABEL,
+                            "backward reference needs an anonymous label",
+                        )
+                        .with_help("add a `:@` label somewhere before this");
+                        ctx.with_span(name.span.clone()).report(message);
                     }
                 }
                 _ => {}
@@ -247,14 +269,16 @@ fn anonymous_backward_pass(stats: &mut [ParsedStat], ctx: &Context) {
                     is_ref: true,
                     is_anon: true,
                     name,
-                } => {
-                    if name.name == "@f" {
-                        if let Some(forward) = namespace_to_latest.get(&name.namespace) {
-                            name.name = forward.clone();
-                        } else {
-                            ctx.with_span(name.span.clone())
-                                .error("forward reference needs an anonymous label");
-                        }
+                } if name.name == "@f" => {
+                    if let Some(forward) = namespace_to_latest.get(&name.namespace) {
+                        name.name = forward.clone();
+                    } else {
+                        let message = CompileMessage::error(
+                            &MISSING_ANONYMOUS_LABEL,
+                            "forward reference needs an anonymous label",
+                        )
+                        .with_help("add a `:@` label somewhere after this");
+                        ctx.with_span(name.span.clone()).report(message);
                     }
                 }
                 _ => {}
@@ -265,22 +289,23 @@ fn anonymous_backward_pass(stats: &mut [ParsedStat], ctx: &Context) {
 
 #[cfg(test)]
 mod test {
-    use std::fs;
+    use std::{fs, path::Path};
 
     use insta::assert_snapshot;
 
     use crate::{
-        error::Context,
-        world::{Board, Stat, World},
+        error::{Context, Level},
+        preprocess::eval::Context as EvalContext,
+        world::{Board, Format, Stat, World},
     };
 
-    use super::process_labels;
+    use super::{Options, process_labels};
 
     fn board_from_text(path: &str) -> Board {
         let input = fs::read_to_string(path).unwrap();
         let codes: Vec<String> = input.split("---\n").map(|s| s.into()).collect();
         let blank = fs::read("tests/blank.brd").unwrap();
-        let mut board = Board::from_bytes(&blank).unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
         board.stats = codes
             .into_iter()
             .map(|code| Stat {
@@ -297,7 +322,9 @@ mod test {
                 under_element: 0,
                 under_color: 0,
                 instruction_pointer: 0,
-                bind_index: 0,
+                bound_to: None,
+                data_pointer: [0; 4],
+                padding: vec![],
                 code,
             })
             .collect();
@@ -318,44 +345,92 @@ mod test {
     #[test]
     fn test_label_sanitization() {
         let board = board_from_text("tests/labels/sanitize.txt");
-        let board = process_labels(&board, &Context::new()).unwrap();
+        let board = process_labels(&board, &Options::default(), &Context::new()).unwrap();
         assert_snapshot!(board_to_text(board));
     }
 
     #[test]
     fn test_anonymous_labels() {
         let board = board_from_text("tests/labels/anonymous.txt");
-        let board = process_labels(&board, &Context::new()).unwrap();
+        let board = process_labels(&board, &Options::default(), &Context::new()).unwrap();
+        assert_snapshot!(board_to_text(board));
+    }
+
+    #[test]
+    fn test_anonymous_labels_in_loops() {
+        let mut board = board_from_text("tests/labels/loops.txt");
+        let eval_context = EvalContext::new(Path::new("."));
+        for stat in &mut board.stats {
+            stat.code = eval_context
+                .eval_program(&stat.code, &Context::new())
+                .unwrap()
+                .text;
+        }
+        let board = process_labels(&board, &Options::default(), &Context::new()).unwrap();
         assert_snapshot!(board_to_text(board));
     }
 
     #[test]
     fn test_local_labels() {
         let board = board_from_text("tests/labels/local.txt");
-        let board = process_labels(&board, &Context::new()).unwrap();
+        let board = process_labels(&board, &Options::default(), &Context::new()).unwrap();
         assert_snapshot!(board_to_text(board));
     }
 
     #[test]
     fn test_namespaces() {
         let board = board_from_text("tests/labels/namespaces.txt");
-        let board = process_labels(&board, &Context::new()).unwrap();
+        let board = process_labels(&board, &Options::default(), &Context::new()).unwrap();
         assert_snapshot!(board_to_text(board));
     }
 
-    #[test]
-    fn test_diagnostics() {
-        let world = world_from_text("tests/labels/diagnostics.txt");
+    fn diagnostics_from_text(path: &str, options: &Options) -> String {
+        let world = world_from_text(path);
         let base_ctx = Context::new();
         process_labels(
             &world.boards[0],
+            options,
             &base_ctx.with_file_path("test.zzt").with_board(0),
         );
         let messages: Vec<String> = base_ctx
             .into_messages()
             .iter()
-            .map(|x| x.rich_format(&world))
+            .map(|x| x.rich_format(&world, false))
             .collect();
-        assert_snapshot!(messages.join("\n\n"));
+        messages.join("\n\n")
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let messages = diagnostics_from_text("tests/labels/diagnostics.txt", &Options::default());
+        assert_snapshot!(messages);
+    }
+
+    #[test]
+    fn test_label_usage() {
+        let messages = diagnostics_from_text("tests/labels/usage.txt", &Options::default());
+        assert_snapshot!(messages);
+    }
+
+    #[test]
+    fn test_message_recipients() {
+        let messages = diagnostics_from_text("tests/labels/messages.txt", &Options::default());
+        assert_snapshot!(messages);
+    }
+
+    #[test]
+    fn test_typo_suggestions() {
+        let messages = diagnostics_from_text("tests/labels/typos.txt", &Options::default());
+        assert_snapshot!(messages);
+    }
+
+    #[test]
+    fn test_undefined_label_level() {
+        let board = board_from_text("tests/labels/usage.txt");
+        assert!(process_labels(&board, &Options::default(), &Context::new()).is_none());
+        let options = Options {
+            undefined_label_level: Level::Warning,
+        };
+        assert!(process_labels(&board, &options, &Context::new()).is_some());
     }
 }
@@ -1,6 +1,9 @@
 use compact_str::CompactString;
 use rustc_hash::{FxHashMap, FxHashSet};
 
+/// Labels that ZZT sends to objects on its own, in response to game events.
+pub const BUILTIN_LABELS: [&str; 5] = ["bombed", "energize", "shot", "thud", "touch"];
+
 pub struct Registry {
     key_to_suffix: FxHashMap<Lowercase, Suffix>,
     names: FxHashSet<Lowercase>,
@@ -31,10 +34,9 @@ impl Suffix {
 
 impl Registry {
     pub fn new() -> Self {
-        let builtin_labels = ["bombed", "energize", "shot", "thud", "touch"];
         let mut key_to_suffix = FxHashMap::default();
         let mut taken = FxHashSet::default();
-        for name in builtin_labels {
+        for name in BUILTIN_LABELS {
             let name = Lowercase(name.into());
             key_to_suffix.insert(name.clone(), Suffix::default());
             taken.insert(name);
@@ -131,7 +133,7 @@ fn increment(s: &mut CompactString) {
     if let Some(c) = last_char {
         // Increment character
         let incremented = match c {
-            'a'..'z' => (c as u8 + 1) as char,
+            'a'..='y' => (c as u8 + 1) as char,
             '_' => 'a',
             _ => unreachable!(),
         };
@@ -0,0 +1,373 @@
+//! Control which lints get reported, and how.
+//!
+//! Lints are diagnostics for code that's probably a mistake but still works,
+//! like trailing characters that ZZT ignores. Their levels can be changed for a
+//! whole build with `-A`/`-W`/`--deny` or the `[lints]` table in
+//! `marzipan.toml`, and they can be allowed in one place with a comment:
+//!
+//! ```text
+//! @Shopkeeper
+//! 'mzp:allow unused          (allowed everywhere in this object)
+//! #give gems 5 10 'mzp:allow trailing
+//! 'mzp:allow undefined       (allowed on the next line)
+//! #send player:thanks
+//! ```
+//!
+//! Suppression comments aren't ZZT-OOP, so they're stripped from the output.
+
+use std::ops::Range;
+
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::{
+    codes::{self, ALL_CODES, Code, INVALID_ALLOW, TRAILING_CHARACTERS},
+    error::{CompileMessage, Context, Level},
+};
+
+const DIRECTIVE: &str = "'mzp:allow";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    /// Don't report the lint at all
+    Allow,
+    Warn,
+    /// Report the lint as an error, failing the build
+    Deny,
+}
+
+/// The level of each lint, for lints that have been changed from their defaults.
+#[derive(Default)]
+pub struct Lints {
+    levels: FxHashMap<&'static str, LintLevel>,
+}
+
+impl Lints {
+    /// Change the level of a lint, given its name or code. Later calls win.
+    pub fn set(&mut self, name: &str, level: LintLevel) -> Result<(), String> {
+        let code = find_lint(name)?;
+        self.levels.insert(code.code, level);
+        Ok(())
+    }
+
+    /// Apply the lint levels to a message. Returns false if it should be dropped.
+    pub fn apply(&self, message: &mut CompileMessage) -> bool {
+        match self.levels.get(message.code.code) {
+            Some(LintLevel::Allow) => false,
+            Some(LintLevel::Warn) => {
+                message.level = Level::Warning;
+                true
+            }
+            Some(LintLevel::Deny) => {
+                message.level = Level::Error;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+/// Look up a lint by name or code, explaining what's wrong if it isn't one.
+fn find_lint(name: &str) -> Result<&'static Code, String> {
+    match codes::lookup(name) {
+        Some(code) if code.lint => Ok(code),
+        Some(code) => Err(format!(
+            "`{}` is not a lint, so its level can't be changed",
+            code.name
+        )),
+        None => Err(format!("unknown lint `{name}`")),
+    }
+}
+
+/// The lints allowed by `'mzp:allow` comments in one stat's code.
+#[derive(Debug, Default)]
+pub struct Suppressions {
+    /// Each allowed lint, and the span of code it's allowed in
+    allowed: Vec<(Range<usize>, &'static Code)>,
+    /// Trailing comments, which ZZT would see as trailing characters
+    comments: Vec<Range<usize>>,
+}
+
+impl Suppressions {
+    /// Find the suppression comments in some code, reporting any that don't
+    /// name a lint.
+    ///
+    /// A comment at the end of a line applies to that line. A comment on its
+    /// own line applies to the next line, or to the whole object if it's at
+    /// the top (after the object's name and any other comments).
+    pub fn parse(code: &str, ctx: &Context) -> Suppressions {
+        let mut result = Suppressions::default();
+        let mut at_top = true;
+        let mut pending = vec![];
+        let mut offset = 0;
+        for (i, line) in code.split_inclusive('\n').enumerate() {
+            let start = offset;
+            offset += line.len();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line_span = start..start + line.len();
+
+            let Some((comment_start, names_start)) = find_comment(line) else {
+                for code in pending.drain(..) {
+                    result.allowed.push((line_span.clone(), code));
+                }
+                at_top &= (i == 0 && line.starts_with('@')) || line.starts_with('\'');
+                continue;
+            };
+            let lints = parse_names(&line[names_start..], start + names_start, ctx);
+            if comment_start > 0 {
+                // At the end of a line
+                result.comments.push(start + comment_start..line_span.end);
+                for code in pending.drain(..).chain(lints) {
+                    result.allowed.push((line_span.clone(), code));
+                }
+                at_top = false;
+            } else if at_top {
+                for code in lints {
+                    result.allowed.push((0..usize::MAX, code));
+                }
+            } else {
+                pending.extend(lints);
+            }
+        }
+        result
+    }
+
+    /// Check whether a message has been allowed by a comment.
+    pub fn allows(&self, message: &CompileMessage) -> bool {
+        let Some(span) = &message.location.span else {
+            return false;
+        };
+
+        // The comment itself doesn't count as trailing characters
+        if message.code == &TRAILING_CHARACTERS
+            && self
+                .comments
+                .iter()
+                .any(|x| x.start <= span.start && span.end <= x.end)
+        {
+            return true;
+        }
+        self.allowed
+            .iter()
+            .any(|(x, code)| *code == message.code && x.contains(&span.start))
+    }
+}
+
+/// Find a suppression comment in a line, returning where the comment starts
+/// (including any spaces before it) and where its lint names start.
+///
+/// Comments only go at the end of commands, labels, and other comments. Text
+/// lines are shown to the player as they are, so anything in them is text.
+fn find_comment(line: &str) -> Option<(usize, usize)> {
+    let index = if line.starts_with(DIRECTIVE) {
+        0
+    } else if line.starts_with(['#', ':', '\'']) {
+        line.find(&format!(" {DIRECTIVE}"))? + 1
+    } else {
+        return None;
+    };
+    let names_start = index + DIRECTIVE.len();
+    if !line[names_start..].is_empty() && !line[names_start..].starts_with(' ') {
+        return None;
+    }
+    let comment_start = line[..index].trim_end_matches(' ').len();
+    Some((comment_start, names_start))
+}
+
+/// Parse the lint names in a suppression comment. `offset` is where the names
+/// start in the code, for pointing out invalid ones.
+fn parse_names(names: &str, offset: usize, ctx: &Context) -> Vec<&'static Code> {
+    let mut result = vec![];
+    let mut any = false;
+    let mut start = offset;
+    for name in names.split(' ') {
+        let span = start..start + name.len();
+        start = span.end + 1;
+        if name.is_empty() {
+            continue;
+        }
+        any = true;
+        match find_lint(name) {
+            Ok(code) => result.push(code),
+            Err(message) => {
+                let lints: Vec<_> = ALL_CODES
+                    .iter()
+                    .filter(|x| x.lint)
+                    .map(|x| format!("`{}`", x.name))
+                    .collect();
+                ctx.with_span(span).report(
+                    CompileMessage::new(Level::Warning, &INVALID_ALLOW, &message)
+                        .with_help(&format!("lints that can be allowed: {}", lints.join(", "))),
+                );
+            }
+        }
+    }
+    if !any {
+        ctx.with_span(offset - DIRECTIVE.len()..offset)
+            .warning(&INVALID_ALLOW, "suppression comment doesn't name any lints");
+    }
+    result
+}
+
+/// Remove suppression comments from code, along with the lines they're on if
+/// there's nothing else there.
+pub fn strip_suppressions(code: &str) -> String {
+    let mut result = String::with_capacity(code.len());
+    for line in code.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        match find_comment(text) {
+            Some((0, _)) => {}
+            Some((comment_start, _)) => {
+                result.push_str(&text[..comment_start]);
+                result.push_str(newline);
+            }
+            None => result.push_str(line),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use insta::assert_snapshot;
+
+    use crate::codes::{UNDEFINED_LABEL, UNUSED_LABEL};
+
+    use super::*;
+
+    /// Find the lints allowed at the start of each line, as "line: codes".
+    fn allowed_by_line(code: &str) -> String {
+        let suppressions = Suppressions::parse(code, &Context::new());
+        let mut result = vec![];
+        let mut offset = 0;
+        for (i, line) in code.lines().enumerate() {
+            let codes: Vec<_> = [&UNDEFINED_LABEL, &UNUSED_LABEL, &TRAILING_CHARACTERS]
+                .into_iter()
+                .filter(|code| {
+                    let mut message = CompileMessage::new(Level::Warning, code, "");
+                    message.location.span = Some(offset..offset + 1);
+                    suppressions.allows(&message)
+                })
+                .map(|code| code.name)
+                .collect();
+            result.push(format!("{}: {}", i + 1, codes.join(" ")));
+            offset += line.len() + 1;
+        }
+        result.join("\n")
+    }
+
+    #[test]
+    fn test_suppressions() {
+        let code = "\
+@Shop
+'Sells things
+'mzp:allow unused
+#end
+:touch
+#give gems 5 10 'mzp:allow trailing
+'mzp:allow undefined-label
+'mzp:allow E0010
+#send nowhere now
+#send nowhere
+";
+        assert_snapshot!(allowed_by_line(code), @r"
+        1: unused-label
+        2: unused-label
+        3: unused-label
+        4: unused-label
+        5: unused-label
+        6: unused-label trailing-characters
+        7: unused-label
+        8: unused-label
+        9: undefined-label unused-label trailing-characters
+        10: unused-label
+        ");
+    }
+
+    #[test]
+    fn test_comment_isnt_trailing() {
+        let code = "#end 'mzp:allow unused\n#end now 'mzp:allow unused\n";
+        let suppressions = Suppressions::parse(code, &Context::new());
+        let mut message = CompileMessage::new(Level::Warning, &TRAILING_CHARACTERS, "");
+        message.location.span = Some(5..22);
+        assert!(suppressions.allows(&message));
+        message.location.span = Some(28..50);
+        assert!(!suppressions.allows(&message));
+    }
+
+    #[test]
+    fn test_text_isnt_comment() {
+        let code = "#end\nDon't 'mzp:allow unused\n:touch\n";
+        assert_eq!(allowed_by_line(code), "1: \n2: \n3: ");
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let code = "@Shop\n'mzp:allow trailing macro-error typo\n#end\n'mzp:allow\n";
+        let ctx = Context::new();
+        Suppressions::parse(code, &ctx);
+        let messages: Vec<_> = ctx
+            .into_messages()
+            .into_iter()
+            .map(|x| {
+                let span = x.location.span.unwrap();
+                format!("{} {:?}: {}", x.code.code, &code[span], x.message)
+            })
+            .collect();
+        assert_snapshot!(messages.join("\n"), @r#"
+        E0013 "macro-error": `macro-error` is not a lint, so its level can't be changed
+        E0013 "typo": unknown lint `typo`
+        E0013 "'mzp:allow": suppression comment doesn't name any lints
+        "#);
+    }
+
+    #[test]
+    fn test_strip() {
+        let code = "\
+@Shop
+'mzp:allow unused
+#end
+#give gems 5 10   'mzp:allow trailing
+'mzp:allowed is just a comment
+:shop 'mzp:allow unused
+'Sells things 'mzp:allow unused
+Don't 'mzp:allow this";
+        assert_snapshot!(strip_suppressions(code), @r"
+        @Shop
+        #end
+        #give gems 5 10
+        'mzp:allowed is just a comment
+        :shop
+        'Sells things
+        Don't 'mzp:allow this
+        ");
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut lints = Lints::default();
+        lints.set("unused", LintLevel::Deny).unwrap();
+        lints.set("E0010", LintLevel::Allow).unwrap();
+        assert!(lints.set("macro-error", LintLevel::Allow).is_err());
+        assert!(lints.set("typo", LintLevel::Allow).is_err());
+
+        let mut message = CompileMessage::new(Level::Warning, &UNUSED_LABEL, "");
+        assert!(lints.apply(&mut message));
+        assert_eq!(message.level, Level::Error);
+        let mut message = CompileMessage::new(Level::Warning, &TRAILING_CHARACTERS, "");
+        assert!(!lints.apply(&mut message));
+        let mut message = CompileMessage::new(Level::Error, &UNDEFINED_LABEL, "");
+        assert!(lints.apply(&mut message));
+        assert_eq!(message.level, Level::Error);
+
+        // Later settings win, so the command line can override the config file
+        lints.set("unused-label", LintLevel::Warn).unwrap();
+        let mut message = CompileMessage::new(Level::Warning, &UNUSED_LABEL, "");
+        assert!(lints.apply(&mut message));
+        assert_eq!(message.level, Level::Warning);
+    }
+}
@@ -0,0 +1,204 @@
+//! Suggest words to type, based on what's before the cursor.
+//!
+//! This doesn't parse the whole line: code that's still being typed usually
+//! doesn't parse. Instead, it looks at the command that the cursor is in and
+//! the words after it.
+
+use crate::oop::keywords::{
+    COLORS, COMMANDS, CONDITIONS, COUNTERS, DIRECTION_MODIFIERS, DIRECTIONS, ELEMENTS,
+};
+
+use super::document::Document;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompletionKind {
+    Command,
+    Direction,
+    Element,
+    Color,
+    Condition,
+    Counter,
+    Label,
+}
+
+#[derive(Debug)]
+pub struct Completion {
+    pub text: String,
+    pub kind: CompletionKind,
+}
+
+/// Get the completions for the word at an offset in a document.
+pub fn complete(doc: &Document, offset: usize) -> Vec<Completion> {
+    let line_start = doc.text[..offset].rfind('\n').map_or(0, |x| x + 1);
+    let line = &doc.text[line_start..offset];
+
+    // Only lines that start with a command or movement have words to complete
+    if !line.starts_with(['#', '/', '?']) {
+        return vec![];
+    }
+    let word_start = line
+        .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map_or(0, |x| x + 1);
+    let word = line[word_start..].to_ascii_lowercase();
+    let before = &line[..word_start];
+
+    let mut result = vec![];
+    let mut add = |words: &[&str], kind| {
+        for &text in words {
+            if text.to_ascii_lowercase().starts_with(&word) {
+                result.push(Completion {
+                    text: text.into(),
+                    kind,
+                });
+            }
+        }
+    };
+    let labels = doc.label_names();
+    let labels: Vec<&str> = labels.iter().map(|x| x.as_str()).collect();
+
+    if before.ends_with(['/', '?']) {
+        add(&DIRECTIONS, CompletionKind::Direction);
+        add(&DIRECTION_MODIFIERS, CompletionKind::Direction);
+        return result;
+    }
+    if before.ends_with('#') {
+        // `#foo` on its own is shorthand for `#send foo`
+        add(&COMMANDS, CompletionKind::Command);
+        add(&labels, CompletionKind::Label);
+        return result;
+    }
+
+    // Find the words of the command that the cursor is in
+    let Some(command_start) = before.rfind('#') else {
+        return result;
+    };
+    let words: Vec<String> = before[command_start + 1..]
+        .split_ascii_whitespace()
+        .map(|x| x.to_ascii_lowercase())
+        .collect();
+    let Some((command, args)) = words.split_first() else {
+        return result;
+    };
+    let last_arg = args.last().map(|x| x.as_str());
+
+    // Directions can be followed by more direction words, like `cw n`
+    let in_direction = |args: &[String]| {
+        args.iter()
+            .all(|x| DIRECTION_MODIFIERS.contains(&x.as_str()))
+    };
+    let wants_direction = match command.as_str() {
+        "go" | "try" | "walk" | "shoot" | "throwstar" => in_direction(args),
+        "put" => in_direction(args),
+        "if" => args
+            .iter()
+            .position(|x| x == "blocked")
+            .is_some_and(|i| in_direction(&args[i + 1..])),
+        _ => false,
+    };
+    if wants_direction {
+        add(&DIRECTIONS, CompletionKind::Direction);
+        add(&DIRECTION_MODIFIERS, CompletionKind::Direction);
+        return result;
+    }
+
+    let after_color = last_arg.is_some_and(|x| COLORS.contains(&x));
+    let wants_kind = match command.as_str() {
+        "become" => args.is_empty() || after_color,
+        "change" => args.len() <= 2 || after_color,
+        // The kind comes after a direction
+        "put" => !args.is_empty(),
+        "if" => last_arg == Some("any") || (after_color && args.contains(&"any".into())),
+        _ => false,
+    };
+    if wants_kind {
+        if !after_color {
+            add(&COLORS, CompletionKind::Color);
+        }
+        add(&ELEMENTS, CompletionKind::Element);
+        return result;
+    }
+
+    match (command.as_str(), args.len()) {
+        ("send" | "zap" | "restore", 0) => add(&labels, CompletionKind::Label),
+        ("give" | "take", 0) => add(&COUNTERS, CompletionKind::Counter),
+        ("if", 0) => add(&CONDITIONS, CompletionKind::Condition),
+        ("if", _) if last_arg == Some("not") => add(&CONDITIONS, CompletionKind::Condition),
+        _ => {}
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Complete at the end of some code, and list the completions.
+    fn completions(code: &str) -> Vec<String> {
+        let doc = Document::new(&format!(":touch\n:Shot\n:.loop\n{code}"));
+        complete(&doc, doc.text.len())
+            .into_iter()
+            .map(|x| x.text)
+            .collect()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(completions("#se"), vec!["send", "set"]);
+        assert_eq!(completions("#if contact #th"), vec!["throwstar"]);
+        assert_eq!(completions("#s"), vec!["send", "set", "shoot", "Shot"]);
+        assert!(completions("#").contains(&"zap".into()));
+        assert!(completions("Hello #").is_empty());
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!(completions("/rn"), vec!["rnd", "rndne", "rndns", "rndp"]);
+        assert_eq!(completions("#go cw s"), vec!["seek", "south", "s"]);
+        assert_eq!(completions("#if blocked opp n"), vec!["north", "n"]);
+        assert_eq!(completions("#put e"), vec!["east", "e"]);
+        assert!(completions("#shoot n ").is_empty());
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(
+            completions("#become b"),
+            vec![
+                "blue",
+                "bear",
+                "blinkwall",
+                "bomb",
+                "boulder",
+                "breakable",
+                "bullet"
+            ]
+        );
+        assert_eq!(
+            completions("#put n red b"),
+            vec![
+                "bear",
+                "blinkwall",
+                "bomb",
+                "boulder",
+                "breakable",
+                "bullet"
+            ]
+        );
+        assert_eq!(
+            completions("#change gem w"),
+            vec![
+                "white", "water", "watere", "watern", "waters", "waterw", "web"
+            ]
+        );
+        assert_eq!(completions("#if any y"), vec!["yellow"]);
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(completions("#send "), vec!["touch", "Shot"]);
+        assert_eq!(completions("#zap t"), vec!["touch"]);
+        assert_eq!(completions("#give g"), vec!["gems"]);
+        assert_eq!(completions("#if not e"), vec!["energized"]);
+        assert!(completions("#send touch ").is_empty());
+    }
+}
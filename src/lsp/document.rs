@@ -0,0 +1,466 @@
+//! What the language server knows about a single source file.
+//!
+//! Files that get `%include`d are fragments of some object's code, so each one
+//! is checked as if it were the code of a single object.
+
+use std::{ops::Range, path::Path};
+
+use compact_str::CompactString;
+
+use crate::{
+    config::Config,
+    error::{CompileMessage, Context as ErrContext, Level},
+    labels::{
+        Options as LabelOptions,
+        parse::{Chunk, parse_stat_labels},
+        process::resolve_local_labels,
+        process_labels,
+    },
+    lints::Suppressions,
+    oop::ast::Program,
+    preprocess::{eval::Context as EvalContext, source_map::SourceMap},
+    world::{Board, Stat},
+};
+
+pub struct Document {
+    /// The file's text, with Windows line endings converted to `\n`
+    pub text: String,
+    labels: Vec<LabelPart>,
+    anonymous: Vec<AnonymousLabel>,
+}
+
+/// A problem found in a document.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub span: Range<usize>,
+    pub level: Level,
+    /// The diagnostic's code, like `E0004`
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+/// A place where a label's name, or the local part of its name, appears.
+///
+/// A label like `touch.open` has two parts: `touch` refers to the `:touch`
+/// label, and `open` refers to the local `:.open` label in that section.
+#[derive(Debug)]
+struct LabelPart {
+    /// Which label this is, as a lowercased full name like `ns~touch` or
+    /// `ns~touch$1.open`. Local labels are resolved to their sections.
+    key: CompactString,
+    span: Range<usize>,
+    is_definition: bool,
+}
+
+/// An anonymous label definition (`:@`) or reference (`@f` or `@b`).
+struct AnonymousLabel {
+    span: Range<usize>,
+    kind: char,
+}
+
+impl Document {
+    pub fn new(text: &str) -> Self {
+        let text = text.replace("\r\n", "\n");
+        let (labels, anonymous) = find_labels(&text);
+        Document {
+            text,
+            labels,
+            anonymous,
+        }
+    }
+
+    /// Check the document for problems. `path` is the document's location,
+    /// which is used to find the files it includes.
+    pub fn diagnostics(&self, path: &Path) -> Vec<Diagnostic> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut eval_context = EvalContext::new(dir);
+        let config = Config::find(dir).and_then(|config| {
+            for include_path in &config.include_paths {
+                eval_context.add_include_path(include_path);
+            }
+            config.lints()
+        });
+        let lints = match config {
+            Ok(lints) => lints,
+            Err(e) => {
+                return vec![Diagnostic {
+                    span: 0..0,
+                    level: Level::Error,
+                    code: None,
+                    message: e.to_string(),
+                }];
+            }
+        };
+
+        // Macro errors already point into the document
+        let base_ctx = ErrContext::new();
+        let Some(output) = eval_context.eval_program(&self.text, &base_ctx) else {
+            let messages = base_ctx.into_messages();
+            return messages
+                .into_iter()
+                .map(|message| {
+                    let span = message.location.span.clone().unwrap_or(0..0);
+                    Diagnostic::new(message, span)
+                })
+                .collect();
+        };
+
+        // Check labels in the expanded code. Other objects might define the
+        // labels that this code sends messages to, so those are just warnings.
+        if Program::try_parse(&output.text).is_none() {
+            return vec![];
+        }
+        let lint_filter = |message: &mut CompileMessage| lints.apply(message);
+        let ctx = base_ctx.with_filter(&lint_filter);
+        let suppressions = Suppressions::parse(&output.text, &ctx);
+        let filter = |message: &mut CompileMessage| !suppressions.allows(message);
+        let mut board = Board::default();
+        board.stats.push(Stat {
+            code: output.text,
+            ..Default::default()
+        });
+        let options = LabelOptions {
+            undefined_label_level: Level::Warning,
+        };
+        process_labels(&board, &options, &ctx.with_filter(&filter));
+        base_ctx
+            .into_messages()
+            .into_iter()
+            .map(|mut message| {
+                let span = message.location.span.clone().unwrap_or(0..0);
+                let (origin, span) = map_span(&output.map, &span);
+                message.location.origin = origin;
+                Diagnostic::new(message, span)
+            })
+            .collect()
+    }
+
+    /// Find the definitions of the label at an offset.
+    pub fn definitions(&self, offset: usize) -> Vec<Range<usize>> {
+        if let Some(anonymous) = self.anonymous_at(offset) {
+            return self.anonymous_target(anonymous).into_iter().collect();
+        }
+        let Some(part) = self.label_at(offset) else {
+            return vec![];
+        };
+        self.labels
+            .iter()
+            .filter(|x| x.key == part.key && x.is_definition)
+            .map(|x| x.span.clone())
+            .collect()
+    }
+
+    /// Find every place that the label at an offset appears.
+    pub fn references(&self, offset: usize, include_definitions: bool) -> Vec<Range<usize>> {
+        let Some(part) = self.label_at(offset) else {
+            return vec![];
+        };
+        self.labels
+            .iter()
+            .filter(|x| x.key == part.key && (include_definitions || !x.is_definition))
+            .map(|x| x.span.clone())
+            .collect()
+    }
+
+    /// Get the span of the label name that a rename at this offset would change.
+    pub fn rename_span(&self, offset: usize) -> Option<Range<usize>> {
+        self.label_at(offset).map(|x| x.span.clone())
+    }
+
+    /// Get the edits for renaming the label at an offset.
+    pub fn rename(&self, offset: usize, new_name: &str) -> Result<Vec<Range<usize>>, String> {
+        let is_valid = !new_name.is_empty()
+            && new_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_valid {
+            return Err(format!(
+                "{new_name:?} isn't a valid label name: use only letters, digits, and underscores"
+            ));
+        }
+        match self.label_at(offset) {
+            Some(_) => Ok(self.references(offset, true)),
+            None => Err("there's no label here to rename".into()),
+        }
+    }
+
+    /// List the names of the labels this document defines, as they're written.
+    /// Local and namespaced labels are left out, since they can't be used on
+    /// their own.
+    pub fn label_names(&self) -> Vec<CompactString> {
+        let mut result: Vec<CompactString> = vec![];
+        for part in &self.labels {
+            let name = &self.text[part.span.clone()];
+            let is_plain = !part.key.contains(['.', '~']);
+            if part.is_definition
+                && is_plain
+                && !result.iter().any(|x| x.eq_ignore_ascii_case(name))
+            {
+                result.push(name.into());
+            }
+        }
+        result
+    }
+
+    fn label_at(&self, offset: usize) -> Option<&LabelPart> {
+        self.labels
+            .iter()
+            .find(|x| x.span.start <= offset && offset <= x.span.end)
+    }
+
+    fn anonymous_at(&self, offset: usize) -> Option<&AnonymousLabel> {
+        self.anonymous
+            .iter()
+            .find(|x| x.span.start <= offset && offset <= x.span.end && x.kind != '@')
+    }
+
+    /// Find the `:@` that an `@f` or `@b` jumps to.
+    fn anonymous_target(&self, reference: &AnonymousLabel) -> Option<Range<usize>> {
+        let mut definitions = self.anonymous.iter().filter(|x| x.kind == '@');
+        let target = if reference.kind == 'f' {
+            definitions.find(|x| x.span.start > reference.span.start)
+        } else {
+            definitions.rfind(|x| x.span.start < reference.span.start)
+        };
+        target.map(|x| x.span.clone())
+    }
+}
+
+impl Diagnostic {
+    /// Convert a compiler message that points to `span` in the document.
+    fn new(message: CompileMessage, span: Range<usize>) -> Self {
+        let mut text = message.message;
+
+        // Problems in included files get reported at the `%include`
+        if let Some(origin) = &message.location.origin
+            && let Some(path) = &origin.file_path
+        {
+            let line = origin.text[..origin.span.start].matches('\n').count() + 1;
+            text.push_str(&format!("\n  in {path} line {line}"));
+        }
+        for note in &message.notes {
+            text.push_str(&format!("\n{}: {}", note.kind.name(), note.message));
+        }
+        Diagnostic {
+            span,
+            level: message.level,
+            code: Some(message.code.code),
+            message: text,
+        }
+    }
+}
+
+/// Map a span of expanded code back to the document. Returns where the span
+/// came from, and the span of the document that it comes from.
+fn map_span(map: &SourceMap, span: &Range<usize>) -> (Option<crate::error::Origin>, Range<usize>) {
+    let origin = map.origin(span);
+    let span = match &origin {
+        Some(origin) if origin.file_path.is_none() => origin.span.clone(),
+        _ => map.root_span(span).unwrap_or(0..0),
+    };
+    (origin, span)
+}
+
+/// Find every label in some code, before macros are expanded.
+fn find_labels(code: &str) -> (Vec<LabelPart>, Vec<AnonymousLabel>) {
+    if Program::try_parse(code).is_none() {
+        return (vec![], vec![]);
+    }
+    let stat = Stat {
+        code: code.into(),
+        ..Default::default()
+    };
+    let ctx = ErrContext::new();
+    let mut stats = vec![parse_stat_labels(&stat, &ctx)];
+
+    // Find where each part of each label is, before local labels get resolved
+    // and the names no longer match the code
+    let mut parts = vec![];
+    for chunk in &stats[0] {
+        if let Chunk::Label {
+            is_anon: false,
+            name,
+            ..
+        } = chunk
+        {
+            let start = name.span.start + name.namespace.as_ref().map_or(0, |x| x.len() + 1);
+            let global = (!name.name.is_empty()).then(|| start..start + name.name.len());
+            let local = name
+                .local
+                .as_ref()
+                .map(|x| name.span.end - x.len()..name.span.end);
+            parts.push((global, local));
+        }
+    }
+    resolve_local_labels(&mut stats, &ctx);
+
+    let mut labels = vec![];
+    let mut anonymous = vec![];
+    let mut parts = parts.into_iter();
+    for chunk in &stats[0] {
+        let Chunk::Label {
+            is_ref,
+            is_anon,
+            name,
+        } = chunk
+        else {
+            continue;
+        };
+        if *is_anon {
+            let kind = code[name.span.clone()].chars().last().unwrap_or('@');
+            anonymous.push(AnonymousLabel {
+                span: name.span.clone(),
+                kind: kind.to_ascii_lowercase(),
+            });
+            continue;
+        }
+        let (global, local) = parts.next().unwrap();
+
+        // Messages to other objects aren't about this object's labels
+        if name.recipient.as_ref().is_some_and(|x| !x.is_self()) {
+            continue;
+        }
+        let mut key = CompactString::const_new("");
+        if let Some(namespace) = &name.namespace {
+            key.push_str(namespace);
+            key.push('~');
+        }
+        key.push_str(&name.name);
+        key.make_ascii_lowercase();
+        if let Some(span) = global {
+            labels.push(LabelPart {
+                key: key.clone(),
+                span,
+                is_definition: !is_ref,
+            });
+        }
+        if let (Some(span), Some(local)) = (local, &name.local) {
+            key.push('.');
+            key.push_str(&local.to_ascii_lowercase());
+            labels.push(LabelPart {
+                key,
+                span,
+                is_definition: !is_ref,
+            });
+        }
+    }
+    (labels, anonymous)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use insta::assert_snapshot;
+
+    use super::*;
+
+    /// Show a document with some spans of it marked with brackets.
+    fn mark(doc: &Document, spans: &[Range<usize>]) -> String {
+        let mut result = doc.text.clone();
+        let mut spans = spans.to_vec();
+        spans.sort_by_key(|x| std::cmp::Reverse(x.start));
+        for span in spans {
+            result.insert(span.end, ']');
+            result.insert(span.start, '[');
+        }
+        result
+    }
+
+    fn offset_of(doc: &Document, needle: &str) -> usize {
+        doc.text.find(needle).unwrap() + 1
+    }
+
+    #[test]
+    fn definitions() {
+        let doc = Document::new(
+            ":touch\r\n#send self:touch\n#send Touch.open\n:.open\n#if contact @f\n:@\n#send @b",
+        );
+        assert_eq!(
+            mark(&doc, &doc.definitions(offset_of(&doc, "Touch.open"))),
+            ":[touch]\n#send self:touch\n#send Touch.open\n:.open\n#if contact @f\n:@\n#send @b"
+        );
+        assert_eq!(
+            mark(&doc, &doc.definitions(offset_of(&doc, "open"))),
+            ":touch\n#send self:touch\n#send Touch.open\n:.[open]\n#if contact @f\n:@\n#send @b"
+        );
+        assert_eq!(
+            mark(&doc, &doc.definitions(offset_of(&doc, "@f"))),
+            ":touch\n#send self:touch\n#send Touch.open\n:.open\n#if contact @f\n:[@]\n#send @b"
+        );
+        assert_eq!(
+            mark(&doc, &doc.definitions(offset_of(&doc, "@b"))),
+            ":touch\n#send self:touch\n#send Touch.open\n:.open\n#if contact @f\n:[@]\n#send @b"
+        );
+        assert!(doc.definitions(0).is_empty());
+    }
+
+    #[test]
+    fn references_respect_scopes() {
+        let code = fs::read_to_string("tests/lsp/scopes.txt").unwrap();
+        let doc = Document::new(&code);
+        let mut results = vec![];
+        for needle in [":.loop", ":touch", "#send ns~touch", "guard:touch"] {
+            let offset = offset_of(&doc, needle) + needle.len() - 1;
+            results.push(format!(
+                "{needle}:\n{}",
+                mark(&doc, &doc.references(offset, true))
+            ));
+        }
+        assert_snapshot!(results.join("\n\n"));
+    }
+
+    #[test]
+    fn rename() {
+        let doc = Document::new(":touch\n:.open\n#send touch.open\n#send .open\n#send guard:touch");
+        assert_eq!(
+            mark(&doc, &doc.rename(offset_of(&doc, "touch"), "poke").unwrap()),
+            ":[touch]\n:.open\n#send [touch].open\n#send .open\n#send guard:touch"
+        );
+        assert_eq!(doc.rename_span(offset_of(&doc, ".open")), Some(9..13));
+        assert_snapshot!(doc.rename(1, "two words").unwrap_err(), @r#""two words" isn't a valid label name: use only letters, digits, and underscores"#);
+        assert!(doc.rename(offset_of(&doc, "#send"), "foo").is_err());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let path = PathBuf::from("tests/preprocess/doc.txt");
+        let doc = Document::new(":touch\n#send nowhere\n%include \"outer.txt\"\n:unused\n#");
+        let diagnostics: Vec<_> = doc
+            .diagnostics(&path)
+            .into_iter()
+            .map(|x| format!("{:?} {:?}: {}", x.level, x.span, x.message))
+            .collect();
+        // The trailing `#` doesn't parse yet, so labels aren't checked
+        assert!(diagnostics.is_empty());
+
+        let doc = Document::new(&doc.text[..doc.text.len() - 2]);
+        let diagnostics: Vec<_> = doc
+            .diagnostics(&path)
+            .into_iter()
+            .map(|x| format!("{:?} {:?}: {}", x.level, x.span, x.message))
+            .collect();
+        assert_snapshot!(diagnostics.join("\n"), @r"
+        Warning 13..20: label `nowhere` is not defined in this object
+        Warning 21..41: label `missing` is not defined in this object
+          in tests/preprocess/inner.txt line 1
+        Warning 43..49: label `unused` is never used
+        ");
+
+        let doc = Document::new(":touch\n#send nowhere 'mzp:allow undefined\n:unused");
+        let diagnostics: Vec<_> = doc
+            .diagnostics(&path)
+            .into_iter()
+            .map(|x| format!("{:?} {:?}: {}", x.level, x.span, x.message))
+            .collect();
+        assert_snapshot!(diagnostics.join("\n"), @"Warning 43..49: label `unused` is never used");
+
+        let doc = Document::new("#end\n%include \"nope.txt\"");
+        let diagnostics: Vec<_> = doc
+            .diagnostics(&path)
+            .into_iter()
+            .map(|x| format!("{:?} {:?}: {}", x.level, x.span, x.message))
+            .collect();
+        assert_snapshot!(diagnostics.join("\n"), @r#"Error 5..24: Couldn't find "nope.txt" (searched tests/preprocess)"#);
+    }
+}
@@ -0,0 +1,366 @@
+//! A language server for ZZT-OOP files, like the ones that get `%include`d.
+//!
+//! It speaks the Language Server Protocol over stdin and stdout, so editors
+//! can show diagnostics as you type, jump between labels and the messages that
+//! use them, rename labels, and complete commands and their arguments.
+
+mod completion;
+mod document;
+
+use std::{ops::Range, path::PathBuf};
+
+use anyhow::Result;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams,
+    Diagnostic as LspDiagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Location, NumberOrString, OneOf, Position, PrepareRenameResponse,
+    PublishDiagnosticsParams, Range as LspRange, ReferenceParams, RenameOptions, RenameParams,
+    ServerCapabilities, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, PrepareRenameRequest, References, Rename, Request as _},
+};
+use rustc_hash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::error::Level;
+
+use completion::{CompletionKind, complete};
+use document::Document;
+
+/// Run the language server until the client shuts it down.
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["#".into(), "/".into(), "?".into(), " ".into()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    main_loop(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Handle messages until the client asks the server to shut down. This takes
+/// ownership of the connection, so that its IO threads can finish afterward.
+fn main_loop(connection: Connection) -> Result<()> {
+    let mut server = Server {
+        connection: &connection,
+        documents: FxHashMap::default(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                server.handle_request(request)?;
+            }
+            Message::Notification(notification) => server.handle_notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: FxHashMap<Url, Document>,
+}
+
+impl Server<'_> {
+    /// Handle a notification. There's no way to tell the client that its
+    /// params were malformed, so those are ignored.
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) =
+                    notification.extract::<DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+                else {
+                    return Ok(());
+                };
+                self.update(params.text_document.uri, &params.text_document.text)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(params) = notification
+                    .extract::<DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD)
+                else {
+                    return Ok(());
+                };
+                // With full sync, the last change has the whole text
+                if let Some(change) = params.content_changes.last() {
+                    self.update(params.text_document.uri, &change.text)?;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let Ok(params) = notification
+                    .extract::<DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                else {
+                    return Ok(());
+                };
+                self.documents.remove(&params.text_document.uri);
+                self.publish(params.text_document.uri, vec![])?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Result<()> {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => params(request, GotoDefinition::METHOD)
+                .map(|x: GotoDefinitionParams| self.definition(x.text_document_position_params)),
+            References::METHOD => params(request, References::METHOD).map(|x: ReferenceParams| {
+                let include_declaration = x.context.include_declaration;
+                self.references(x.text_document_position, include_declaration)
+            }),
+            PrepareRenameRequest::METHOD => params(request, PrepareRenameRequest::METHOD)
+                .map(|x: TextDocumentPositionParams| self.prepare_rename(x)),
+            Rename::METHOD => {
+                params(request, Rename::METHOD).and_then(|x: RenameParams| self.rename(x))
+            }
+            Completion::METHOD => params(request, Completion::METHOD)
+                .map(|x: CompletionParams| self.completion(x.text_document_position)),
+            _ => {
+                let code = ErrorCode::MethodNotFound as i32;
+                let message = format!("Unsupported request: {}", request.method);
+                return self.respond(Response::new_err(id, code, message));
+            }
+        };
+        let response = match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(message) => Response::new_err(id, ErrorCode::InvalidParams as i32, message),
+        };
+        self.respond(response)
+    }
+
+    fn respond(&self, response: Response) -> Result<()> {
+        self.connection.sender.send(response.into())?;
+        Ok(())
+    }
+
+    /// Store a document's new text, and check it for problems.
+    fn update(&mut self, uri: Url, text: &str) -> Result<()> {
+        let document = Document::new(text);
+        let path = uri.to_file_path().unwrap_or_else(|_| PathBuf::new());
+        let diagnostics = document
+            .diagnostics(&path)
+            .into_iter()
+            .map(|x| LspDiagnostic {
+                range: to_lsp_range(&document.text, &x.span),
+                severity: Some(match x.level {
+                    Level::Error => DiagnosticSeverity::ERROR,
+                    Level::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: x.code.map(|x| NumberOrString::String(x.into())),
+                source: Some("marzipan".into()),
+                message: x.message,
+                ..Default::default()
+            })
+            .collect();
+        self.documents.insert(uri.clone(), document);
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<LspDiagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+
+    /// Find the document and offset that a request is about.
+    fn locate(&self, params: &TextDocumentPositionParams) -> Option<(&Document, usize)> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let offset = to_offset(&document.text, params.position);
+        Some((document, offset))
+    }
+
+    fn locations(&self, uri: &Url, spans: Vec<Range<usize>>) -> Vec<Location> {
+        let text = &self.documents[uri].text;
+        spans
+            .iter()
+            .map(|span| Location::new(uri.clone(), to_lsp_range(text, span)))
+            .collect()
+    }
+
+    fn definition(&self, params: TextDocumentPositionParams) -> Value {
+        let Some((document, offset)) = self.locate(&params) else {
+            return Value::Null;
+        };
+        let locations = self.locations(&params.text_document.uri, document.definitions(offset));
+        to_json(GotoDefinitionResponse::Array(locations))
+    }
+
+    fn references(&self, params: TextDocumentPositionParams, include_declaration: bool) -> Value {
+        let Some((document, offset)) = self.locate(&params) else {
+            return Value::Null;
+        };
+        let spans = document.references(offset, include_declaration);
+        to_json(self.locations(&params.text_document.uri, spans))
+    }
+
+    fn prepare_rename(&self, params: TextDocumentPositionParams) -> Value {
+        let span = self
+            .locate(&params)
+            .and_then(|(document, offset)| document.rename_span(offset));
+        match span {
+            Some(span) => {
+                let text = &self.documents[&params.text_document.uri].text;
+                to_json(PrepareRenameResponse::Range(to_lsp_range(text, &span)))
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Value, String> {
+        let position = params.text_document_position;
+        let Some((document, offset)) = self.locate(&position) else {
+            return Ok(Value::Null);
+        };
+        let edits = document
+            .rename(offset, &params.new_name)?
+            .iter()
+            .map(|span| TextEdit::new(to_lsp_range(&document.text, span), params.new_name.clone()))
+            .collect();
+        let changes = [(position.text_document.uri, edits)].into_iter().collect();
+        Ok(to_json(WorkspaceEdit::new(changes)))
+    }
+
+    fn completion(&self, params: TextDocumentPositionParams) -> Value {
+        let Some((document, offset)) = self.locate(&params) else {
+            return Value::Null;
+        };
+        let items: Vec<_> = complete(document, offset)
+            .into_iter()
+            .map(|x| CompletionItem {
+                label: x.text,
+                kind: Some(match x.kind {
+                    CompletionKind::Command => CompletionItemKind::KEYWORD,
+                    CompletionKind::Direction => CompletionItemKind::ENUM_MEMBER,
+                    CompletionKind::Element => CompletionItemKind::CLASS,
+                    CompletionKind::Color => CompletionItemKind::COLOR,
+                    CompletionKind::Condition => CompletionItemKind::OPERATOR,
+                    CompletionKind::Counter => CompletionItemKind::VARIABLE,
+                    CompletionKind::Label => CompletionItemKind::FUNCTION,
+                }),
+                ..Default::default()
+            })
+            .collect();
+        to_json(items)
+    }
+}
+
+fn to_json(value: impl serde::Serialize) -> Value {
+    serde_json::to_value(value).unwrap()
+}
+
+/// Convert an LSP position, which counts UTF-16 code units, to a byte offset.
+fn to_offset(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = text[line_start..].split('\n').next().unwrap();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    let line = before.matches('\n').count();
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Position::new(line as u32, character as u32)
+}
+
+fn to_lsp_range(text: &str, span: &Range<usize>) -> LspRange {
+    LspRange::new(to_position(text, span.start), to_position(text, span.end))
+}
+
+/// Read a request's params. If they're malformed, the error goes back to the
+/// client, rather than stopping the server.
+fn params<P: DeserializeOwned>(request: Request, method: &str) -> Result<P, String> {
+    request
+        .extract(method)
+        .map(|(_, params)| params)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let text = "#end\n'café ☺ x\n";
+        let offset = text.find('x').unwrap();
+        let position = to_position(text, offset);
+        assert_eq!(position, Position::new(1, 8));
+        assert_eq!(to_offset(text, position), offset);
+        assert_eq!(to_offset(text, Position::new(0, 99)), 4);
+        assert_eq!(to_offset(text, Position::new(9, 0)), text.len());
+    }
+
+    #[test]
+    fn malformed_params() {
+        let (server, client) = Connection::memory();
+        let thread = std::thread::spawn(move || main_loop(server));
+        let bogus = serde_json::json!({"textDocument": 5});
+        let notification = Notification::new(DidOpenTextDocument::METHOD.into(), bogus.clone());
+        client.sender.send(notification.into()).unwrap();
+        let request = Request::new(1.into(), GotoDefinition::METHOD.into(), bogus);
+        client.sender.send(request.into()).unwrap();
+        let Message::Response(response) = client.receiver.recv().unwrap() else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.id, 1.into());
+        assert_eq!(
+            response.error.unwrap().code,
+            ErrorCode::InvalidParams as i32
+        );
+
+        // The server is still running, and shuts down when asked
+        let shutdown = Request::new(2.into(), "shutdown".into(), Value::Null);
+        client.sender.send(shutdown.into()).unwrap();
+        let Message::Response(response) = client.receiver.recv().unwrap() else {
+            panic!("expected a response");
+        };
+        assert_eq!(response.id, 2.into());
+        client
+            .sender
+            .send(Notification::new("exit".into(), ()).into())
+            .unwrap();
+        thread.join().unwrap().unwrap();
+    }
+}
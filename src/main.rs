@@ -1,25 +1,67 @@
+mod board_file;
+mod budget;
+mod codes;
+mod compile;
+mod config;
 mod encoding;
 mod error;
 mod labels;
+mod lints;
+mod lsp;
+mod minify;
+mod oop;
 mod peg;
 mod preprocess;
+mod unpack;
+mod watch;
 mod world;
 
 use anyhow::{Result, anyhow};
-use error::Context as ErrContext;
-use labels::process_labels;
+use compile::{MessageFormat, Options, compile, is_board_file};
 use lexopt::prelude::*;
-use preprocess::eval::Context;
+use lints::LintLevel;
+use preprocess::eval::Value as MacroValue;
 use std::{
-    env, fs,
+    env,
+    io::{self, IsTerminal},
     path::{Path, PathBuf},
     process::exit,
 };
-use world::World;
+use watch::watch;
+
+/// What to do with the world.
+#[derive(PartialEq)]
+enum Command {
+    /// Compile it and write the output
+    Build,
+    /// Compile it and report problems, without writing anything
+    Check,
+    /// Compile it every time it changes
+    Watch,
+    /// Run a language server for editors, instead of compiling a world
+    Lsp,
+    /// Print the explanation of a diagnostic's code, instead of compiling
+    Explain,
+    /// Copy a board out of the world into a board file
+    Extract,
+    /// Add a board to the world from a board file, or replace one with it
+    Insert,
+    /// Write the world out as a directory of text files
+    Unpack,
+    /// Build a world from a directory written by `Unpack`
+    Pack,
+}
 
 fn main() -> Result<()> {
+    let mut options = Options::default();
+    let mut command = None;
     let mut input_file = None;
+    // Positional args after the input file, for commands that take more than one
+    let mut extra_args = vec![];
     let mut output_file = None;
+    let mut replace = None;
+    let mut json = false;
+    let mut color = None;
     let mut parser = lexopt::Parser::from_env();
     let mut has_args = false;
 
@@ -29,11 +71,71 @@ fn main() -> Result<()> {
             Short('o') | Long("output") => {
                 output_file = Some(parser.value()?.string()?);
             }
+            Long("warn-undefined-labels") => {
+                options.label_options.undefined_label_level = error::Level::Warning;
+            }
+            Short('A') | Long("allow") => {
+                let name = parser.value()?.string()?;
+                options.lints.push((name, LintLevel::Allow));
+            }
+            Short('W') | Long("warn") => {
+                let name = parser.value()?.string()?;
+                options.lints.push((name, LintLevel::Warn));
+            }
+            // -D is taken by --define
+            Long("deny") => {
+                let name = parser.value()?.string()?;
+                options.lints.push((name, LintLevel::Deny));
+            }
+            Long("replace") => {
+                replace = Some(parser.value()?.string()?);
+            }
+            Long("minify") => {
+                options.minify = true;
+            }
+            Short('D') | Long("define") => {
+                let variable = parse_variable(&parser.value()?.string()?)?;
+                options.variables.push(variable);
+            }
+            Short('I') | Long("include-path") => {
+                options.include_paths.push(PathBuf::from(parser.value()?));
+            }
+            Long("message-format") => {
+                json = match parser.value()?.string()?.as_str() {
+                    "human" => false,
+                    "json" => true,
+                    other => return Err(anyhow!("Unknown message format: {other:?}")),
+                };
+            }
+            Long("color") => {
+                color = match parser.value()?.string()?.as_str() {
+                    "auto" => None,
+                    "always" => Some(true),
+                    "never" => Some(false),
+                    other => return Err(anyhow!("Unknown color setting: {other:?}")),
+                };
+            }
+            Value(val) if command.is_none() && input_file.is_none() => {
+                command = match val.to_str() {
+                    Some("check") => Some(Command::Check),
+                    Some("watch") => Some(Command::Watch),
+                    Some("lsp") => Some(Command::Lsp),
+                    Some("explain") => Some(Command::Explain),
+                    Some("extract") => Some(Command::Extract),
+                    Some("insert") => Some(Command::Insert),
+                    Some("unpack") => Some(Command::Unpack),
+                    Some("pack") => Some(Command::Pack),
+                    _ => {
+                        input_file = Some(val.string()?);
+                        Some(Command::Build)
+                    }
+                };
+            }
             Value(val) => {
                 if input_file.is_none() {
                     input_file = Some(val.string()?);
                 } else {
-                    return Err(anyhow!("Unknown arg: {}", val.to_string_lossy()));
+                    extra_args.push(val.string()?);
                 }
             }
             _ => return Err(arg.unexpected().into()),
@@ -41,16 +143,154 @@ fn main() -> Result<()> {
     }
 
     if !has_args {
-        eprintln!("Usage: {} INPUT -o OUTPUT", env::args().next().unwrap());
+        let program = env::args().next().unwrap();
+        eprintln!("Usage: {program} INPUT -o OUTPUT");
+        eprintln!("       {program} check INPUT");
+        eprintln!("       {program} watch INPUT -o OUTPUT");
+        eprintln!("       {program} lsp");
+        eprintln!("       {program} explain CODE");
+        eprintln!("       {program} extract WORLD BOARD -o OUTPUT.BRD");
+        eprintln!("       {program} insert WORLD INPUT.BRD [--replace BOARD] -o OUTPUT");
+        eprintln!("       {program} unpack WORLD DIR");
+        eprintln!("       {program} pack DIR -o OUTPUT");
         exit(1);
     }
 
+    let command = command.unwrap_or(Command::Build);
+    let max_extra_args = match command {
+        Command::Extract | Command::Insert | Command::Unpack => 1,
+        _ => 0,
+    };
+    if let Some(arg) = extra_args.get(max_extra_args) {
+        return Err(anyhow!("Unknown arg: {arg}"));
+    }
+    if replace.is_some() && command != Command::Insert {
+        return Err(anyhow!("Only insert takes --replace"));
+    }
+    if command == Command::Lsp {
+        if input_file.is_some() {
+            return Err(anyhow!("lsp doesn't take an input file"));
+        }
+        return lsp::run();
+    }
+    if command == Command::Explain {
+        let query = input_file.ok_or_else(|| anyhow!("No code specified, like E0004"))?;
+        let code = codes::lookup(&query).ok_or_else(|| anyhow!("Unknown code: {query:?}"))?;
+        println!("{} ({})\n\n{}", code.code, code.name, code.explanation);
+        return Ok(());
+    }
+
+    if command == Command::Extract || command == Command::Insert {
+        let world_filename = input_file.ok_or_else(|| anyhow!("No world file specified"))?;
+        let output_filename = output_file.ok_or_else(|| anyhow!("No output file specified"))?;
+        let output_path = check_output_path(&world_filename, &output_filename)?;
+        let world_path = Path::new(&world_filename);
+        let status = if command == Command::Extract {
+            let query = extra_args
+                .first()
+                .ok_or_else(|| anyhow!("No board specified, by number or name"))?;
+            board_file::extract(world_path, query, &output_path)?
+        } else {
+            let board_filename = extra_args
+                .first()
+                .ok_or_else(|| anyhow!("No board file specified"))?;
+            board_file::insert(
+                world_path,
+                Path::new(board_filename),
+                replace.as_deref(),
+                &output_path,
+            )?
+        };
+        println!("{status}");
+        return Ok(());
+    }
+    if command == Command::Unpack {
+        if output_file.is_some() {
+            return Err(anyhow!(
+                "unpack takes the directory to write as its second arg, not -o"
+            ));
+        }
+        let world_filename = input_file.ok_or_else(|| anyhow!("No world file specified"))?;
+        let dir = extra_args
+            .first()
+            .ok_or_else(|| anyhow!("No directory specified"))?;
+        println!(
+            "{}",
+            unpack::unpack(Path::new(&world_filename), Path::new(dir))?
+        );
+        return Ok(());
+    }
+    if command == Command::Pack {
+        let dir = input_file.ok_or_else(|| anyhow!("No directory specified"))?;
+        let output_filename = output_file.ok_or_else(|| anyhow!("No output file specified"))?;
+        check_output_kind(&dir, &output_filename)?;
+        let output_path = check_output_path(&dir, &output_filename)?;
+        println!("{}", unpack::pack(Path::new(&dir), &output_path)?);
+        return Ok(());
+    }
+
+    // Only use color if it's going to a terminal, unless told otherwise
+    let message_format = match json {
+        true => MessageFormat::Json,
+        false => MessageFormat::Human {
+            color: color
+                .unwrap_or_else(|| io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()),
+        },
+    };
     let input_filename = input_file.ok_or_else(|| anyhow!("No input file specified"))?;
+    options.input_file = input_filename.clone();
+    if command == Command::Check {
+        if output_file.is_some() {
+            return Err(anyhow!(
+                "check doesn't write any output, so it doesn't take -o"
+            ));
+        }
+        let compilation = compile(&options)?;
+        compilation.print_diagnostics(message_format);
+        if !compilation.succeeded() {
+            exit(1);
+        }
+        return Ok(());
+    }
     let output_filename = output_file.ok_or_else(|| anyhow!("No output file specified"))?;
+    check_output_kind(&input_filename, &output_filename)?;
+    let output_path = check_output_path(&input_filename, &output_filename)?;
 
-    // Prevent overwriting input file
-    let input_path = Path::new(&input_filename).canonicalize()?;
-    let output_path = Path::new(&output_filename);
+    if command == Command::Watch {
+        watch(&options, &output_path, message_format);
+    }
+    let compilation = compile(&options)?;
+    compilation.print_diagnostics(message_format);
+    if !compilation.succeeded() {
+        exit(1);
+    }
+
+    // Try to write a modified world file
+    compilation.write(&output_path)
+}
+
+/// Make sure the output is the same kind of file as the input, for commands
+/// that write what they read: a world as a world, or a board file as a board
+/// file.
+fn check_output_kind(input_filename: &str, output_filename: &str) -> Result<()> {
+    match (
+        is_board_file(Path::new(input_filename)),
+        is_board_file(Path::new(output_filename)),
+    ) {
+        (true, false) => Err(anyhow!(
+            "{input_filename} is a board file, so the output has to end in .BRD too (use `insert` to put the board in a world)"
+        )),
+        (false, true) => Err(anyhow!(
+            "{input_filename} is a world, so the output can't be a board file (use `extract` to copy a board out of it)"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Resolve the output path, making sure it won't overwrite the input file.
+fn check_output_path(input_filename: &str, output_filename: &str) -> Result<PathBuf> {
+    let input_path = Path::new(input_filename).canonicalize()?;
+    let output_path = Path::new(output_filename);
     let output_dir = output_path
         .parent()
         .filter(|x| !x.as_os_str().is_empty())
@@ -61,69 +301,19 @@ fn main() -> Result<()> {
         eprintln!("Error: Output file cannot be the same as input file");
         exit(1);
     }
+    Ok(output_path)
+}
 
-    let bytes = fs::read(&input_path)?;
-    let mut world = World::from_bytes(&bytes)?;
-
-    // Prepare to evaluate macros from the world file's directory
-    let world_pathbuf = PathBuf::from(&input_path);
-    let world_dir = world_pathbuf
-        .parent()
-        .ok_or(anyhow!("Couldn't get world's directory"))?;
-    let eval_context = Context::new(&world_dir);
-
-    // Codegen: Evaluate all macros
-    for board in &mut world.boards {
-        for stat in &mut board.stats {
-            stat.code = eval_context.eval_program(&stat.code)?;
-        }
-    }
-
-    // Resolve labels to proper ZZT-OOP
-    let base_ctx = ErrContext::new();
-    let ctx = base_ctx.with_file_path(&input_filename);
-    for (i, board) in world.boards.iter_mut().enumerate() {
-        let ctx = ctx.with_board(i);
-        if let Some(processed_board) = process_labels(&board, &ctx) {
-            *board = processed_board;
-        }
-    }
-
-    // Print diagnostics
-    let messages = base_ctx.into_messages();
-    for message in messages.iter() {
-        println!("{}\n", message.rich_format(&world));
-    }
-    if !messages.is_empty() {
-        let mut warnings = 0;
-        let mut errors = 0;
-        for message in messages {
-            match message.level {
-                error::Level::Error => errors += 1,
-                error::Level::Warning => warnings += 1,
-            }
-        }
-        let plural = |x| if x == 1 { "" } else { "s" };
-        if errors == 0 {
-            println!(
-                "Compilation succeeded with {warnings} warning{}.",
-                plural(warnings)
-            )
-        } else {
-            println!(
-                "Compilation failed with {warnings} warning{} and {errors} error{}.",
-                plural(warnings),
-                plural(errors)
-            );
-            exit(1);
-        }
+/// Parse a `-D NAME=value` arg. A name on its own sets the variable to 1.
+fn parse_variable(arg: &str) -> Result<(String, MacroValue)> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, MacroValue::from_arg(value)),
+        None => (arg, MacroValue::Integer(1)),
+    };
+    let is_identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(anyhow!("Invalid variable name for -D: {name:?}"));
     }
-
-    // Try to write a modified world file
-    let world_bytes = world
-        .to_bytes()
-        .map_err(|e| anyhow!("Couldn't serialize world, {}", e))?;
-    fs::write(&output_path, world_bytes)?;
-
-    Ok(())
+    Ok((name.into(), value))
 }
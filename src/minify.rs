@@ -0,0 +1,409 @@
+//! Shrink ZZT-OOP code without changing what it does.
+//!
+//! ZZT 3.2 boards are limited to about 20 KB, and generated code eats into
+//! that quickly. This runs after labels have been processed, so it only ever
+//! sees vanilla ZZT-OOP.
+
+use compact_str::CompactString;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    labels::check::{leading_word_key, name_key, object_name},
+    oop::{
+        ast::{
+            Command, CommandKind, Condition, ConditionKind, Direction, Kind, Message, Program,
+            Span, Statement, StatementKind,
+        },
+        keywords::COMMANDS,
+    },
+    world::Board,
+};
+
+/// Minify the code of every stat on a board, and then bind stats with the
+/// same code together.
+///
+/// Returns the number of bytes saved.
+pub fn minify_board(board: &mut Board) -> usize {
+    let zapped = zapped_labels(board);
+    let mut saved = 0;
+    for stat in &mut board.stats {
+        // A stat that's partway through its code would lose its place
+        if stat.instruction_pointer > 0 {
+            continue;
+        }
+        let code = minify_code(&stat.code, &zapped);
+        saved += stat
+            .code
+            .chars()
+            .count()
+            .saturating_sub(code.chars().count());
+        stat.code = code;
+    }
+    saved + bind_duplicates(board)
+}
+
+/// Bind stats to earlier stats with exactly the same code, so the board only
+/// stores the code once.
+///
+/// Bound stats share their code in memory, so if one of them zaps a label,
+/// they all lose it. Stats whose labels might get zapped or restored are left
+/// alone.
+///
+/// Returns the number of bytes saved.
+pub fn bind_duplicates(board: &mut Board) -> usize {
+    let zappable = zappable_stats(board);
+    let mut first: FxHashMap<&str, usize> = FxHashMap::default();
+    let mut binds = vec![];
+    // The player (stat 0) can't be bound to
+    for (i, stat) in board.stats.iter().enumerate().skip(1) {
+        if stat.bound_to.is_some() || stat.code.is_empty() || zappable[i] {
+            continue;
+        }
+        let target = *first.entry(&stat.code).or_insert(i);
+        if target != i {
+            binds.push((i, target));
+        }
+    }
+
+    let mut saved = 0;
+    for (i, target) in binds {
+        let stat = &mut board.stats[i];
+        saved += stat.code.chars().count();
+        stat.code.clear();
+        stat.bound_to = Some(target);
+    }
+    saved
+}
+
+/// Find the stats whose code might get changed by `#zap` or `#restore`,
+/// either their own or another object's.
+fn zappable_stats(board: &Board) -> Vec<bool> {
+    let programs: Vec<_> = board
+        .stats
+        .iter()
+        .map(|stat| Program::parse(&stat.code))
+        .collect();
+    let names: Vec<_> = (0..board.stats.len())
+        .map(|i| object_name(&programs[board.code_index(i)]))
+        .collect();
+
+    let mut result = vec![false; board.stats.len()];
+    for (i, program) in programs.iter().enumerate() {
+        for statement in program.statements() {
+            let StatementKind::Command(Command {
+                kind: CommandKind::Zap(message) | CommandKind::Restore(message),
+                ..
+            }) = &statement.kind
+            else {
+                continue;
+            };
+            let recipient = message.recipient.as_ref().map(|x| name_key(x.value));
+            for j in 0..board.stats.len() {
+                let targeted = match recipient.as_deref() {
+                    None | Some("self") => j == i,
+                    Some("all" | "others") => true,
+                    Some(name) => names[j].as_deref() == Some(name),
+                };
+                if targeted {
+                    // Zapping a bound stat changes the code it's bound to
+                    result[j] = true;
+                    result[board.code_index(j)] = true;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Minify a single stat's code.
+///
+/// `zapped` holds the lowercased names of labels that get zapped or restored
+/// anywhere on the board. Comments named after those labels are kept, because
+/// `#restore` can turn them back into labels.
+pub fn minify_code(code: &str, zapped: &FxHashSet<CompactString>) -> String {
+    let program = Program::parse(code);
+    let statements = program.statements();
+    let defined = statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Label(label) => Some(name_key(&code[label.span.clone()])),
+            _ => None,
+        })
+        .collect();
+    let minifier = Minifier {
+        code,
+        defined,
+        zapped,
+    };
+
+    let mut lines = vec![];
+    for (i, line) in program.lines.iter().enumerate() {
+        if let [
+            Statement {
+                kind: StatementKind::Comment(text),
+                ..
+            },
+        ] = line.statements[..]
+        {
+            // Removing a leading comment would turn an `@` line into the object's name
+            let next_is_name = program.lines.get(i + 1).is_some_and(|x| {
+                matches!(
+                    x.statements.first(),
+                    Some(Statement {
+                        kind: StatementKind::Name(_),
+                        ..
+                    })
+                )
+            });
+            let is_zapped_label = leading_word_key(text).is_some_and(|x| zapped.contains(&x));
+            let keep = is_zapped_label || (lines.is_empty() && next_is_name);
+            if !keep {
+                continue;
+            }
+        }
+
+        let mut out = String::new();
+        for statement in &line.statements {
+            minifier.statement(statement, &mut out);
+        }
+        lines.push(out);
+    }
+    lines.join("\n")
+}
+
+/// Find the names of all labels that get zapped or restored on a board.
+fn zapped_labels(board: &Board) -> FxHashSet<CompactString> {
+    let mut result = FxHashSet::default();
+    for stat in &board.stats {
+        let program = Program::parse(&stat.code);
+        for statement in program.statements() {
+            if let StatementKind::Command(Command {
+                kind: CommandKind::Zap(message) | CommandKind::Restore(message),
+                ..
+            }) = &statement.kind
+            {
+                result.insert(name_key(&stat.code[message.label.span.clone()]));
+            }
+        }
+    }
+    result
+}
+
+struct Minifier<'a> {
+    code: &'a str,
+    /// Lowercased names of the labels defined in this stat
+    defined: FxHashSet<CompactString>,
+    /// Lowercased names of the labels that might get zapped
+    zapped: &'a FxHashSet<CompactString>,
+}
+
+impl Minifier<'_> {
+    fn statement(&self, statement: &Statement, out: &mut String) {
+        match &statement.kind {
+            StatementKind::Movement(movement) => {
+                out.push_str(&self.text(&statement.span)[..1]);
+                out.push_str(&self.direction(&movement.direction));
+            }
+            StatementKind::Command(command) if command.trailing.is_none() => {
+                if self.text(&statement.span).starts_with('#') {
+                    out.push('#');
+                }
+                self.command(command, out);
+            }
+            // Leave everything else alone, including commands that ZZT
+            // might be reading a little differently than we are
+            _ => out.push_str(self.text(&statement.span)),
+        }
+    }
+
+    fn command(&self, command: &Command, out: &mut String) {
+        use CommandKind::*;
+        let words = match &command.kind {
+            Send { message, .. } if self.can_shorten(message) => {
+                vec![self.text(&message.span).into()]
+            }
+            Send { message, .. } => vec!["send".into(), self.text(&message.span).into()],
+            Become(kind) => vec!["become".into(), self.kind(kind)],
+            Bind(word) => vec!["bind".into(), self.text(&word.span).into()],
+            Change(from, to) => vec!["change".into(), self.kind(from), self.kind(to)],
+            Char(value) => vec!["char".into(), self.text(&value.span).into()],
+            Clear(word) => vec!["clear".into(), self.text(&word.span).into()],
+            Cycle(value) => vec!["cycle".into(), self.text(&value.span).into()],
+            Die => vec!["die".into()],
+            End => vec!["end".into()],
+            EndGame => vec!["endgame".into()],
+            Give {
+                counter, amount, ..
+            } => vec![
+                "give".into(),
+                self.text(&counter.span).into(),
+                self.text(&amount.span).into(),
+            ],
+            Go(direction) => vec!["go".into(), self.direction(direction)],
+            Idle => vec!["idle".into()],
+            If { condition, .. } => vec!["if".into(), self.condition(condition)],
+            Lock => vec!["lock".into()],
+            Play(music) => match music.value.trim_start() {
+                "" => vec!["play".into()],
+                music => vec!["play".into(), music.into()],
+            },
+            Put(direction, kind) => vec!["put".into(), self.direction(direction), self.kind(kind)],
+            Restart => vec!["restart".into()],
+            Restore(message) => vec!["restore".into(), self.text(&message.span).into()],
+            Set(word) => vec!["set".into(), self.text(&word.span).into()],
+            Shoot(direction) => vec!["shoot".into(), self.direction(direction)],
+            Take {
+                counter, amount, ..
+            } => vec![
+                "take".into(),
+                self.text(&counter.span).into(),
+                self.text(&amount.span).into(),
+            ],
+            ThrowStar(direction) => vec!["throwstar".into(), self.direction(direction)],
+            Try { direction, .. } => vec!["try".into(), self.direction(direction)],
+            Unlock => vec!["unlock".into()],
+            Walk(direction) => vec!["walk".into(), self.direction(direction)],
+            Zap(message) => vec!["zap".into(), self.text(&message.span).into()],
+        };
+        out.push_str(&words.join(" "));
+
+        // Compound commands keep one space before the command they run
+        let nested = command.nested();
+        if !nested.is_empty() {
+            out.push(' ');
+            for statement in nested {
+                self.statement(statement, out);
+            }
+        }
+    }
+
+    /// Check whether `#send foo` can be written as `#foo`.
+    ///
+    /// ZZT complains about `#foo` if the object doesn't have a `:foo` label, so
+    /// this only happens for labels that are defined here and never zapped.
+    /// Messages with a recipient, like `#door:open`, are always safe.
+    fn can_shorten(&self, message: &Message) -> bool {
+        let text = name_key(self.text(&message.span));
+        let is_keyword = leading_word_key(&text).is_some_and(|x| COMMANDS.contains(&x.as_str()));
+        let is_safe = message.recipient.is_some()
+            || (self.defined.contains(&text) && !self.zapped.contains(&text));
+        !is_keyword && is_safe
+    }
+
+    fn condition(&self, condition: &Condition) -> String {
+        let mut words = vec!["not".to_string(); condition.negations];
+        match &condition.kind {
+            ConditionKind::Alligned => words.push("alligned".into()),
+            ConditionKind::Any(kind) => words.extend(["any".into(), self.kind(kind)]),
+            ConditionKind::Blocked(direction) => {
+                words.extend(["blocked".into(), self.direction(direction)])
+            }
+            ConditionKind::Contact => words.push("contact".into()),
+            ConditionKind::Energized => words.push("energized".into()),
+            ConditionKind::Flag(flag) => words.push(flag.value.into()),
+        }
+        words.join(" ")
+    }
+
+    fn direction(&self, direction: &Direction) -> String {
+        let mut words: Vec<_> = direction.modifiers.iter().map(|x| x.value.name()).collect();
+        words.push(direction.base.value.short_name());
+        words.join(" ")
+    }
+
+    fn kind(&self, kind: &Kind) -> String {
+        match &kind.color {
+            Some(color) => format!("{} {}", self.text(&color.span), kind.element.value),
+            None => kind.element.value.into(),
+        }
+    }
+
+    fn text(&self, span: &Span) -> &str {
+        &self.code[span.clone()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use insta::assert_snapshot;
+
+    use crate::world::test::board_with_objects;
+
+    use super::*;
+
+    fn minify(code: &str) -> String {
+        minify_code(code, &FxHashSet::default())
+    }
+
+    fn binds(board: &Board) -> Vec<Option<usize>> {
+        board.stats.iter().map(|x| x.bound_to).collect()
+    }
+
+    #[test]
+    fn test_minify() {
+        let code = fs::read_to_string("tests/minify/commands.txt").unwrap();
+        assert_snapshot!(minify(&code));
+    }
+
+    #[test]
+    fn test_shorthand_send() {
+        assert_eq!(minify("#send foo\n:foo"), "#foo\n:foo");
+        assert_eq!(minify("#send foo"), "#send foo");
+        assert_eq!(minify("#send die\n:die"), "#send die\n:die");
+        assert_eq!(minify("#send end_it\n:end_it"), "#end_it\n:end_it");
+        assert_eq!(minify("#send  door:open"), "#door:open");
+        assert_eq!(minify("#send die:open"), "#send die:open");
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(minify("'note\n#end\n'more"), "#end");
+        assert_eq!(minify("'note\n@Name\n#end"), "'note\n@Name\n#end");
+
+        let zapped = FxHashSet::from_iter(["foo".into()]);
+        assert_eq!(
+            minify_code("'foo\n'bar\n#send foo\n#end", &zapped),
+            "'foo\n#send foo\n#end"
+        );
+        assert_eq!(
+            minify_code(":foo\n#zap foo\n#send foo", &zapped),
+            ":foo\n#zap foo\n#send foo"
+        );
+    }
+
+    #[test]
+    fn test_bind_duplicates() {
+        let mut board =
+            board_with_objects(&["@a\n#end\n", "/n\n", "/n\n", "@a\n#end\n", "/n\n", ""]);
+        assert_eq!(bind_duplicates(&mut board), 3 + 3 + 8);
+        assert_eq!(
+            binds(&board),
+            [None, None, None, Some(2), Some(1), Some(2), None]
+        );
+        assert!(board.stats[3].code.is_empty());
+        assert!(board.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn test_zapped_stats_arent_bound() {
+        let mut board = board_with_objects(&[
+            ":a\n#zap a\n",
+            ":a\n#zap a\n",
+            "@door\n:open\n",
+            "@door\n:open\n",
+            "@key\n#if any gem #zap door:open\n",
+            "@gem\n#end\n",
+            "@gem\n#end\n",
+        ]);
+        bind_duplicates(&mut board);
+        assert_eq!(
+            binds(&board),
+            [None, None, None, None, None, None, None, Some(6)]
+        );
+
+        let mut board = board_with_objects(&["#end\n", "#end\n", "#restore all:x\n"]);
+        assert_eq!(bind_duplicates(&mut board), 0);
+    }
+}
@@ -0,0 +1,728 @@
+//! Typed syntax tree for ZZT-OOP.
+//!
+//! Every node records the span of code it came from, so later passes can
+//! report diagnostics or rewrite code without walking the parse tree again.
+//!
+//! Some fields aren't read by any pass yet, but they're part of what the tree
+//! models and they show up in the tests' snapshots, so they're kept with a
+//! narrow `#[allow(dead_code)]`.
+
+use std::ops::Range;
+
+use crate::peg::{Capture, ParseState};
+
+use super::grammar::{self, Tag};
+
+pub type Span = Range<usize>;
+
+/// A value, along with the span of code it came from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub value: T,
+}
+
+#[derive(Debug)]
+pub struct Program<'a> {
+    pub lines: Vec<Line<'a>>,
+}
+
+/// A single line of code.
+///
+/// Most lines hold a single statement, but movements can be chained together
+/// with a command at the end: `/n/n#send done`.
+#[derive(Debug)]
+pub struct Line<'a> {
+    #[allow(dead_code)]
+    pub span: Span,
+    pub statements: Vec<Statement<'a>>,
+}
+
+#[derive(Debug)]
+pub struct Statement<'a> {
+    pub span: Span,
+    pub kind: StatementKind<'a>,
+}
+
+#[derive(Debug)]
+pub enum StatementKind<'a> {
+    /// Text to display, e.g., `Hello!`
+    #[allow(dead_code)]
+    Text(&'a str),
+    /// Centered text to display, e.g., `$Hello!` (without the `$`)
+    #[allow(dead_code)]
+    Centered(&'a str),
+    /// The object's name, e.g., `@Guard` (without the `@`)
+    Name(&'a str),
+    /// A comment, e.g., `'note` (without the `'`). This might be a zapped label.
+    Comment(&'a str),
+    /// A label definition, e.g., `:touch`
+    Label(Label<'a>),
+    /// A hyperlink, e.g., `!foo;Text`
+    #[allow(dead_code)]
+    Hyperlink(Hyperlink<'a>),
+    /// A movement, e.g., `/n` or `?n`
+    Movement(Movement),
+    /// A command, e.g., `#send foo`
+    Command(Command<'a>),
+}
+
+#[derive(Debug)]
+pub struct Label<'a> {
+    pub span: Span,
+    #[allow(dead_code)]
+    pub namespace: Option<&'a str>,
+    #[allow(dead_code)]
+    pub name: LabelName<'a>,
+}
+
+#[derive(Debug)]
+pub enum LabelName<'a> {
+    /// A named label: `foo`, `foo.local`, or `.local`
+    #[allow(dead_code)]
+    Named {
+        global: Option<&'a str>,
+        local: Option<&'a str>,
+    },
+    /// An anonymous label: `@` in a definition, `@b` or `@f` in a reference
+    #[allow(dead_code)]
+    Anonymous(&'a str),
+}
+
+/// A message sent to a label, e.g., `door:open` in `#send door:open`.
+#[derive(Debug)]
+pub struct Message<'a> {
+    pub span: Span,
+    pub recipient: Option<Spanned<&'a str>>,
+    pub label: Label<'a>,
+}
+
+#[derive(Debug)]
+pub struct Hyperlink<'a> {
+    #[allow(dead_code)]
+    pub target: HyperlinkTarget<'a>,
+    #[allow(dead_code)]
+    pub text: Spanned<&'a str>,
+}
+
+#[derive(Debug)]
+pub enum HyperlinkTarget<'a> {
+    /// `!foo;Text` sends a message
+    #[allow(dead_code)]
+    Message(Message<'a>),
+    /// `!-file;Text` opens a help file (the span excludes the `-`)
+    #[allow(dead_code)]
+    HelpFile(Spanned<&'a str>),
+}
+
+#[derive(Debug)]
+pub struct Movement {
+    #[allow(dead_code)]
+    pub mode: MovementMode,
+    pub direction: Direction,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MovementMode {
+    /// `/n`: keep trying until the move succeeds
+    Go,
+    /// `?n`: try to move once
+    Try,
+}
+
+#[derive(Debug)]
+pub struct Direction {
+    #[allow(dead_code)]
+    pub span: Span,
+    pub modifiers: Vec<Spanned<DirectionModifier>>,
+    pub base: Spanned<BaseDirection>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DirectionModifier {
+    Cw,
+    Ccw,
+    Opp,
+    Rndp,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BaseDirection {
+    Flow,
+    Rnd,
+    RndNe,
+    RndNs,
+    Seek,
+    North,
+    South,
+    East,
+    West,
+    Idle,
+}
+
+/// A kind of tile, e.g., `red lion`.
+#[derive(Debug)]
+pub struct Kind<'a> {
+    #[allow(dead_code)]
+    pub span: Span,
+    pub color: Option<Spanned<Color>>,
+    pub element: Spanned<&'a str>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Blue,
+    Green,
+    Cyan,
+    Red,
+    Purple,
+    Yellow,
+    White,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Counter {
+    Ammo,
+    Gems,
+    Health,
+    Score,
+    Time,
+    /// ZZT only
+    Torches,
+    /// Stones of Power, Super ZZT only
+    Z,
+}
+
+#[derive(Debug)]
+pub struct Condition<'a> {
+    #[allow(dead_code)]
+    pub span: Span,
+    /// How many times `not` appears before the condition
+    pub negations: usize,
+    pub kind: ConditionKind<'a>,
+}
+
+#[derive(Debug)]
+pub enum ConditionKind<'a> {
+    Alligned,
+    Any(Kind<'a>),
+    Blocked(Direction),
+    Contact,
+    Energized,
+    Flag(Spanned<&'a str>),
+}
+
+#[derive(Debug)]
+pub struct Command<'a> {
+    #[allow(dead_code)]
+    pub span: Span,
+    pub kind: CommandKind<'a>,
+    /// Extra text at the end of the line, which ZZT ignores
+    pub trailing: Option<Span>,
+}
+
+#[derive(Debug)]
+pub enum CommandKind<'a> {
+    Become(Kind<'a>),
+    Bind(Spanned<&'a str>),
+    Change(Kind<'a>, Kind<'a>),
+    Char(Spanned<u32>),
+    Clear(Spanned<&'a str>),
+    Cycle(Spanned<u32>),
+    Die,
+    End,
+    EndGame,
+    Give {
+        counter: Spanned<Counter>,
+        amount: Spanned<u32>,
+        otherwise: Vec<Statement<'a>>,
+    },
+    Go(Direction),
+    Idle,
+    If {
+        condition: Condition<'a>,
+        then: Vec<Statement<'a>>,
+    },
+    Lock,
+    Play(Spanned<&'a str>),
+    Put(Direction, Kind<'a>),
+    Restart,
+    Restore(Message<'a>),
+    /// `#send foo`, or just `#foo` if `shorthand` is set
+    Send {
+        message: Message<'a>,
+        #[allow(dead_code)]
+        shorthand: bool,
+    },
+    Set(Spanned<&'a str>),
+    Shoot(Direction),
+    Take {
+        counter: Spanned<Counter>,
+        amount: Spanned<u32>,
+        otherwise: Vec<Statement<'a>>,
+    },
+    ThrowStar(Direction),
+    Try {
+        direction: Direction,
+        otherwise: Vec<Statement<'a>>,
+    },
+    Unlock,
+    Walk(Direction),
+    Zap(Message<'a>),
+}
+
+impl<'a> Program<'a> {
+    pub fn parse(code: &'a str) -> Self {
+        Self::try_parse(code).unwrap_or_else(|| panic!("Couldn't parse code: {:?}", code))
+    }
+
+    /// Parse code that might not be valid, e.g., because the user is still
+    /// typing it.
+    pub fn try_parse(code: &'a str) -> Option<Self> {
+        let mut parser = ParseState::new(code);
+        if !grammar::program(&mut parser) {
+            return None;
+        }
+
+        // Every top-level capture is a statement; group them by line
+        let builder = Builder { code };
+        let mut statements = parser
+            .captures()
+            .map(|cap| builder.statement(&cap))
+            .peekable();
+        let mut lines = vec![];
+        let mut offset = 0;
+        for line in code.split('\n') {
+            let span = offset..offset + line.len();
+            let mut line = Line {
+                span: span.clone(),
+                statements: vec![],
+            };
+            while let Some(statement) = statements.next_if(|x| x.span.start <= span.end) {
+                line.statements.push(statement);
+            }
+            lines.push(line);
+            offset = span.end + 1;
+        }
+        Some(Program { lines })
+    }
+
+    /// Get every statement in the program, including the ones nested inside
+    /// compound commands like `#if`.
+    pub fn statements(&self) -> Vec<&Statement<'a>> {
+        fn visit<'s, 'a>(statements: &'s [Statement<'a>], result: &mut Vec<&'s Statement<'a>>) {
+            for statement in statements {
+                result.push(statement);
+                if let StatementKind::Command(command) = &statement.kind {
+                    visit(command.nested(), result);
+                }
+            }
+        }
+        let mut result = vec![];
+        for line in &self.lines {
+            visit(&line.statements, &mut result);
+        }
+        result
+    }
+}
+
+impl<'a> Command<'a> {
+    /// Get the statements that a compound command like `#if` might run.
+    pub fn nested(&self) -> &[Statement<'a>] {
+        match &self.kind {
+            CommandKind::Give { otherwise, .. }
+            | CommandKind::Take { otherwise, .. }
+            | CommandKind::Try { otherwise, .. } => otherwise,
+            CommandKind::If { then, .. } => then,
+            _ => &[],
+        }
+    }
+}
+
+impl DirectionModifier {
+    pub fn name(self) -> &'static str {
+        match self {
+            DirectionModifier::Cw => "cw",
+            DirectionModifier::Ccw => "ccw",
+            DirectionModifier::Opp => "opp",
+            DirectionModifier::Rndp => "rndp",
+        }
+    }
+}
+
+impl BaseDirection {
+    /// Get the shortest name ZZT accepts for this direction, e.g., `n` for `north`.
+    pub fn short_name(self) -> &'static str {
+        match self {
+            BaseDirection::Flow => "flow",
+            BaseDirection::Rnd => "rnd",
+            BaseDirection::RndNe => "rndne",
+            BaseDirection::RndNs => "rndns",
+            BaseDirection::Seek => "seek",
+            BaseDirection::North => "n",
+            BaseDirection::South => "s",
+            BaseDirection::East => "e",
+            BaseDirection::West => "w",
+            BaseDirection::Idle => "i",
+        }
+    }
+}
+
+/// Converts captures from the grammar into AST nodes.
+struct Builder<'a> {
+    code: &'a str,
+}
+
+type Cap<'p> = Capture<'p, Tag>;
+
+impl<'a> Builder<'a> {
+    fn statement(&self, cap: &Cap) -> Statement<'a> {
+        let text = self.text(cap);
+        let kind = match cap.kind() {
+            Tag::Text => StatementKind::Text(text),
+            Tag::Centered => StatementKind::Centered(&text[1..]),
+            Tag::Name => StatementKind::Name(&text[1..]),
+            Tag::Comment => StatementKind::Comment(&text[1..]),
+            Tag::LabelLine => StatementKind::Label(self.label(&child(cap, Tag::Label))),
+            Tag::Hyperlink => StatementKind::Hyperlink(self.hyperlink(cap)),
+            Tag::Movement => StatementKind::Movement(self.movement(cap)),
+            Tag::Command => StatementKind::Command(self.command(cap)),
+            tag => unreachable!("unexpected statement: {:?}", tag),
+        };
+        Statement {
+            span: cap.span(),
+            kind,
+        }
+    }
+
+    fn label(&self, cap: &Cap) -> Label<'a> {
+        let mut namespace = None;
+        let (mut global, mut local, mut anon) = (None, None, None);
+        for child in cap.children() {
+            match child.kind() {
+                Tag::Namespace => namespace = Some(self.text(&child)),
+                Tag::Global => global = Some(self.text(&child)),
+                Tag::Local => local = Some(self.text(&child)),
+                Tag::Anon => anon = Some(self.text(&child)),
+                tag => unreachable!("unexpected label part: {:?}", tag),
+            }
+        }
+        let name = match anon {
+            Some(anon) => LabelName::Anonymous(anon),
+            None => LabelName::Named { global, local },
+        };
+        Label {
+            span: cap.span(),
+            namespace,
+            name,
+        }
+    }
+
+    fn message(&self, cap: &Cap) -> Message<'a> {
+        Message {
+            span: cap.span(),
+            recipient: find_child(cap, Tag::Recipient).map(|x| self.spanned_text(&x)),
+            label: self.label(&child(cap, Tag::Label)),
+        }
+    }
+
+    fn hyperlink(&self, cap: &Cap) -> Hyperlink<'a> {
+        let target = match find_child(cap, Tag::HelpFile) {
+            Some(file) => HyperlinkTarget::HelpFile(self.spanned_text(&file)),
+            None => HyperlinkTarget::Message(self.message(&child(cap, Tag::Reference))),
+        };
+        Hyperlink {
+            target,
+            text: self.spanned_text(&child(cap, Tag::LinkText)),
+        }
+    }
+
+    fn movement(&self, cap: &Cap) -> Movement {
+        let mode = if self.text(cap).starts_with('/') {
+            MovementMode::Go
+        } else {
+            MovementMode::Try
+        };
+        Movement {
+            mode,
+            direction: self.direction(&child(cap, Tag::Direction)),
+        }
+    }
+
+    fn command(&self, cap: &Cap) -> Command<'a> {
+        // Gather up the arguments, then sort them out based on the keyword
+        let mut keyword = None;
+        let mut kinds = vec![];
+        let mut directions = vec![];
+        let mut values = vec![];
+        let mut words = vec![];
+        let mut statements = vec![];
+        let (mut counter, mut condition, mut message, mut music) = (None, None, None, None);
+        let mut trailing = None;
+        for child in cap.children() {
+            match child.kind() {
+                Tag::Keyword => keyword = Some(self.text(&child).to_ascii_lowercase()),
+                Tag::Kind => kinds.push(self.kind(&child)),
+                Tag::Direction => directions.push(self.direction(&child)),
+                Tag::Value => values.push(self.value(&child)),
+                Tag::Word => words.push(self.spanned_text(&child)),
+                Tag::Counter => counter = Some(self.counter(&child)),
+                Tag::Condition => condition = Some(self.condition(&child)),
+                Tag::Reference => message = Some(self.message(&child)),
+                Tag::Music => music = Some(self.spanned_text(&child)),
+                Tag::Movement | Tag::Command => statements.push(self.statement(&child)),
+                Tag::WarnTrailing => trailing = Some(child.span()),
+                tag => unreachable!("unexpected command part: {:?}", tag),
+            }
+        }
+        let mut kinds = kinds.into_iter();
+        let mut directions = directions.into_iter();
+        let mut kind = || kinds.next().unwrap();
+        let mut direction = || directions.next().unwrap();
+        let value = || values.into_iter().next().unwrap();
+        let word = || words.into_iter().next().unwrap();
+        let message = || message.unwrap();
+
+        use CommandKind::*;
+        let kind = match keyword.as_deref() {
+            None => Send {
+                message: message(),
+                shorthand: true,
+            },
+            Some("become") => Become(kind()),
+            Some("bind") => Bind(word()),
+            Some("change") => Change(kind(), kind()),
+            Some("char") => Char(value()),
+            Some("clear") => Clear(word()),
+            Some("cycle") => Cycle(value()),
+            Some("die") => Die,
+            Some("end") => End,
+            Some("endgame") => EndGame,
+            Some("give") => Give {
+                counter: counter.unwrap(),
+                amount: value(),
+                otherwise: statements,
+            },
+            Some("go") => Go(direction()),
+            Some("idle") => Idle,
+            Some("if") => If {
+                condition: condition.unwrap(),
+                then: statements,
+            },
+            Some("lock") => Lock,
+            Some("play") => Play(music.unwrap()),
+            Some("put") => Put(direction(), kind()),
+            Some("restart") => Restart,
+            Some("restore") => Restore(message()),
+            Some("send") => Send {
+                message: message(),
+                shorthand: false,
+            },
+            Some("set") => Set(word()),
+            Some("shoot") => Shoot(direction()),
+            Some("take") => Take {
+                counter: counter.unwrap(),
+                amount: value(),
+                otherwise: statements,
+            },
+            Some("throwstar") => ThrowStar(direction()),
+            Some("try") => Try {
+                direction: direction(),
+                otherwise: statements,
+            },
+            Some("unlock") => Unlock,
+            Some("walk") => Walk(direction()),
+            Some("zap") => Zap(message()),
+            Some(other) => unreachable!("unexpected keyword: {:?}", other),
+        };
+        Command {
+            span: cap.span(),
+            kind,
+            trailing,
+        }
+    }
+
+    fn condition(&self, cap: &Cap) -> Condition<'a> {
+        let mut negations = 0;
+        let mut keyword = None;
+        let (mut kind, mut direction, mut flag) = (None, None, None);
+        for child in cap.children() {
+            match child.kind() {
+                Tag::Not => negations += 1,
+                Tag::Keyword => keyword = Some(self.text(&child).to_ascii_lowercase()),
+                Tag::Kind => kind = Some(self.kind(&child)),
+                Tag::Direction => direction = Some(self.direction(&child)),
+                Tag::Word => flag = Some(self.spanned_text(&child)),
+                tag => unreachable!("unexpected condition part: {:?}", tag),
+            }
+        }
+        let kind = match keyword.as_deref() {
+            None => ConditionKind::Flag(flag.unwrap()),
+            Some("alligned") => ConditionKind::Alligned,
+            Some("any") => ConditionKind::Any(kind.unwrap()),
+            Some("blocked") => ConditionKind::Blocked(direction.unwrap()),
+            Some("contact") => ConditionKind::Contact,
+            Some("energized") => ConditionKind::Energized,
+            Some(other) => unreachable!("unexpected condition: {:?}", other),
+        };
+        Condition {
+            span: cap.span(),
+            negations,
+            kind,
+        }
+    }
+
+    fn direction(&self, cap: &Cap) -> Direction {
+        let mut modifiers = vec![];
+        let mut base = None;
+        for child in cap.children() {
+            let name = self.text(&child).to_ascii_lowercase();
+            let span = child.span();
+            match child.kind() {
+                Tag::Modifier => {
+                    let value = match name.as_str() {
+                        "cw" => DirectionModifier::Cw,
+                        "ccw" => DirectionModifier::Ccw,
+                        "opp" => DirectionModifier::Opp,
+                        "rndp" => DirectionModifier::Rndp,
+                        other => unreachable!("unexpected modifier: {:?}", other),
+                    };
+                    modifiers.push(Spanned { span, value });
+                }
+                Tag::BaseDirection => {
+                    let value = match name.as_str() {
+                        "flow" => BaseDirection::Flow,
+                        "rnd" => BaseDirection::Rnd,
+                        "rndne" => BaseDirection::RndNe,
+                        "rndns" => BaseDirection::RndNs,
+                        "seek" => BaseDirection::Seek,
+                        "n" | "north" => BaseDirection::North,
+                        "s" | "south" => BaseDirection::South,
+                        "e" | "east" => BaseDirection::East,
+                        "w" | "west" => BaseDirection::West,
+                        "i" | "idle" => BaseDirection::Idle,
+                        other => unreachable!("unexpected direction: {:?}", other),
+                    };
+                    base = Some(Spanned { span, value });
+                }
+                tag => unreachable!("unexpected direction part: {:?}", tag),
+            }
+        }
+        Direction {
+            span: cap.span(),
+            modifiers,
+            base: base.unwrap(),
+        }
+    }
+
+    fn kind(&self, cap: &Cap) -> Kind<'a> {
+        let color = find_child(cap, Tag::Color).map(|child| {
+            let value = match self.text(&child).to_ascii_lowercase().as_str() {
+                "blue" => Color::Blue,
+                "green" => Color::Green,
+                "cyan" => Color::Cyan,
+                "red" => Color::Red,
+                "purple" => Color::Purple,
+                "yellow" => Color::Yellow,
+                "white" => Color::White,
+                other => unreachable!("unexpected color: {:?}", other),
+            };
+            Spanned {
+                span: child.span(),
+                value,
+            }
+        });
+        Kind {
+            span: cap.span(),
+            color,
+            element: self.spanned_text(&child(cap, Tag::Element)),
+        }
+    }
+
+    fn counter(&self, cap: &Cap) -> Spanned<Counter> {
+        let value = match self.text(cap).to_ascii_lowercase().as_str() {
+            "ammo" => Counter::Ammo,
+            "gems" => Counter::Gems,
+            "health" => Counter::Health,
+            "score" => Counter::Score,
+            "time" => Counter::Time,
+            "torches" => Counter::Torches,
+            "z" => Counter::Z,
+            other => unreachable!("unexpected counter: {:?}", other),
+        };
+        Spanned {
+            span: cap.span(),
+            value,
+        }
+    }
+
+    fn value(&self, cap: &Cap) -> Spanned<u32> {
+        Spanned {
+            span: cap.span(),
+            value: self.text(cap).parse().unwrap_or(u32::MAX),
+        }
+    }
+
+    fn spanned_text(&self, cap: &Cap) -> Spanned<&'a str> {
+        Spanned {
+            span: cap.span(),
+            value: self.text(cap),
+        }
+    }
+
+    fn text(&self, cap: &Cap) -> &'a str {
+        &self.code[cap.span()]
+    }
+}
+
+fn child<'p>(cap: &Cap<'p>, tag: Tag) -> Cap<'p> {
+    find_child(cap, tag).unwrap_or_else(|| panic!("missing {:?} in {:?}", tag, cap.kind()))
+}
+
+fn find_child<'p>(cap: &Cap<'p>, tag: Tag) -> Option<Cap<'p>> {
+    cap.children().find(|x| x.kind() == tag)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use insta::assert_debug_snapshot;
+
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let code = "@Guard\n'comment\n:touch\n$Centered\nText\n!foo;Link\n!-help;Help\n/n?s#end";
+        assert_debug_snapshot!(Program::parse(code));
+    }
+
+    #[test]
+    fn test_commands() {
+        let code = fs::read_to_string("tests/oop/commands.txt").unwrap();
+        let program = Program::parse(&code);
+        let commands: Vec<_> = program
+            .lines
+            .iter()
+            .flat_map(|line| &line.statements)
+            .filter_map(|statement| match &statement.kind {
+                StatementKind::Command(command) => Some(command),
+                _ => None,
+            })
+            .collect();
+        assert_debug_snapshot!(commands);
+    }
+
+    #[test]
+    fn test_empty() {
+        let program = Program::parse("");
+        assert_eq!(program.lines.len(), 1);
+        assert!(matches!(
+            program.lines[0].statements[..],
+            [Statement {
+                kind: StatementKind::Text(""),
+                ..
+            }]
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! PEG grammar for ZZT-OOP.
+//!
+//! Captures mark every statement and command argument, so the parse tree can
+//! be turned into an AST (see `oop::ast`) or scanned for labels directly.
+
+use mzp_peg_macro::grammar;
+
+grammar! {
+    program = (line ("\n" line)*)? EOI;
+    line = label_line / name_line / comment_line / centered_line / hyperlink / statement / text;
+    statement = movement+ command? / command;
+    movement = #Movement:(("/" / "?") s direction);
+    text = #Text:(!("#" / "/" / "?") (!"\n" ANY)*);
+
+    label_line = #LabelLine:(":" label) eol;
+    name_line = #Name:("@" (!"\n" ANY)*);
+    comment_line = #Comment:("'" (!"\n" ANY)*);
+    centered_line = #Centered:("$" (!"\n" ANY)*);
+
+    // Hyperlinks send a message, unless they start with "-" (load a help file)
+    // Examples: !foo;Text, !door:open;Text, !-file;Text
+    hyperlink = #Hyperlink:("!" (hyperlink_file / message) ";" #LinkText:(!"\n" ANY)*);
+    hyperlink_file = "-" #HelpFile:(!(";" / "\n") ANY)*;
+
+    command = #Command:("#" bare_command);
+    bare_command = bare_compound_command / bare_simple_command;
+    @icase
+    bare_compound_command = (
+        #Keyword:("give" / "take") sp counter sp value /
+        #Keyword:"if" sp condition /
+        #Keyword:"try" sp direction
+    ) s (statement / #Command:bare_command / eol);
+    @icase
+    bare_simple_command = (
+        &'b'..'c' (
+            #Keyword:"become" sp kind /
+            #Keyword:"bind" sp #Word:word /
+            #Keyword:"change" sp kind sp kind /
+            #Keyword:"char" sp value /
+            #Keyword:"clear" sp #Word:word /
+            #Keyword:"cycle" sp value
+        ) /
+        &'d'..'l' (
+            #Keyword:"die" eow /
+            #Keyword:("end" "game"?) eow /
+            #Keyword:"go" sp direction /
+            #Keyword:"idle" eow /
+            #Keyword:"lock" eow
+        ) /
+        &'p'..'s' (
+            #Keyword:"play" eow #Music:(!"\n" ANY)* /
+            #Keyword:"put" sp direction sp kind /
+            #Keyword:"restart" eow /
+            #Keyword:"restore" sp message /
+            #Keyword:"send" sp message /
+            #Keyword:"set" sp #Word:word /
+            #Keyword:"shoot" sp direction
+        ) /
+        &'t'..'z' (
+            #Keyword:"throwstar" sp direction /
+            #Keyword:"unlock" eow /
+            #Keyword:"walk" sp direction /
+            #Keyword:"zap" sp message
+        ) /
+        message // shorthand send
+    ) warn_trailing eol;
+
+    //
+    //  Common definitions
+    //
+
+    // Color names
+    @icase
+    color = #Color:("blue" / "green" / "cyan" / "red" / "purple" / "yellow" / "white") eow;
+
+    // Conditions
+    condition = #Condition:((#Not:"not"i sp)* base_condition);
+    @icase
+    base_condition =
+        // These need `eow`/`sp` immediately after each literal because each one
+        // could potentially appear in a flag name as a prefix: `#set allignedxyz`
+        #Keyword:"alligned" eow /
+        #Keyword:"any" sp kind /
+        #Keyword:"blocked" sp direction /
+        #Keyword:"contact" eow /
+        #Keyword:"energized" eow /
+        #Word:word; // flag name
+
+    // Counter names, for both ZZT and Super ZZT. Only ZZT has torches, and only
+    // Super ZZT has z (Stones of Power).
+    @icase
+    counter = #Counter:("ammo" / "gems" / "health" / "score" / "time" / "torches" / "z") eow;
+
+    // Directions
+    direction = #Direction:((direction_modifier sp)* base_direction);
+    @icase
+    direction_modifier = #Modifier:("cw" / "ccw" / "opp" / "rndp") eow;
+    @icase
+    base_direction = #BaseDirection:(
+        "flow" / "rnd" ("ne" / "ns")? / "seek" /        // dynamic
+        "north" / "south" / "east" / "west" / "idle" /  // long forms
+        "n" / "s" / "e" / "w" / "i"                     // short forms
+    ) eow;
+
+    // Labels
+    // Examples: foo, namespace~foo, foo.local, .local, @
+    label = #Label:(namespace? (label_name / #Anon:"@"));
+    namespace = #Namespace:label_word "~";
+    label_name = label_local / label_global label_local?;
+    label_global = #Global:label_word;
+    label_local = "." #Local:label_word;
+    label_word = word_char+; // labels can start with 0-9
+
+    // References to labels
+    // Examples: foo, all:namespace~bar.baz, @b, @f
+    message = #Reference:(recipient? #Label:message_name);
+    recipient = #Recipient:word ":";
+    message_name = namespace? (label_name / #Anon:anon_message);
+    anon_message = "@" ("b" / "f");
+
+    // Tile kinds, for both ZZT and Super ZZT. The games don't have all the
+    // same elements, but an element name can only follow a command like
+    // `#put`, so there's no risk of mistaking one game's element for a message.
+    kind = #Kind:((color sp)? base_kind);
+    @icase
+    base_kind = #Element:(
+        &'a'..'b' ("ammo" / "bear" / "blinkwall" / "bomb" / "boulder" / "breakable" / "bullet") /
+        &'c'..'e' ("clockwise" / "counter" / "door" / "dragonpup" / "duplicator" / "empty" / "energizer") /
+        &'f'..'k' ("fake" / "floor" / "forest" / "gem" / "head" / "invisible" / "key") /
+        &'l'..'o' ("lava" / "line" / "lion" / "monitor" / "normal" / "object") /
+        &'p'..'r' ("pairer" / "passage" / "player" / "pusher" / "ricochet" / "roton" / "ruffian") /
+        &"s" ("scroll" / "segment" / "shark" / "slider"("ew"/"ns") / "slime" / "solid" / "spider" / "spinninggun" / "star" / "stone") /
+        &'t'..'w' ("tiger" / "torch" / "transporter" / "water"("n"/"s"/"w"/"e")? / "web")
+    ) eow;
+
+    // Warnings
+    warn_trailing = (#WarnTrailing:(!eol ANY)+)?; // TODO: Document precedence rules
+
+    //
+    // Generic helpers
+    //
+
+    eol = &("\n" / EOI);
+    eow = !('a'..'z'i / '0'..'9' / "_");
+    s = " "*;
+    sp = " "+;
+    value = #Value:('0'..'9'+);
+    word = !'0'..'9' word_char+;
+    word_char = ('a'..'z'i / '0'..'9' / "_");
+}
+
+#[cfg(test)]
+mod test {
+    use crate::peg::ParseState;
+
+    fn parse<T: Clone, F: Fn(&mut ParseState<T>) -> bool>(rule: F, input: &str) {
+        use crate::peg::backend::LowLevel;
+        let mut p = ParseState::new(input);
+        assert!(rule(&mut p));
+        assert!(p.eoi());
+    }
+
+    fn parse_err<T: Clone, F: Fn(&mut ParseState<T>) -> bool>(rule: F, input: &str) {
+        use crate::peg::backend::LowLevel;
+        let mut p = ParseState::new(input);
+        assert!(!rule(&mut p) || !p.eoi());
+    }
+
+    #[test]
+    fn test_label() {
+        parse(super::label, "foo");
+        parse(super::label, ".loop");
+        parse(super::label, "foo.loop");
+        parse(super::label, "ns~foo");
+        parse(super::label, "ns~.loop");
+
+        parse_err(super::label, "foo.");
+        parse_err(super::label, "foo.bar.baz");
+        parse_err(super::label, "foo~bar~baz");
+        parse_err(super::label, "~foo");
+    }
+
+    #[test]
+    fn test_direction() {
+        parse(super::direction, "n");
+        parse(super::direction, "north");
+        parse(super::direction, "rndp rndne");
+        parse(super::direction, "opp   seek");
+        parse(super::direction, "cw cw cw flow");
+    }
+
+    #[test]
+    fn test_condition() {
+        parse(super::condition, "alligned");
+        parse(super::condition, "blocked seek");
+        parse(super::condition, "not blocked rndp seek");
+        parse(super::condition, "any red lion");
+        parse(super::condition, "any bear");
+    }
+
+    #[test]
+    fn test_counter() {
+        parse(super::counter, "gems");
+        parse(super::counter, "GEMS");
+        parse_err(super::counter, "gemstones");
+        parse(super::counter, "z");
+        parse_err(super::counter, "zap");
+    }
+
+    #[test]
+    fn test_kind() {
+        parse(super::kind, "red lion");
+        parse(super::kind, "water");
+        parse(super::kind, "watern");
+        parse(super::kind, "blue dragonpup");
+        parse_err(super::kind, "waternorth");
+    }
+}
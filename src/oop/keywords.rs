@@ -0,0 +1,146 @@
+//! The words that have special meaning in ZZT-OOP, as listed in the grammar.
+
+/// Words that ZZT reads as commands, so they can't be used as `#foo` shorthand
+/// for `#send foo`.
+pub const COMMANDS: [&str; 27] = [
+    "become",
+    "bind",
+    "change",
+    "char",
+    "clear",
+    "cycle",
+    "die",
+    "end",
+    "endgame",
+    "give",
+    "go",
+    "idle",
+    "if",
+    "lock",
+    "play",
+    "put",
+    "restart",
+    "restore",
+    "send",
+    "set",
+    "shoot",
+    "take",
+    "throwstar",
+    "try",
+    "unlock",
+    "walk",
+    "zap",
+];
+
+pub const DIRECTIONS: [&str; 15] = [
+    "flow", "rnd", "rndne", "rndns", "seek", "north", "south", "east", "west", "idle", "n", "s",
+    "e", "w", "i",
+];
+
+pub const DIRECTION_MODIFIERS: [&str; 4] = ["cw", "ccw", "opp", "rndp"];
+
+pub const COLORS: [&str; 7] = ["blue", "green", "cyan", "red", "purple", "yellow", "white"];
+
+/// Elements for both ZZT and Super ZZT. See `grammar::base_kind`.
+pub const ELEMENTS: [&str; 54] = [
+    "ammo",
+    "bear",
+    "blinkwall",
+    "bomb",
+    "boulder",
+    "breakable",
+    "bullet",
+    "clockwise",
+    "counter",
+    "door",
+    "dragonpup",
+    "duplicator",
+    "empty",
+    "energizer",
+    "fake",
+    "floor",
+    "forest",
+    "gem",
+    "head",
+    "invisible",
+    "key",
+    "lava",
+    "line",
+    "lion",
+    "monitor",
+    "normal",
+    "object",
+    "pairer",
+    "passage",
+    "player",
+    "pusher",
+    "ricochet",
+    "roton",
+    "ruffian",
+    "scroll",
+    "segment",
+    "shark",
+    "sliderew",
+    "sliderns",
+    "slime",
+    "solid",
+    "spider",
+    "spinninggun",
+    "star",
+    "stone",
+    "tiger",
+    "torch",
+    "transporter",
+    "water",
+    "watere",
+    "watern",
+    "waters",
+    "waterw",
+    "web",
+];
+
+/// Counters for both ZZT and Super ZZT.
+pub const COUNTERS: [&str; 7] = ["ammo", "gems", "health", "score", "time", "torches", "z"];
+
+/// Conditions for `#if`, other than flags.
+pub const CONDITIONS: [&str; 6] = ["alligned", "any", "blocked", "contact", "energized", "not"];
+
+#[cfg(test)]
+mod test {
+    use crate::{oop::grammar, peg::ParseState};
+
+    use super::*;
+
+    fn parses(rule: fn(&mut ParseState<grammar::Tag>) -> bool, input: &str) -> bool {
+        use crate::peg::backend::LowLevel;
+        let mut p = ParseState::new(input);
+        rule(&mut p) && p.eoi()
+    }
+
+    #[test]
+    fn test_keywords_match_grammar() {
+        for direction in DIRECTIONS {
+            assert!(parses(grammar::direction, direction), "{direction}");
+        }
+        for modifier in DIRECTION_MODIFIERS {
+            assert!(parses(grammar::direction, &format!("{modifier} n")));
+        }
+        for element in ELEMENTS {
+            for color in COLORS {
+                assert!(parses(grammar::kind, &format!("{color} {element}")));
+            }
+        }
+        for counter in COUNTERS {
+            assert!(parses(grammar::counter, counter), "{counter}");
+        }
+        for condition in CONDITIONS {
+            let condition = match condition {
+                "any" => "any gem",
+                "blocked" => "blocked n",
+                "not" => "not contact",
+                x => x,
+            };
+            assert!(parses(grammar::condition, condition), "{condition}");
+        }
+    }
+}
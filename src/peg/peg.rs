@@ -126,22 +126,22 @@ pub mod backend {
         }
 
         fn range(&mut self, r: RangeInclusive<char>) -> bool {
-            if let Some(next) = self.input[self.offset..].chars().next() {
-                if r.contains(&next) {
-                    self.offset += next.len_utf8();
-                    return true;
-                }
+            if let Some(next) = self.input[self.offset..].chars().next()
+                && r.contains(&next)
+            {
+                self.offset += next.len_utf8();
+                return true;
             }
             false
         }
 
         fn range_i(&mut self, r: RangeInclusive<char>) -> bool {
-            if let Some(next) = self.input[self.offset..].chars().next() {
-                if r.contains(&next.to_ascii_lowercase()) || r.contains(&next.to_ascii_uppercase())
-                {
-                    self.offset += next.len_utf8();
-                    return true;
-                }
+            if let Some(next) = self.input[self.offset..].chars().next()
+                && (r.contains(&next.to_ascii_lowercase())
+                    || r.contains(&next.to_ascii_uppercase()))
+            {
+                self.offset += next.len_utf8();
+                return true;
             }
             false
         }
@@ -169,7 +169,7 @@ impl<'a, T: Clone> Iterator for Captures<'a, T> {
         let subtree_slice = &self.raw[self.index..self.index + subtree_len];
         self.index += if self.walk { 1 } else { subtree_len };
         Some(Capture {
-            input: &self.input,
+            input: self.input,
             raw: subtree_slice,
         })
     }
@@ -178,7 +178,7 @@ impl<'a, T: Clone> Iterator for Captures<'a, T> {
 impl<'a, T: Clone> Capture<'a, T> {
     pub fn children(&self) -> Captures<'a, T> {
         Captures {
-            input: &self.input,
+            input: self.input,
             raw: &self.raw[1..],
             index: 0,
             walk: false,
@@ -1,110 +1,794 @@
 use std::{
+    cell::RefCell,
+    fmt::Display,
     fs,
+    ops::Range,
     path::{Path, PathBuf},
+    rc::Rc,
 };
 
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Result as AnyResult, anyhow};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    codes::{Code, INCLUDE_ERROR, MACRO_ERROR, MACRO_SYNTAX},
+    error::{CompileMessage, Context as ErrContext, SpanLabel},
+};
 
 use super::{
-    parse::{Expr, parse},
+    parse::{BinaryOp, Define, Expr, Loop, Macro, parse},
     scan::scan,
+    source_map::{Output, Source},
 };
 
 pub struct Context {
     file_loader: Box<dyn FileLoaderTrait>,
+    /// Where files included by a stat's own code are found
+    working_dir: PathBuf,
+    /// Where to look for included files that aren't next to the file that
+    /// includes them, in order
+    include_paths: Vec<PathBuf>,
+    /// Variables that are set for every stat, e.g., from the command line
+    variables: FxHashMap<String, Value>,
+    /// Every path that was checked for an included file, whether or not it
+    /// existed, since creating or editing any of them could change the output
+    dependencies: RefCell<FxHashSet<PathBuf>>,
 }
 
 trait FileLoaderTrait {
-    fn load(&self, path: &Path) -> Result<String>;
+    fn exists(&self, path: &Path) -> bool;
+    fn load(&self, path: &Path) -> AnyResult<String>;
+    /// Get a name for a file that's the same no matter which path was used to
+    /// reach it, so the same file can be recognized when it's included twice.
+    fn canonicalize(&self, path: &Path) -> PathBuf;
 }
 
-struct FileLoader {
-    working_dir: PathBuf,
-}
+struct FileLoader;
 
 impl FileLoaderTrait for FileLoader {
-    fn load(&self, path: &Path) -> Result<String> {
-        // let mut full_path = self.working_dir.clone();
-        // full_path.extend(path);
-        let full_path = self.working_dir.join(path);
-        fs::read_to_string(full_path).map_err(|e| anyhow!("Couldn't load {:?}: {}", path, e))
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn load(&self, path: &Path) -> AnyResult<String> {
+        fs::read_to_string(path).map_err(|e| anyhow!("Couldn't load {:?}: {}", path, e))
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        fs::canonicalize(path).unwrap_or_else(|_| path.into())
     }
 }
 
 #[cfg(test)]
 struct MockFileLoader {
-    content: String,
+    files: FxHashMap<PathBuf, String>,
 }
 
 #[cfg(test)]
 impl FileLoaderTrait for MockFileLoader {
-    fn load(&self, _path: &Path) -> Result<String> {
-        Ok(self.content.clone())
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&self.canonicalize(path))
+    }
+
+    fn load(&self, path: &Path) -> AnyResult<String> {
+        let content = self.files.get(&self.canonicalize(path));
+        content
+            .cloned()
+            .ok_or_else(|| anyhow!("Couldn't load {:?}", path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        use std::path::Component;
+        let mut result = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    result.pop();
+                }
+                _ => result.push(component),
+            }
+        }
+        result
     }
 }
 
 impl Context {
     pub fn new(working_directory: &Path) -> Self {
         Context {
-            file_loader: Box::new(FileLoader {
-                working_dir: working_directory.into(),
-            }),
+            file_loader: Box::new(FileLoader),
+            working_dir: working_directory.into(),
+            include_paths: vec![],
+            variables: FxHashMap::default(),
+            dependencies: RefCell::default(),
+        }
+    }
+
+    /// Add a directory to search for included files. Directories are searched
+    /// in the order they're added.
+    pub fn add_include_path(&mut self, path: &Path) {
+        self.include_paths.push(path.into());
+    }
+
+    /// Set a variable that every stat's code can use.
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.into(), value);
+    }
+
+    /// List the files that included files came from, or might have come from,
+    /// across every stat evaluated so far.
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let mut result: Vec<_> = self.dependencies.borrow().iter().cloned().collect();
+        result.sort();
+        result
+    }
+
+    /// Evaluate the macros in a stat's code.
+    ///
+    /// Problems are reported to `ctx`, with spans pointing into `input`. If
+    /// there are any errors, this returns `None`.
+    pub fn eval_program(&self, input: &str, ctx: &ErrContext) -> Option<Output> {
+        let (tokens, scan_errors) = scan(input);
+        if !scan_errors.is_empty() {
+            for (message, span) in scan_errors {
+                ctx.with_span(span).error(&MACRO_SYNTAX, &message);
+            }
+            return None;
+        }
+        let exprs = match parse(tokens) {
+            Ok(exprs) => exprs,
+            Err(e) => {
+                ctx.with_span(e.span).error(&MACRO_SYNTAX, &e.message);
+                return None;
+            }
+        };
+
+        let mut evaluator = Evaluator {
+            context: self,
+            macros: FxHashMap::default(),
+            scopes: vec![self.variables.clone()],
+            included: FxHashSet::default(),
+            depth: 0,
+            steps: 0,
+        };
+        let source = Rc::new(Source {
+            path: None,
+            text: input.into(),
+            included_at: None,
+        });
+        match evaluator.eval_exprs(&exprs, &source) {
+            Ok(output) => Some(output),
+            Err(e) => {
+                ctx.report(e.into_message());
+                None
+            }
+        }
+    }
+}
+
+/// How deeply macros can expand inside each other before we assume they're
+/// stuck in infinite recursion.
+const MAX_DEPTH: usize = 64;
+
+/// The most times a loop can run. Anything more than this wouldn't fit on a
+/// board anyway, and is probably a mistake.
+const MAX_ITERATIONS: i64 = 10000;
+
+/// The most loop iterations and macro expansions a stat's code can take, in
+/// total. Nested loops and recursive macros can each stay under their own
+/// limits while multiplying out to something that would never finish.
+const MAX_STEPS: usize = 100_000;
+
+/// The value of a compile-time expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    String(String),
+}
+
+impl Value {
+    /// Parse a value given on the command line: an integer if it looks like
+    /// one, or a string otherwise.
+    pub fn from_arg(arg: &str) -> Value {
+        match arg.parse() {
+            Ok(n) => Value::Integer(n),
+            Err(_) => Value::String(arg.into()),
+        }
+    }
+
+    /// Check whether `%if` should treat this as true: non-zero integers and
+    /// non-empty strings are true.
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Integer(n) => *n != 0,
+            Value::String(s) => !s.is_empty(),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Integer(value as i64)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// A problem found while evaluating macros.
+struct EvalError {
+    code: &'static Code,
+    message: String,
+    source: Rc<Source>,
+    span: Range<usize>,
+    /// Other places worth pointing out, like where a macro was defined
+    labels: Vec<(Rc<Source>, Range<usize>, String)>,
+    notes: Vec<String>,
+}
+
+impl EvalError {
+    fn new(source: &Rc<Source>, span: &Range<usize>, message: &str) -> Self {
+        EvalError {
+            code: &MACRO_ERROR,
+            message: message.into(),
+            source: source.clone(),
+            span: span.clone(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    fn with_code(mut self, code: &'static Code) -> Self {
+        self.code = code;
+        self
+    }
+
+    fn with_label(mut self, source: &Rc<Source>, span: &Range<usize>, message: &str) -> Self {
+        self.labels
+            .push((source.clone(), span.clone(), message.into()));
+        self
+    }
+
+    /// Add a note explaining where the error came from.
+    ///
+    /// Recursive macros would add the same note many times over, so each note
+    /// only gets added once.
+    fn note(mut self, note: &str) -> Self {
+        if !self.notes.iter().any(|x| x == note) {
+            self.notes.push(note.into());
+        }
+        self
+    }
+
+    /// Convert to a message for the stat whose code was being evaluated.
+    fn into_message(self) -> CompileMessage {
+        // Errors in included files also need a span in the stat's code,
+        // for sorting and for tools that only look at the stat
+        let mut message = CompileMessage::error(self.code, &self.message);
+        message.location.span = Some(self.source.root_span(&self.span));
+        message.location.origin = Some(self.source.origin(&self.span));
+        for (source, span, text) in &self.labels {
+            message.labels.push(SpanLabel {
+                span: source.root_span(span),
+                origin: Some(source.origin(span)),
+                message: text.clone(),
+            });
+        }
+        for note in &self.notes {
+            message = message.with_note(note);
+        }
+        message
+    }
+}
+
+type Result<T> = std::result::Result<T, EvalError>;
+
+struct MacroDef {
+    define: Define,
+    source: Rc<Source>,
+}
+
+/// Evaluates a single stat's code. Macros and variables defined in the code
+/// (or in files it includes) are available until the end of that code.
+struct Evaluator<'a> {
+    context: &'a Context,
+    macros: FxHashMap<String, Rc<MacroDef>>,
+    /// Variables, innermost scope last. Each macro expansion gets its own scope.
+    scopes: Vec<FxHashMap<String, Value>>,
+    /// Canonical paths of the files that have been included, for `%include_once`
+    included: FxHashSet<PathBuf>,
+    depth: usize,
+    /// Loop iterations and macro expansions so far, counted against `MAX_STEPS`
+    steps: usize,
+}
+
+impl Evaluator<'_> {
+    /// Evaluate an included file.
+    fn eval_source(&mut self, source: &Rc<Source>) -> Result<Output> {
+        let (tokens, errors) = scan(&source.text);
+        if let Some((message, span)) = errors.first() {
+            return Err(EvalError::new(source, span, message).with_code(&MACRO_SYNTAX));
         }
+        let exprs = parse(tokens)
+            .map_err(|e| EvalError::new(source, &e.span, &e.message).with_code(&MACRO_SYNTAX))?;
+        self.eval_exprs(&exprs, source)
     }
 
-    pub fn eval_program(&self, input: &str) -> Result<String> {
-        let tokens = scan(input).0;
-        let exprs = parse(tokens)?;
-        let mut result: Vec<String> = vec![];
+    fn eval_exprs(&mut self, exprs: &[Expr], source: &Rc<Source>) -> Result<Output> {
+        let mut output = Output::default();
         for expr in exprs {
             match expr {
-                Expr::ZztOop(s) => result.push(s),
-                Expr::Macro(name, args) => match name.as_str() {
-                    "include" => {
-                        if args.len() != 1 {
-                            bail!("wrong number of args for %include");
-                        }
-                        let filename = if let Expr::String(s) = args[0].as_ref() {
-                            s
-                        } else {
-                            bail!("%include filename must be a string")
-                        };
-
-                        let mut content = self.file_loader.load(Path::new(filename))?;
-                        content = content.replace("\r\n", "\n");
-                        if content.ends_with("\n") {
-                            content.pop();
+                Expr::ZztOop(s, span) => output.push(s, source, span),
+                Expr::Interpolation(expr, span) => {
+                    let value = self.eval_value(expr, source)?;
+                    output.push(&value.to_string(), source, span);
+                }
+                Expr::Define(define) => self.define(define, source)?,
+                Expr::If(conditional) => {
+                    let mut body = &conditional.otherwise;
+                    for (condition, branch) in &conditional.branches {
+                        if self.eval_value(condition, source)?.is_truthy() {
+                            body = branch;
+                            break;
                         }
-                        result.push(content)
                     }
-                    _ => bail!("Unknown macro: {:?}", name),
-                },
-                _ => {
-                    bail!("Unexpected expr: {:?}", expr);
+                    output.append(self.eval_exprs(body, source)?);
+                }
+                Expr::Loop(looped) => output.append(self.eval_loop(looped, source)?),
+                Expr::Let(assignment) => {
+                    let value = self.eval_value(&assignment.value, source)?;
+                    let scope = self.scopes.last_mut().unwrap();
+                    scope.insert(assignment.name.clone(), value);
+                }
+                Expr::Macro(invocation) => output.append(self.expand(invocation, source)?),
+                _ => unreachable!("parser only puts statements in blocks, not {:?}", expr),
+            }
+        }
+        Ok(output)
+    }
+
+    fn eval_value(&mut self, expr: &Expr, source: &Rc<Source>) -> Result<Value> {
+        match expr {
+            Expr::Integer(n) => Ok(Value::Integer(*n)),
+            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::Template(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    result.push_str(&self.eval_value(part, source)?.to_string());
+                }
+                Ok(Value::String(result))
+            }
+            Expr::Variable(name, span) => match self.lookup(name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(EvalError::new(
+                    source,
+                    span,
+                    &format!("Unknown variable: {:?}", name),
+                )),
+            },
+            Expr::Defined(name) => Ok(self.lookup(name).is_some().into()),
+            Expr::Not(operand) => Ok((!self.eval_value(operand, source)?.is_truthy()).into()),
+            Expr::Negate(operand, span) => match self.eval_value(operand, source)? {
+                Value::Integer(n) => n
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| EvalError::new(source, span, "integer overflow")),
+                Value::String(_) => Err(EvalError::new(source, span, "can't negate a string")),
+            },
+            // Only evaluate the right side if it matters
+            Expr::Binary(binary) if matches!(binary.op, BinaryOp::And | BinaryOp::Or) => {
+                let left = self.eval_value(&binary.left, source)?.is_truthy();
+                if left == (binary.op == BinaryOp::Or) {
+                    return Ok(left.into());
+                }
+                Ok(self.eval_value(&binary.right, source)?.is_truthy().into())
+            }
+            Expr::Binary(binary) => {
+                let left = self.eval_value(&binary.left, source)?;
+                let right = self.eval_value(&binary.right, source)?;
+                eval_binary(binary.op, left, right)
+                    .map_err(|e| EvalError::new(source, &binary.span, &e))
+            }
+            _ => unreachable!("parser only puts values in expressions, not {:?}", expr),
+        }
+    }
+
+    fn eval_loop(&mut self, looped: &Loop, source: &Rc<Source>) -> Result<Output> {
+        let location = source.describe(looped.span.start);
+        let start = self.eval_value(&looped.start, source)?;
+        let end = self.eval_value(&looped.end, source)?;
+        let (Value::Integer(start), Value::Integer(mut end)) = (start, end) else {
+            let message = match looped.variable {
+                Some(_) => "%for range must be integers",
+                None => "%repeat count must be an integer",
+            };
+            return Err(EvalError::new(source, &looped.span, message));
+        };
+        if looped.inclusive {
+            end = end.saturating_add(1);
+        }
+        let count = end.saturating_sub(start);
+        if count > MAX_ITERATIONS {
+            return Err(EvalError::new(
+                source,
+                &looped.span,
+                &format!("loop would run {count} times, but the limit is {MAX_ITERATIONS}"),
+            ));
+        }
+
+        // Each iteration gets its own scope, with the loop variable in it
+        let mut result = Output::default();
+        for i in start..end {
+            self.step(source, &looped.span)?;
+            let mut scope = FxHashMap::default();
+            if let Some(name) = &looped.variable {
+                scope.insert(name.clone(), Value::Integer(i));
+            }
+            self.scopes.push(scope);
+            let body = self.eval_exprs(&looped.body, source);
+            self.scopes.pop();
+            let note = match &looped.variable {
+                Some(name) => format!("in the loop at {location}, with {name} = {i}"),
+                None => format!("in the loop at {location}, iteration {}", i + 1),
+            };
+            result.append(body.map_err(|e| e.note(&note))?);
+        }
+        Ok(result)
+    }
+
+    /// Count a loop iteration or macro expansion against the stat's budget.
+    fn step(&mut self, source: &Rc<Source>, span: &Range<usize>) -> Result<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!(
+                    "loops and macros ran more than {MAX_STEPS} times in total; are they nested too deeply?"
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn define(&mut self, define: &Define, source: &Rc<Source>) -> Result<()> {
+        if BUILTIN_MACROS.contains(&define.name.as_str()) {
+            return Err(EvalError::new(
+                source,
+                &define.span,
+                &format!("%{} is built in and can't be redefined", define.name),
+            ));
+        }
+        if let Some(existing) = self.macros.get(&define.name) {
+            let previous = &existing.define.span;
+            return Err(EvalError::new(
+                source,
+                &define.span,
+                &format!(
+                    "%{} is already defined at {}",
+                    define.name,
+                    existing.source.describe(previous.start)
+                ),
+            )
+            .with_label(&existing.source, previous, "previous definition here"));
+        }
+        let def = MacroDef {
+            define: define.clone(),
+            source: source.clone(),
+        };
+        self.macros.insert(define.name.clone(), Rc::new(def));
+        Ok(())
+    }
+
+    fn expand(&mut self, invocation: &Macro, source: &Rc<Source>) -> Result<Output> {
+        let Macro { name, args, span } = invocation;
+        if self.depth >= MAX_DEPTH {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!("macros are nested more than {MAX_DEPTH} deep; is %{name} recursive?"),
+            ));
+        }
+        self.step(source, span)?;
+        let args = args
+            .iter()
+            .map(|arg| self.eval_value(arg, source))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.depth += 1;
+        let result = match name.as_str() {
+            "include" | "include_once" => self.include(name, &args, span, source),
+            _ => self.expand_user_macro(name, args, span, source),
+        };
+        self.depth -= 1;
+        result
+    }
+
+    fn include(
+        &mut self,
+        name: &str,
+        args: &[Value],
+        span: &Range<usize>,
+        source: &Rc<Source>,
+    ) -> Result<Output> {
+        let [filename] = args else {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!("wrong number of args for %{name}"),
+            ));
+        };
+        let Value::String(filename) = filename else {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!("%{name} filename must be a string"),
+            ));
+        };
+        let path = self
+            .resolve(filename, source)
+            .map_err(|e| EvalError::new(source, span, &e).with_code(&INCLUDE_ERROR))?;
+
+        let file_loader = self.context.file_loader.as_ref();
+        let canonical = file_loader.canonicalize(&path);
+        let mut chain = vec![path.display().to_string()];
+        let mut parent = Some(source);
+        while let Some(includer) = parent {
+            if let Some(includer_path) = &includer.path {
+                chain.push(includer_path.display().to_string());
+                if file_loader.canonicalize(includer_path) == canonical {
+                    chain.reverse();
+                    return Err(EvalError::new(
+                        source,
+                        span,
+                        &format!("include cycle: {}", chain.join(" -> ")),
+                    )
+                    .with_code(&INCLUDE_ERROR));
                 }
             }
+            parent = includer.included_at.as_ref().map(|(x, _)| x);
+        }
+        if !self.included.insert(canonical) && name == "include_once" {
+            return Ok(Output::default());
+        }
+
+        let mut content = file_loader
+            .load(&path)
+            .map_err(|e| EvalError::new(source, span, &e.to_string()).with_code(&INCLUDE_ERROR))?;
+        content = content.replace("\r\n", "\n");
+        if content.ends_with("\n") {
+            content.pop();
+        }
+
+        // Included files can use macros too, and define them for the includer
+        let included = Rc::new(Source {
+            path: Some(path),
+            text: content.into(),
+            included_at: Some((source.clone(), span.clone())),
+        });
+        self.eval_source(&included)
+    }
+
+    /// Find a file to include. Paths are relative to the file doing the
+    /// including (or the world's directory, for a stat's own code), and
+    /// failing that, to each of the include paths.
+    fn resolve(&self, filename: &str, source: &Source) -> std::result::Result<PathBuf, String> {
+        let context = self.context;
+        let base_dir = match &source.path {
+            Some(path) => path.parent().unwrap_or(Path::new("")),
+            None => &context.working_dir,
+        };
+        let dirs: Vec<&Path> = [base_dir]
+            .into_iter()
+            .chain(context.include_paths.iter().map(|x| x.as_path()))
+            .collect();
+        for dir in &dirs {
+            let path = dir.join(filename);
+            context.dependencies.borrow_mut().insert(path.clone());
+            if context.file_loader.exists(&path) {
+                return Ok(path);
+            }
+        }
+        let searched: Vec<_> = dirs
+            .iter()
+            .map(|dir| match dir.as_os_str().is_empty() {
+                true => ".".into(),
+                false => dir.display().to_string(),
+            })
+            .collect();
+        Err(format!(
+            "Couldn't find {:?} (searched {})",
+            filename,
+            searched.join(", ")
+        ))
+    }
+
+    fn expand_user_macro(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        span: &Range<usize>,
+        source: &Rc<Source>,
+    ) -> Result<Output> {
+        let Some(def) = self.macros.get(name).cloned() else {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!("Unknown macro: {:?}", name),
+            ));
+        };
+        let params = &def.define.params;
+        let location = source.describe(span.start);
+        let definition = def.source.describe(def.define.span.start);
+        if args.len() != params.len() {
+            return Err(EvalError::new(
+                source,
+                span,
+                &format!(
+                    "%{name} takes {} args but got {} (defined at {definition})",
+                    params.len(),
+                    args.len()
+                ),
+            )
+            .with_label(&def.source, &def.define.span, "defined here"));
+        }
+
+        // Expand the body, with each param available as a variable
+        let scope = params.iter().cloned().zip(args).collect();
+        self.scopes.push(scope);
+        let result = self.eval_exprs(&def.define.body, &def.source);
+        self.scopes.pop();
+        result.map_err(|e| {
+            e.note(&format!(
+                "in %{name} at {location} (defined at {definition})"
+            ))
+        })
+    }
+}
+
+/// Macros that can't be redefined by the user.
+const BUILTIN_MACROS: [&str; 11] = [
+    "define",
+    "elif",
+    "else",
+    "end",
+    "endif",
+    "for",
+    "if",
+    "include",
+    "include_once",
+    "let",
+    "repeat",
+];
+
+fn eval_binary(op: BinaryOp, left: Value, right: Value) -> std::result::Result<Value, String> {
+    use BinaryOp::*;
+    match op {
+        Add | Subtract | Multiply | Divide => eval_arithmetic(op, left, right),
+        Equal => Ok((left == right).into()),
+        NotEqual => Ok((left != right).into()),
+        Less | LessEqual | Greater | GreaterEqual => {
+            let ordering = match (&left, &right) {
+                (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+                (Value::String(a), Value::String(b)) => a.cmp(b),
+                _ => return Err("can't compare a string with an integer".into()),
+            };
+            let result = match op {
+                Less => ordering.is_lt(),
+                LessEqual => ordering.is_le(),
+                Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            };
+            Ok(result.into())
+        }
+        And | Or => unreachable!("logical operators are evaluated lazily"),
+    }
+}
+
+fn eval_arithmetic(op: BinaryOp, left: Value, right: Value) -> std::result::Result<Value, String> {
+    use Value::*;
+    let (a, b) = match (op, left, right) {
+        // Adding anything to a string concatenates them
+        (BinaryOp::Add, String(a), b) => return Ok(String(format!("{a}{b}"))),
+        (BinaryOp::Add, a, String(b)) => return Ok(String(format!("{a}{b}"))),
+        (_, Integer(a), Integer(b)) => (a, b),
+        _ => return Err(format!("can only {} integers", op.verb())),
+    };
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Subtract => a.checked_sub(b),
+        BinaryOp::Multiply => a.checked_mul(b),
+        BinaryOp::Divide if b == 0 => return Err("division by zero".into()),
+        BinaryOp::Divide => a.checked_div(b),
+        _ => unreachable!("not an arithmetic operator"),
+    };
+    result.map(Integer).ok_or_else(|| "integer overflow".into())
+}
+
+impl BinaryOp {
+    fn verb(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "subtract",
+            BinaryOp::Multiply => "multiply",
+            BinaryOp::Divide => "divide",
+            _ => unreachable!("not an arithmetic operator"),
         }
-        Ok(result.join(""))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use insta::assert_debug_snapshot;
+    use insta::{assert_debug_snapshot, assert_snapshot};
+
+    use crate::world::{Board, Format, World};
 
     use super::*;
 
-    fn make_context(data: String) -> Context {
+    fn make_context(files: &[(&str, &str)]) -> Context {
+        let files = files
+            .iter()
+            .map(|&(path, content)| (path.into(), content.into()))
+            .collect();
         Context {
-            file_loader: Box::new(MockFileLoader { content: data }),
+            file_loader: Box::new(MockFileLoader { files }),
+            working_dir: PathBuf::new(),
+            include_paths: vec![],
+            variables: FxHashMap::default(),
+            dependencies: RefCell::default(),
+        }
+    }
+
+    /// Evaluate a program, returning either its output or its errors. Each
+    /// error starts with the line it points to, which might be in a file.
+    fn run(context: &Context, program: &str) -> std::result::Result<String, String> {
+        let ctx = ErrContext::new();
+        match context.eval_program(program, &ctx) {
+            Some(output) => Ok(output.text),
+            None => {
+                let errors: Vec<_> = ctx
+                    .into_messages()
+                    .iter()
+                    .map(|message| {
+                        let location = &message.location;
+                        let (text, span, path) = match &location.origin {
+                            Some(x) => (&*x.text, x.span.clone(), x.file_path.as_deref()),
+                            None => (program, location.span.clone().unwrap(), None),
+                        };
+                        let line = text[..span.start].matches('\n').count() + 1;
+                        let mut result = match path {
+                            Some(path) => format!("{path} line {line}: {}", message.message),
+                            None => format!("line {line}: {}", message.message),
+                        };
+                        for note in &message.notes {
+                            result.push_str(&format!("\n  {}", note.message));
+                        }
+                        for include in location.origin.iter().flat_map(|x| &x.included_from) {
+                            result.push_str(&format!("\n  included from {include}"));
+                        }
+                        result
+                    })
+                    .collect();
+                Err(errors.join("\n"))
+            }
         }
     }
 
     #[test]
     fn include() {
-        let program = format!("foo\n%include \"bb.txt\"\nquux");
+        let program = "foo\n%include \"bb.txt\"\nquux".to_string();
         let file = "bar\nbaz\n";
-        assert_debug_snapshot!(make_context(file.into()).eval_program(&program), @r###"
+        assert_debug_snapshot!(run(&make_context(&[("bb.txt", file)]), &program), @r###"
         Ok(
             "foo\nbar\nbaz\nquux",
         )
@@ -113,22 +797,395 @@ mod tests {
 
     #[test]
     fn include_windows() {
-        let program = format!("%include \"foo.txt\"");
+        let program = "%include \"foo.txt\"".to_string();
         let file = "foo\r\nbar";
-        assert_debug_snapshot!(make_context(file.into()).eval_program(&program), @r###"
+        assert_debug_snapshot!(run(&make_context(&[("foo.txt", file)]), &program), @r###"
         Ok(
             "foo\nbar",
         )
         "###);
     }
 
+    #[test]
+    fn include_paths() {
+        let files = [
+            (
+                "world/lib/guards.txt",
+                "%include \"../shared.txt\"\n%include \"util.txt\"",
+            ),
+            ("world/shared.txt", "shared"),
+            ("std/util.txt", "util"),
+            ("util.txt", "wrong util"),
+        ];
+        let mut context = make_context(&files);
+        context.working_dir = "world".into();
+        context.add_include_path(Path::new("std"));
+        assert_eq!(
+            run(&context, "%include \"lib/guards.txt\"").unwrap(),
+            "shared\nutil"
+        );
+        assert_snapshot!(run(&context, "%include \"missing.txt\"").unwrap_err(), @r#"line 1: Couldn't find "missing.txt" (searched world, std)"#);
+
+        // Files that weren't found are dependencies too, in case they get created
+        let dependencies: Vec<_> = context
+            .dependencies()
+            .into_iter()
+            .map(|x| x.display().to_string())
+            .collect();
+        assert_eq!(
+            dependencies,
+            [
+                "std/missing.txt",
+                "std/util.txt",
+                "world/lib/../shared.txt",
+                "world/lib/guards.txt",
+                "world/lib/util.txt",
+                "world/missing.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn include_cycles() {
+        let files = [
+            ("a.txt", "%include \"lib/b.txt\""),
+            ("lib/b.txt", "%include \"../a.txt\""),
+            ("self.txt", "%include \"./self.txt\""),
+        ];
+        let context = make_context(&files);
+        assert_snapshot!(run(&context, "%include \"a.txt\"").unwrap_err(), @r"
+        lib/b.txt line 1: include cycle: a.txt -> lib/b.txt -> lib/../a.txt
+          included from a.txt line 1
+          included from line 1
+        ");
+        assert_snapshot!(run(&context, "%include \"self.txt\"").unwrap_err(), @r"
+        self.txt line 1: include cycle: self.txt -> ./self.txt
+          included from line 1
+        ");
+
+        // Including the same file twice is fine, as long as it's not inside itself
+        let context = make_context(&[("x.txt", "x")]);
+        assert_eq!(
+            run(&context, "%include \"x.txt\"\n%include \"x.txt\"").unwrap(),
+            "x\nx"
+        );
+    }
+
+    #[test]
+    fn include_once() {
+        let files = [
+            ("lib.txt", "%define shout(x)\n${x}!\n%end"),
+            ("a.txt", "%include_once \"lib.txt\"\n%shout \"a\""),
+            ("b.txt", "%include_once \"lib.txt\"\n%shout \"b\""),
+        ];
+        let context = make_context(&files);
+        let program = "%include \"a.txt\"\n%include \"b.txt\"\n%include_once \"./a.txt\"";
+        assert_eq!(run(&context, program).unwrap(), "\na!\n\nb!\n");
+        // Without the guard, the macro would get defined twice
+        let program = "%include \"lib.txt\"\n%include \"lib.txt\"";
+        assert_snapshot!(run(&context, program).unwrap_err(), @r"
+        lib.txt line 1: %shout is already defined at lib.txt line 1
+          included from line 2
+        ");
+    }
+
     #[test]
     fn unknown_macro() {
-        make_context("".into())
-            .eval_program("%foo")
-            .expect_err("Expected error: unknown macro");
-        assert_debug_snapshot!(make_context("".into())
-            .eval_program("%foo")
-            .expect_err("Expected error: unknown macro"), @r###""Unknown macro: \"foo\"""###);
+        run(&make_context(&[]), "%foo").expect_err("Expected error: unknown macro");
+        assert_debug_snapshot!(run(&make_context(&[]), "%foo")
+            .expect_err("Expected error: unknown macro"), @r###""line 1: Unknown macro: \"foo\"""###);
+    }
+
+    fn eval(program: &str) -> String {
+        run(&make_context(&[]), program).unwrap()
+    }
+
+    fn eval_err(program: &str) -> String {
+        run(&make_context(&[]), program).expect_err("Expected error")
+    }
+
+    #[test]
+    fn define() {
+        let program = "%define greet(who, what)\nHi, ${who}!\n#give ${what} 1\n%end\n@Greeter\n%greet \"world\" \"gems\"\n#end";
+        assert_eq!(eval(program), "@Greeter\nHi, world!\n#give gems 1\n#end");
+    }
+
+    #[test]
+    fn define_without_params() {
+        assert_eq!(eval("%define stop\n#end\n%end\n%stop\n%stop"), "#end\n#end");
+    }
+
+    #[test]
+    fn define_nested_macros() {
+        let program = "%define inner(x)\n[${x}]\n%end\n%define outer(x)\n%inner \"<${x}>\"\n%end\n%outer \"hi\"";
+        assert_eq!(eval(program), "[<hi>]");
+    }
+
+    #[test]
+    fn define_in_include() {
+        let file = "%define shout(x)\n${x}!\n%end\n";
+        let program = "%include \"lib.txt\"\n%shout \"hey\"";
+        assert_eq!(
+            run(&make_context(&[("lib.txt", file)]), program).unwrap(),
+            "\nhey!"
+        );
+    }
+
+    #[test]
+    fn define_errors() {
+        assert_snapshot!(eval_err("%define foo(a)\n%end\n\n%foo"), @"line 4: %foo takes 1 args but got 0 (defined at line 1)");
+        assert_snapshot!(eval_err("%define foo\n%end\n%define foo\n%end"), @"line 3: %foo is already defined at line 1");
+        assert_snapshot!(eval_err("%define include\n%end"), @"line 1: %include is built in and can't be redefined");
+        assert_snapshot!(eval_err("%define foo\n%bar\n%end\n%foo"), @r#"
+        line 2: Unknown macro: "bar"
+          in %foo at line 4 (defined at line 1)
+        "#);
+        assert_snapshot!(eval_err("%define foo\n%end\n%end"), @"line 3: %end without a matching %define, %for, or %repeat");
+    }
+
+    #[test]
+    fn define_recursion() {
+        assert_snapshot!(eval_err("%define foo\nfoo\n%foo\n%end\n%foo"), @r"
+        line 3: macros are nested more than 64 deep; is %foo recursive?
+          in %foo at line 3 (defined at line 1)
+          in %foo at line 5 (defined at line 1)
+        ");
+    }
+
+    #[test]
+    fn variables() {
+        let program = "%let wall = 178\n%let name = \"Wall\"\n@${name}\n#char ${wall}";
+        assert_eq!(eval(program), "@Wall\n#char 178");
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            eval("%let x = 7\n${x * 2 - -3} ${x / 2} ${(1 + x) * 2}"),
+            "17 3 16"
+        );
+        assert_eq!(
+            eval("%let x = \"a\" + 1 + 2\n${x} ${1 + 2 + \"b\"}"),
+            "a12 3b"
+        );
+        assert_eq!(
+            eval("%let n = 3\n%let s = \"frame ${n + 1}\"\n${s}"),
+            "frame 4"
+        );
+    }
+
+    #[test]
+    fn variable_scopes() {
+        let program =
+            "%let x = 1\n%define show(x)\n%let y = x\n${x}${y}\n%end\n%show 2\n%show \"z\"\n${x}";
+        assert_eq!(eval(program), "22\nzz\n1");
+        assert_snapshot!(eval_err("%define set\n%let y = 1\n%end\n%set\n${y}"), @r#"line 5: Unknown variable: "y""#);
+    }
+
+    #[test]
+    fn expression_errors() {
+        assert_snapshot!(eval_err("\n${x}"), @r#"line 2: Unknown variable: "x""#);
+        assert_snapshot!(eval_err("${1 / (2 - 2)}"), @"line 1: division by zero");
+        assert_snapshot!(eval_err("${\"a\" * 2}"), @"line 1: can only multiply integers");
+        assert_snapshot!(eval_err("${-\"a\"}"), @"line 1: can't negate a string");
+        assert_snapshot!(eval_err("${9223372036854775807 + 1}"), @"line 1: integer overflow");
+        assert_snapshot!(eval_err("%include 5"), @"line 1: %include filename must be a string");
+    }
+
+    #[test]
+    fn conditionals() {
+        let program = "%if mode == 1\none\n%elif mode == 2\ntwo\n%else\nother\n%endif\n#end";
+        for (mode, expected) in [(1, "one\n#end"), (2, "two\n#end"), (3, "other\n#end")] {
+            let mut context = make_context(&[]);
+            context.set_variable("mode", Value::Integer(mode));
+            assert_eq!(run(&context, program).unwrap(), expected);
+        }
+        assert_eq!(eval("%if 0\nfoo\n%endif\nbar"), "bar");
+        assert_eq!(
+            eval("%let x = 1\n%if x\n%if x > 0\nfoo\n%endif\n%endif"),
+            "foo\n"
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(
+            eval("${1 < 2}${2 <= 1}${\"a\" < \"b\"}${\"1\" == 1}${3 != 4}"),
+            "10101"
+        );
+        assert_eq!(
+            eval("${!0}${!\"\"}${!\"x\"}${1 && 2 == 2}${0 || \"\"}"),
+            "11010"
+        );
+        assert_eq!(eval("%let x = 1\n${defined(x)}${defined(y)}"), "10");
+        assert_snapshot!(eval_err("${1 < \"2\"}"), @"line 1: can't compare a string with an integer");
+    }
+
+    #[test]
+    fn conditionals_skip_untaken_branches() {
+        // Skipped code isn't evaluated, so it can use undefined things
+        let program = "%if defined(debug) && debug\n%cheat missing\n%endif\n${0 && missing}";
+        assert_eq!(eval(program), "0");
+        assert_snapshot!(eval_err("\n%if 1\n%cheat\n%endif"), @r#"line 3: Unknown macro: "cheat""#);
+    }
+
+    #[test]
+    fn global_variables() {
+        let mut context = make_context(&[]);
+        context.set_variable("debug", Value::from_arg("1"));
+        context.set_variable("name", Value::from_arg("Hero"));
+        let program = "%if debug\n@${name}\n%endif\n%let debug = 0\n${debug}";
+        assert_eq!(run(&context, program).unwrap(), "@Hero\n0");
+        // Each stat starts over with the original values
+        assert_eq!(run(&context, "${debug}").unwrap(), "1");
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            eval("%repeat 3\n#cycle 1\n%end\n#end"),
+            "#cycle 1\n#cycle 1\n#cycle 1\n#end"
+        );
+        assert_eq!(
+            eval("%for i in 1..=3\n#char ${i}\n%end\n#end"),
+            "#char 1\n#char 2\n#char 3\n#end"
+        );
+        assert_eq!(
+            eval("%for i in 5..1\nfoo\n%end\n%repeat 0\nbar\n%end\n#end"),
+            "#end"
+        );
+        let program = "%let n = 2\n%for y in 0..n\n%for x in 0..n\n${x}${y}\n%end\n%end";
+        assert_eq!(eval(program), "00\n10\n01\n11\n");
+    }
+
+    #[test]
+    fn loop_scopes() {
+        let program = "%let i = \"outer\"\n%for i in 0..2\n%let j = i * 10\n${j}\n%end\n${i}";
+        assert_eq!(eval(program), "0\n10\nouter");
+        assert_snapshot!(eval_err("%repeat 1\n%let j = 1\n%end\n${j}"), @r#"line 4: Unknown variable: "j""#);
+    }
+
+    #[test]
+    fn loops_with_anonymous_labels() {
+        let program = "%for i in 1..=2\n#if blocked n @f\n#put n ${i}\n:@\n%end\n#end";
+        assert_eq!(
+            eval(program),
+            "#if blocked n @f\n#put n 1\n:@\n#if blocked n @f\n#put n 2\n:@\n#end"
+        );
+    }
+
+    #[test]
+    fn loop_errors() {
+        assert_snapshot!(eval_err("%repeat \"3\"\n%end"), @"line 1: %repeat count must be an integer");
+        assert_snapshot!(eval_err("%for i in 0..\"3\"\n%end"), @"line 1: %for range must be integers");
+        assert_snapshot!(eval_err("%repeat 10001\n%end"), @"line 1: loop would run 10001 times, but the limit is 10000");
+        assert_snapshot!(eval_err("%for i in 0..3\n${10 / (1 - i)}\n%end"), @r"
+        line 2: division by zero
+          in the loop at line 1, with i = 1
+        ");
+        assert_snapshot!(eval_err("%repeat 2\n%foo\n%end"), @r#"
+        line 2: Unknown macro: "foo"
+          in the loop at line 1, iteration 1
+        "#);
+    }
+
+    #[test]
+    fn loop_budget() {
+        // Each loop is under the limit, but together they'd run 100 million times
+        let nested = "%repeat 10000\n%repeat 10000\n#end\n%end\n%end";
+        assert_snapshot!(eval_err(nested), @r###"
+        line 2: loops and macros ran more than 100000 times in total; are they nested too deeply?
+          in the loop at line 1, iteration 10
+        "###);
+        let recursive =
+            "%define foo(n)\n%if n > 0\n%repeat 10\n%foo (n - 1)\n%end\n%endif\n%end\n%foo 6";
+        assert_snapshot!(eval_err(recursive), @r###"
+        line 4: loops and macros ran more than 100000 times in total; are they nested too deeply?
+          in the loop at line 3, iteration 9
+          in %foo at line 4 (defined at line 1)
+          in the loop at line 3, iteration 10
+          in the loop at line 3, iteration 5
+          in the loop at line 3, iteration 1
+          in %foo at line 8 (defined at line 1)
+        "###);
+    }
+
+    #[test]
+    fn errors_in_included_files() {
+        let file = "#end\n%bogus";
+        let program = "@Object\n%include \"lib.txt\"";
+        assert_snapshot!(run(&make_context(&[("lib.txt", file)]), program).unwrap_err(), @r###"
+        lib.txt line 2: Unknown macro: "bogus"
+          included from line 2
+        "###);
+
+        let file = "%define broken\n${missing}\n%end";
+        let program = "%include \"lib.txt\"\n\n%broken";
+        assert_snapshot!(run(&make_context(&[("lib.txt", file)]), program).unwrap_err(), @r###"
+        lib.txt line 2: Unknown variable: "missing"
+          in %broken at line 3 (defined at lib.txt line 1)
+          included from line 1
+        "###);
+    }
+
+    #[test]
+    fn scan_errors() {
+        assert_snapshot!(eval_err("%foo \"bar\n#end\n%baz @ &"), @r###"
+        line 1: string not terminated
+        line 3: Unexpected character '@'
+        line 3: Unexpected character '&'
+        "###);
+    }
+
+    #[test]
+    fn rich_diagnostics() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        board.stats[0].code = "@Guard\n#end\n:touch\n#char ${wall + 1}".into();
+        let mut world = World::default();
+        world.boards.push(board);
+
+        let base_ctx = ErrContext::new();
+        let file_ctx = base_ctx.with_file_path("test.zzt");
+        let board_ctx = file_ctx.with_board(0);
+        let code = &world.boards[0].stats[0].code;
+        let result = make_context(&[]).eval_program(code, &board_ctx.with_stat(0));
+        assert!(result.is_none());
+        let messages: Vec<_> = base_ctx
+            .into_messages()
+            .iter()
+            .map(|x| x.rich_format(&world, false))
+            .collect();
+        assert_snapshot!(messages.join("\n\n"), @r###"
+        error[E0002]: Unknown variable: "wall"
+         => test.zzt -> Title screen -> @Guard (1,1) -> line 4:9
+           |
+         1 | @Guard
+         2 | #end
+         3 | :touch
+         4 | #char ${wall + 1}
+           |         ^^^^
+        "###);
+    }
+
+    #[test]
+    fn rich_diagnostics_with_labels() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        board.stats[0].code = "%define greet\nHi!\n%end\n%include \"lib.txt\"".into();
+        let mut world = World::default();
+        world.boards.push(board);
+
+        let base_ctx = ErrContext::new();
+        let ctx = base_ctx.with_board(0);
+        let code = &world.boards[0].stats[0].code;
+        let context = make_context(&[("lib.txt", "'Greetings\n%define greet\nHello!\n%end")]);
+        assert!(context.eval_program(code, &ctx.with_stat(0)).is_none());
+        let messages: Vec<_> = base_ctx
+            .into_messages()
+            .iter()
+            .map(|x| x.rich_format(&world, false))
+            .collect();
+        assert_snapshot!(messages.join("\n\n"));
     }
 }
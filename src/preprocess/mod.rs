@@ -1,3 +1,4 @@
 pub mod eval;
 pub mod parse;
 pub mod scan;
+pub mod source_map;
@@ -1,8 +1,8 @@
-use anyhow::{Result, anyhow};
+use std::{fmt::Display, ops::Range};
 
 use super::scan::Token;
 
-pub fn parse(input: Vec<Token>) -> Result<Vec<Expr>> {
+pub fn parse(input: Vec<(Token, Range<usize>)>) -> Result<Vec<Expr>> {
     // TODO: Should this take ownership of the tokens?
     let mut parser = Parser::new(input);
     parser.parse()
@@ -10,18 +10,137 @@ pub fn parse(input: Vec<Token>) -> Result<Vec<Expr>> {
 
 struct Parser {
     pos: usize,
-    tokens: Vec<Token>,
+    tokens: Vec<(Token, Range<usize>)>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Expr {
-    ZztOop(String),
-    Macro(String, Vec<Box<Expr>>),
+    ZztOop(String, Range<usize>),
+    /// An inline expression in ZZT-OOP code, like `${foo}`
+    Interpolation(Box<Expr>, Range<usize>),
+    Macro(Macro),
+    Define(Define),
+    If(Box<If>),
+    Loop(Box<Loop>),
+    Let(Box<Let>),
     String(String),
+    /// A string with inline expressions, like `"foo${bar}"`
+    Template(Vec<Expr>),
+    Integer(i64),
+    Variable(String, Range<usize>),
+    /// A check for whether a variable is set, like `defined(foo)`
+    Defined(String),
+    Binary(Box<Binary>),
+    Negate(Box<Expr>, Range<usize>),
+    Not(Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+/// A binary operation, like `a + 1`.
+#[derive(Clone, Debug)]
+pub struct Binary {
+    pub op: BinaryOp,
+    pub left: Expr,
+    pub right: Expr,
+    /// Span of the operator
+    pub span: Range<usize>,
+}
+
+/// Conditional code: `%if`, any number of `%elif`s, an optional `%else`, and
+/// `%endif`.
+#[derive(Clone, Debug)]
+pub struct If {
+    /// Each condition, and the code to use if it's the first one that's true
+    pub branches: Vec<(Expr, Vec<Expr>)>,
+    /// The code to use if none of the conditions are true
+    pub otherwise: Vec<Expr>,
+}
+
+/// A loop: a `%repeat count` or `%for name in start..end` line, a body, and
+/// `%end`.
+#[derive(Clone, Debug)]
+pub struct Loop {
+    /// The loop variable. `%repeat` loops don't have one.
+    pub variable: Option<String>,
+    pub start: Expr,
+    pub end: Expr,
+    /// Whether the range includes `end`, as in `start..=end`
+    pub inclusive: bool,
+    pub body: Vec<Expr>,
+    /// Span of the `%repeat` or `%for` line
+    pub span: Range<usize>,
+}
+
+/// A variable assignment, like `%let foo = 1`.
+#[derive(Clone, Debug)]
+pub struct Let {
+    pub name: String,
+    pub value: Expr,
+}
+
+/// A macro invocation, like `%include "foo.txt"`.
+#[derive(Clone, Debug)]
+pub struct Macro {
+    pub name: String,
+    pub args: Vec<Expr>,
+    pub span: Range<usize>,
+}
+
+/// A user-defined macro: a `%define name(params)` line, a body, and `%end`.
+#[derive(Clone, Debug)]
+pub struct Define {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Expr>,
+    /// Span of the `%define` line
+    pub span: Range<usize>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+/// Macros that end a block, rather than starting a new expression.
+const CLOSERS: [&str; 4] = ["end", "elif", "else", "endif"];
+
+/// The line that started a block, like `%define foo`.
+struct Opener {
+    /// How to refer to the opening line in errors
+    description: String,
+    span: Range<usize>,
+    /// Which of the `CLOSERS` can end this block
+    closers: &'static [&'static str],
 }
 
 impl Parser {
-    fn new(input: Vec<Token>) -> Self {
+    fn new(input: Vec<(Token, Range<usize>)>) -> Self {
         Self {
             pos: 0,
             tokens: input,
@@ -29,68 +148,507 @@ impl Parser {
     }
 
     fn parse(&mut self) -> Result<Vec<Expr>> {
+        self.parse_block(None)
+    }
+
+    /// Parse expressions until the end of input, or until a line that closes
+    /// the current block. The closing line is left for the caller to consume.
+    fn parse_block(&mut self, opener: Option<&Opener>) -> Result<Vec<Expr>> {
         let mut result = vec![];
         while let Some(token) = self.peek() {
             match token {
-                Token::Newline | Token::RawText(_) => result.push(self.parse_zzt_oop()),
-                Token::Percent => result.push(self.parse_macro()?),
-                _ => return Err(anyhow!("Unexpected token {:?}", token)),
+                Token::Newline | Token::RawText(_) | Token::InterpolationStart => {
+                    result.extend(self.parse_zzt_oop()?)
+                }
+                Token::Percent => match self.peek_macro_name() {
+                    Some(name) if CLOSERS.contains(&name) => {
+                        if opener.is_some_and(|x| x.closers.contains(&name)) {
+                            return Ok(result);
+                        }
+                        let expected = if name == "end" {
+                            "%define, %for, or %repeat"
+                        } else {
+                            "%if"
+                        };
+                        return Err(self.error(&format!("%{name} without a matching {expected}")));
+                    }
+                    Some("define") => result.push(self.parse_define()?),
+                    Some("if") => result.push(self.parse_if()?),
+                    Some("for" | "repeat") => result.push(self.parse_loop()?),
+                    Some("let") => result.push(self.parse_let()?),
+                    _ => result.push(self.parse_macro()?),
+                },
+                _ => return Err(self.error(&format!("Unexpected token {:?}", token))),
             }
         }
-        Ok(result)
+        match opener {
+            Some(opener) => Err(ParseError {
+                message: format!(
+                    "{} is missing its %{}",
+                    opener.description,
+                    opener.closers.last().unwrap()
+                ),
+                span: opener.span.clone(),
+            }),
+            None => Ok(result),
+        }
+    }
+
+    /// Consume the `%` and name at the start of a block's closing line, and
+    /// return the name.
+    fn consume_closer(&mut self) -> String {
+        let name = self.peek_macro_name().unwrap().to_string();
+        self.advance(); // consume %
+        self.advance(); // consume name
+        name
     }
 
-    fn parse_zzt_oop(&mut self) -> Expr {
-        let mut parts: Vec<String> = vec![];
+    /// Parse lines of ZZT-OOP code, along with any inline expressions.
+    ///
+    /// Text gets merged into as few `ZztOop`s as possible, except for text with
+    /// escape sequences in it, which gets a `ZztOop` of its own. That way, the
+    /// others are exactly as long as their spans, which makes it easy to map
+    /// them back to the source.
+    fn parse_zzt_oop(&mut self) -> Result<Vec<Expr>> {
+        fn flush(result: &mut Vec<Expr>, text: &mut String, span: &Range<usize>) {
+            if !text.is_empty() {
+                result.push(Expr::ZztOop(std::mem::take(text), span.clone()));
+            }
+        }
+
+        let mut result = vec![];
+        let mut text = String::new();
+        let mut span = 0..0;
         while let Some(token) = self.peek() {
-            match token {
-                Token::RawText(s) => {
-                    parts.push(s.into());
-                    self.advance();
-                }
-                Token::Newline => {
-                    parts.push("\n".into());
-                    self.advance();
+            let token_span = self.span();
+            let token_text = match token {
+                Token::RawText(s) => s.clone(),
+                Token::Newline => "\n".into(),
+                Token::InterpolationStart => {
+                    flush(&mut result, &mut text, &span);
+                    let expr = self.parse_interpolation()?;
+                    let span = token_span.start..self.prev_end();
+                    result.push(Expr::Interpolation(Box::new(expr), span));
+                    continue;
                 }
                 _ => break,
+            };
+            self.advance();
+            if token_text.len() != token_span.len() {
+                flush(&mut result, &mut text, &span);
+                result.push(Expr::ZztOop(token_text, token_span));
+            } else if text.is_empty() {
+                text = token_text;
+                span = token_span;
+            } else {
+                text.push_str(&token_text);
+                span.end = token_span.end;
             }
         }
-        Expr::ZztOop(parts.concat())
+        flush(&mut result, &mut text, &span);
+        Ok(result)
     }
 
-    fn parse_macro(&mut self) -> anyhow::Result<Expr> {
+    fn parse_macro(&mut self) -> Result<Expr> {
+        let start = self.span().start;
         self.advance().unwrap(); // consume %
 
-        let ident = self.advance();
-        let name = match ident {
-            Some(Token::Identifier(name)) => name.into(),
-            _ => return Err(anyhow!("Expected identifier")),
-        };
-
+        let name = self.expect_identifier()?;
         let mut args = vec![];
         while let Some(token) = self.peek() {
             match token {
-                Token::String(s) => {
-                    let val = s.into();
-                    self.advance();
-                    args.push(Box::new(Expr::String(val)));
-                }
                 Token::Newline => {
                     //self.advance();
                     break;
                 }
-                _ => return Err(anyhow!("Unexpected token {:?}", token)),
+                _ => args.push(self.parse_primary()?),
+            }
+        }
+        Ok(Expr::Macro(Macro {
+            name,
+            args,
+            span: start..self.prev_end(),
+        }))
+    }
+
+    /// Parse a variable assignment: `%let name = expr`
+    fn parse_let(&mut self) -> Result<Expr> {
+        self.advance(); // consume %
+        self.advance(); // consume "let"
+
+        let name = self.expect_identifier()?;
+        self.expect(Token::Equals, "Expected `=`")?;
+        let value = self.parse_expression()?;
+        self.expect_end_of_line()?;
+        Ok(Expr::Let(Box::new(Let { name, value })))
+    }
+
+    /// Parse conditional code:
+    ///
+    /// ```text
+    /// %if condition
+    /// ...
+    /// %elif other_condition
+    /// ...
+    /// %else
+    /// ...
+    /// %endif
+    /// ```
+    ///
+    /// Like `%define`, the lines with `%` don't produce any output.
+    fn parse_if(&mut self) -> Result<Expr> {
+        let start = self.span().start;
+        self.advance(); // consume %
+        self.advance(); // consume "if"
+        let opener = Opener {
+            description: "%if".into(),
+            span: start..self.prev_end(),
+            closers: &["elif", "else", "endif"],
+        };
+
+        let mut branches = vec![];
+        let closer = loop {
+            let condition = self.parse_expression()?;
+            self.expect_end_of_line()?;
+            let body = self.parse_block(Some(&opener))?;
+            branches.push((condition, body));
+            match self.consume_closer().as_str() {
+                "elif" => continue,
+                closer => break closer.to_string(),
+            }
+        };
+
+        let mut otherwise = vec![];
+        if closer == "else" {
+            let else_opener = Opener {
+                description: "%else".into(),
+                span: self.tokens[self.pos - 2].1.start..self.prev_end(),
+                closers: &["endif"],
+            };
+            self.expect_end_of_line()?;
+            otherwise = self.parse_block(Some(&else_opener))?;
+            self.consume_closer();
+        }
+        self.expect_end_of_line()?;
+        Ok(Expr::If(Box::new(If {
+            branches,
+            otherwise,
+        })))
+    }
+
+    /// Parse a loop, either
+    ///
+    /// ```text
+    /// %repeat count
+    /// ...
+    /// %end
+    /// ```
+    ///
+    /// or
+    ///
+    /// ```text
+    /// %for name in start..end
+    /// ...
+    /// %end
+    /// ```
+    ///
+    /// Like `%if`, the lines with `%` don't produce any output.
+    fn parse_loop(&mut self) -> Result<Expr> {
+        let start_offset = self.span().start;
+        self.advance(); // consume %
+        let keyword = self.expect_identifier()?;
+
+        let (variable, start, end, inclusive) = if keyword == "repeat" {
+            let count = self.parse_expression()?;
+            (None, Expr::Integer(0), count, false)
+        } else {
+            let variable = self.expect_identifier()?;
+            if self.peek() != Some(&Token::Identifier("in".into())) {
+                return Err(self.error("Expected `in`"));
+            }
+            self.advance();
+            let start = self.parse_expression()?;
+            let inclusive = match self.peek() {
+                Some(Token::DotDot) => false,
+                Some(Token::DotDotEquals) => true,
+                _ => return Err(self.error("Expected `..` or `..=`")),
+            };
+            self.advance();
+            let end = self.parse_expression()?;
+            (Some(variable), start, end, inclusive)
+        };
+        let span = start_offset..self.prev_end();
+        let opener = Opener {
+            description: format!("%{keyword}"),
+            span: span.clone(),
+            closers: &["end"],
+        };
+        self.expect_end_of_line()?;
+
+        let body = self.parse_block(Some(&opener))?;
+        self.consume_closer();
+        self.expect_end_of_line()?;
+        Ok(Expr::Loop(Box::new(Loop {
+            variable,
+            start,
+            end,
+            inclusive,
+            body,
+            span,
+        })))
+    }
+
+    /// Parse an expression like `foo + 1`.
+    fn parse_expression(&mut self) -> Result<Expr> {
+        self.parse_binary(0)
+    }
+
+    /// Parse binary operators, starting at a given level of precedence.
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        const LEVELS: [&[(Token, BinaryOp)]; 5] = [
+            &[(Token::Or, BinaryOp::Or)],
+            &[(Token::And, BinaryOp::And)],
+            &[
+                (Token::DoubleEquals, BinaryOp::Equal),
+                (Token::NotEquals, BinaryOp::NotEqual),
+                (Token::Less, BinaryOp::Less),
+                (Token::LessEquals, BinaryOp::LessEqual),
+                (Token::Greater, BinaryOp::Greater),
+                (Token::GreaterEquals, BinaryOp::GreaterEqual),
+            ],
+            &[
+                (Token::Plus, BinaryOp::Add),
+                (Token::Minus, BinaryOp::Subtract),
+            ],
+            &[
+                (Token::Star, BinaryOp::Multiply),
+                (Token::Slash, BinaryOp::Divide),
+            ],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.parse_unary();
+        };
+        let mut left = self.parse_binary(level + 1)?;
+        while let Some((_, op)) = ops.iter().find(|(token, _)| self.peek() == Some(token)) {
+            let span = self.span();
+            self.advance();
+            let right = self.parse_binary(level + 1)?;
+            left = Expr::Binary(Box::new(Binary {
+                op: *op,
+                left,
+                right,
+                span,
+            }));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                let span = self.span();
+                self.advance();
+                let operand = self.parse_unary()?;
+                Ok(Expr::Negate(Box::new(operand), span))
+            }
+            Some(Token::Not) => {
+                self.advance();
+                let operand = self.parse_unary()?;
+                Ok(Expr::Not(Box::new(operand)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    /// Parse a single value: a literal, a variable, or an expression in parentheses.
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let span = self.span();
+        match self.peek() {
+            Some(Token::Integer(n)) => {
+                let n = *n;
+                self.advance();
+                Ok(Expr::Integer(n))
+            }
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                if name == "defined" && self.peek() == Some(&Token::LeftParen) {
+                    self.advance();
+                    let name = self.expect_identifier()?;
+                    self.expect(Token::RightParen, "Expected `)`")?;
+                    return Ok(Expr::Defined(name));
+                }
+                Ok(Expr::Variable(name, span))
+            }
+            Some(Token::String(_)) => self.parse_string(),
+            Some(Token::LeftParen) => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.expect(Token::RightParen, "Expected `)`")?;
+                Ok(expr)
             }
+            Some(token) => Err(self.error(&format!("Unexpected token {:?}", token))),
+            None => Err(self.error("Expected an expression")),
+        }
+    }
+
+    /// Parse a string literal, which might have inline expressions in it.
+    fn parse_string(&mut self) -> Result<Expr> {
+        let mut parts = vec![];
+        loop {
+            let Some(Token::String(s)) = self.advance() else {
+                unreachable!("strings start and end with a String token");
+            };
+            if !s.is_empty() {
+                parts.push(Expr::String(s.clone()));
+            }
+            if self.peek() != Some(&Token::InterpolationStart) {
+                break;
+            }
+            parts.push(self.parse_interpolation()?);
+        }
+        match &parts[..] {
+            [] => Ok(Expr::String(String::new())),
+            [Expr::String(_)] => Ok(parts.pop().unwrap()),
+            _ => Ok(Expr::Template(parts)),
+        }
+    }
+
+    /// Parse an inline expression: `${expr}`
+    fn parse_interpolation(&mut self) -> Result<Expr> {
+        self.advance(); // consume ${
+        let expr = self.parse_expression()?;
+        self.expect(Token::InterpolationEnd, "Expected `}`")?;
+        Ok(expr)
+    }
+
+    /// Parse a macro definition:
+    ///
+    /// ```text
+    /// %define name(param1, param2)
+    /// ...body...
+    /// %end
+    /// ```
+    ///
+    /// The `%define` and `%end` lines don't produce any output, so this also
+    /// consumes the newlines that end them.
+    fn parse_define(&mut self) -> Result<Expr> {
+        let start = self.span().start;
+        self.advance(); // consume %
+        self.advance(); // consume "define"
+
+        let name = self.expect_identifier()?;
+        let mut params = vec![];
+        if let Some(Token::LeftParen) = self.peek() {
+            self.advance();
+            while !matches!(self.peek(), Some(Token::RightParen)) {
+                if !params.is_empty() {
+                    self.expect(Token::Comma, "Expected `,` or `)`")?;
+                }
+                params.push(self.expect_identifier()?);
+            }
+            self.advance(); // consume )
+        }
+        let span = start..self.prev_end();
+        let opener = Opener {
+            description: format!("%define {name}"),
+            span: span.clone(),
+            closers: &["end"],
+        };
+        self.expect_end_of_line()?;
+
+        let mut body = self.parse_block(Some(&opener))?;
+        self.consume_closer();
+        self.expect_end_of_line()?;
+
+        // The newline before `%end` belongs to the `%end` line, not the body
+        if let Some(Expr::ZztOop(s, span)) = body.last_mut() {
+            if s.ends_with('\n') {
+                s.pop();
+                span.end -= 1;
+            }
+            if s.is_empty() {
+                body.pop();
+            }
+        }
+        Ok(Expr::Define(Define {
+            name,
+            params,
+            body,
+            span,
+        }))
+    }
+
+    /// Make sure nothing else is on the current line, then consume its newline.
+    fn expect_end_of_line(&mut self) -> Result<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(Token::Newline) => {
+                self.advance();
+                Ok(())
+            }
+            Some(token) => Err(self.error(&format!("Unexpected token {:?}", token))),
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.into();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error("Expected identifier")),
+        }
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<()> {
+        if self.peek() == Some(&token) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    /// Get the name of the macro starting at the current `%` token, if any.
+    fn peek_macro_name(&self) -> Option<&str> {
+        match self.tokens.get(self.pos + 1) {
+            Some((Token::Identifier(name), _)) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            message: message.into(),
+            span: self.span(),
+        }
+    }
+
+    /// Get the span of the current token. At the end of input, this is an
+    /// empty span after the last token.
+    fn span(&self) -> Range<usize> {
+        match self.tokens.get(self.pos) {
+            Some((_, span)) => span.clone(),
+            None => self.prev_end()..self.prev_end(),
+        }
+    }
+
+    /// Get the offset where the previous token ended.
+    fn prev_end(&self) -> usize {
+        match self.pos.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some((_, span)) => span.end,
+            None => 0,
         }
-        Ok(Expr::Macro(name, args))
     }
 
     fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+        self.tokens.get(self.pos).map(|(token, _)| token)
     }
 
     fn advance(&mut self) -> Option<&Token> {
-        let result = self.tokens.get(self.pos);
+        let result = self.tokens.get(self.pos).map(|(token, _)| token);
         self.pos = (self.pos + 1).min(self.tokens.len());
         result
     }
@@ -109,12 +667,18 @@ mod tests {
         parse(tokens).expect("Failed to parse code string")
     }
 
+    fn parse_err(input: &str) -> ParseError {
+        let tokens = scan(input).0;
+        parse(tokens).expect_err("Expected parse error")
+    }
+
     #[test]
     fn raw_text() {
         assert_debug_snapshot!(parse_str("This is\nsome text."), @r###"
         [
             ZztOop(
                 "This is\nsome text.",
+                0..18,
             ),
         ]
         "###);
@@ -125,8 +689,11 @@ mod tests {
         assert_debug_snapshot!(parse_str("%foo"), @r###"
         [
             Macro(
-                "foo",
-                [],
+                Macro {
+                    name: "foo",
+                    args: [],
+                    span: 0..4,
+                },
             ),
         ]
         "###);
@@ -137,15 +704,18 @@ mod tests {
         assert_debug_snapshot!(parse_str(r#"%foo "bar" "baz""#), @r###"
         [
             Macro(
-                "foo",
-                [
-                    String(
-                        "bar",
-                    ),
-                    String(
-                        "baz",
-                    ),
-                ],
+                Macro {
+                    name: "foo",
+                    args: [
+                        String(
+                            "bar",
+                        ),
+                        String(
+                            "baz",
+                        ),
+                    ],
+                    span: 0..16,
+                },
             ),
         ]
         "###)
@@ -157,15 +727,376 @@ mod tests {
         [
             ZztOop(
                 "Foo.\n",
+                0..5,
             ),
             Macro(
-                "bar",
-                [],
+                Macro {
+                    name: "bar",
+                    args: [],
+                    span: 5..9,
+                },
             ),
             ZztOop(
                 "\nBaz.",
+                9..14,
             ),
         ]
         "###)
     }
+
+    #[test]
+    fn define() {
+        assert_debug_snapshot!(parse_str("Foo.\n%define greet(who, what)\nHi, ${who}!\n%baz\n%end\nBar."), @r###"
+        [
+            ZztOop(
+                "Foo.\n",
+                0..5,
+            ),
+            Define(
+                Define {
+                    name: "greet",
+                    params: [
+                        "who",
+                        "what",
+                    ],
+                    body: [
+                        ZztOop(
+                            "Hi, ",
+                            30..34,
+                        ),
+                        Interpolation(
+                            Variable(
+                                "who",
+                                36..39,
+                            ),
+                            34..40,
+                        ),
+                        ZztOop(
+                            "!\n",
+                            40..42,
+                        ),
+                        Macro(
+                            Macro {
+                                name: "baz",
+                                args: [],
+                                span: 42..46,
+                            },
+                        ),
+                    ],
+                    span: 5..29,
+                },
+            ),
+            ZztOop(
+                "Bar.",
+                52..56,
+            ),
+        ]
+        "###)
+    }
+
+    #[test]
+    fn define_errors() {
+        assert_eq!(
+            parse_err("%define foo\nbar").message,
+            "%define foo is missing its %end"
+        );
+        assert_eq!(parse_err("%define foo\nbar").span, 0..11);
+        assert_eq!(
+            parse_err("foo\n%end").message,
+            "%end without a matching %define, %for, or %repeat"
+        );
+        assert_eq!(
+            parse_err("%define foo(a b)\n%end").message,
+            "Expected `,` or `)`"
+        );
+        assert_eq!(parse_err("%define foo bar\n%end").span, 12..15);
+    }
+
+    #[test]
+    fn let_precedence() {
+        assert_debug_snapshot!(parse_str("%let x = 1 + 2 * -y"), @r###"
+        [
+            Let(
+                Let {
+                    name: "x",
+                    value: Binary(
+                        Binary {
+                            op: Add,
+                            left: Integer(
+                                1,
+                            ),
+                            right: Binary(
+                                Binary {
+                                    op: Multiply,
+                                    left: Integer(
+                                        2,
+                                    ),
+                                    right: Negate(
+                                        Variable(
+                                            "y",
+                                            18..19,
+                                        ),
+                                        17..18,
+                                    ),
+                                    span: 15..16,
+                                },
+                            ),
+                            span: 11..12,
+                        },
+                    ),
+                },
+            ),
+        ]
+        "###);
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_debug_snapshot!(parse_str("#char ${(a - b) - c}\n%foo \"x${y}\" 2"), @r###"
+        [
+            ZztOop(
+                "#char ",
+                0..6,
+            ),
+            Interpolation(
+                Binary(
+                    Binary {
+                        op: Subtract,
+                        left: Binary(
+                            Binary {
+                                op: Subtract,
+                                left: Variable(
+                                    "a",
+                                    9..10,
+                                ),
+                                right: Variable(
+                                    "b",
+                                    13..14,
+                                ),
+                                span: 11..12,
+                            },
+                        ),
+                        right: Variable(
+                            "c",
+                            18..19,
+                        ),
+                        span: 16..17,
+                    },
+                ),
+                6..20,
+            ),
+            ZztOop(
+                "\n",
+                20..21,
+            ),
+            Macro(
+                Macro {
+                    name: "foo",
+                    args: [
+                        Template(
+                            [
+                                String(
+                                    "x",
+                                ),
+                                Variable(
+                                    "y",
+                                    30..31,
+                                ),
+                            ],
+                        ),
+                        Integer(
+                            2,
+                        ),
+                    ],
+                    span: 21..35,
+                },
+            ),
+        ]
+        "###);
+    }
+
+    #[test]
+    fn expression_errors() {
+        assert_eq!(parse_err("%let x 1").message, "Expected `=`");
+        assert_eq!(parse_err("%let x = (1 + 2").message, "Expected `)`");
+        assert_eq!(
+            parse_err("%let x = 1 2").message,
+            "Unexpected token Integer(2)"
+        );
+        assert_eq!(
+            parse_err("${1 +}").message,
+            "Unexpected token InterpolationEnd"
+        );
+        assert_eq!(parse_err("${1 2}").message, "Expected `}`");
+    }
+
+    #[test]
+    fn conditional() {
+        assert_debug_snapshot!(parse_str("a\n%if x\nb\n%elif !defined(y)\nc\n%else\nd\n%endif\ne"), @r###"
+        [
+            ZztOop(
+                "a\n",
+                0..2,
+            ),
+            If(
+                If {
+                    branches: [
+                        (
+                            Variable(
+                                "x",
+                                6..7,
+                            ),
+                            [
+                                ZztOop(
+                                    "b\n",
+                                    8..10,
+                                ),
+                            ],
+                        ),
+                        (
+                            Not(
+                                Defined(
+                                    "y",
+                                ),
+                            ),
+                            [
+                                ZztOop(
+                                    "c\n",
+                                    28..30,
+                                ),
+                            ],
+                        ),
+                    ],
+                    otherwise: [
+                        ZztOop(
+                            "d\n",
+                            36..38,
+                        ),
+                    ],
+                },
+            ),
+            ZztOop(
+                "e",
+                45..46,
+            ),
+        ]
+        "###);
+    }
+
+    #[test]
+    fn conditional_errors() {
+        assert_eq!(parse_err("%if x\nfoo").message, "%if is missing its %endif");
+        assert_eq!(
+            parse_err("%if x\n%else\n").message,
+            "%else is missing its %endif"
+        );
+        assert_eq!(parse_err("%else").message, "%else without a matching %if");
+        assert_eq!(
+            parse_err("%if x\n%end").message,
+            "%end without a matching %define, %for, or %repeat"
+        );
+        assert_eq!(
+            parse_err("%define foo\n%endif\n%end").message,
+            "%endif without a matching %if"
+        );
+        assert_eq!(
+            parse_err("%if x\n%endif y").message,
+            "Unexpected token Identifier(\"y\")"
+        );
+        assert_eq!(parse_err("%if\n%endif").message, "Unexpected token Newline");
+    }
+
+    #[test]
+    fn loops() {
+        assert_debug_snapshot!(parse_str("%repeat n + 1\n#cycle 1\n%end\n%for i in 1..=3\n#char ${i}\n%end\n#end"), @r###"
+        [
+            Loop(
+                Loop {
+                    variable: None,
+                    start: Integer(
+                        0,
+                    ),
+                    end: Binary(
+                        Binary {
+                            op: Add,
+                            left: Variable(
+                                "n",
+                                8..9,
+                            ),
+                            right: Integer(
+                                1,
+                            ),
+                            span: 10..11,
+                        },
+                    ),
+                    inclusive: false,
+                    body: [
+                        ZztOop(
+                            "#cycle 1\n",
+                            14..23,
+                        ),
+                    ],
+                    span: 0..13,
+                },
+            ),
+            Loop(
+                Loop {
+                    variable: Some(
+                        "i",
+                    ),
+                    start: Integer(
+                        1,
+                    ),
+                    end: Integer(
+                        3,
+                    ),
+                    inclusive: true,
+                    body: [
+                        ZztOop(
+                            "#char ",
+                            44..50,
+                        ),
+                        Interpolation(
+                            Variable(
+                                "i",
+                                52..53,
+                            ),
+                            50..54,
+                        ),
+                        ZztOop(
+                            "\n",
+                            54..55,
+                        ),
+                    ],
+                    span: 28..43,
+                },
+            ),
+            ZztOop(
+                "#end",
+                60..64,
+            ),
+        ]
+        "###);
+    }
+
+    #[test]
+    fn loop_errors() {
+        assert_eq!(
+            parse_err("%repeat 3\nfoo").message,
+            "%repeat is missing its %end"
+        );
+        assert_eq!(parse_err("%for i 1..3\n%end").message, "Expected `in`");
+        assert_eq!(
+            parse_err("%for i in 3\n%end").message,
+            "Expected `..` or `..=`"
+        );
+        assert_eq!(
+            parse_err("%for 1 in 0..3\n%end").message,
+            "Expected identifier"
+        );
+        assert_eq!(
+            parse_err("%repeat 2\n%if x\n%end").message,
+            "%end without a matching %define, %for, or %repeat"
+        );
+    }
 }
@@ -4,10 +4,10 @@
     1. Evaluate macro invocations first.
         - Resolve escape sequences, e.g., long lines.
         - Replace top-level invocations with their output.
-        - Eventually, we'll replace in-line `${...}` expressions.
+        - Replace in-line `${...}` expressions with their values.
         - Output: a string representing a ZZT-OOP program.
     2. Parse ZZT-OOP next.
-        - Output: looks like Vec<Statement>.
+        - Output: an `oop::ast::Program`, which is lines of `Statement`s.
         - Statements are ZZT-OOP statements: text, centered, command, move, etc.
 
     From there, we can implement things like label rewriting.
@@ -15,19 +15,57 @@
 
 use std::{
     iter::{Fuse, Peekable},
+    ops::Range,
     str::CharIndices,
 };
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Token {
+    /// `&&`
+    And,
+    Comma,
+    /// `..`
+    DotDot,
+    /// `..=`
+    DotDotEquals,
+    /// `==`
+    DoubleEquals,
+    Equals,
+    Greater,
+    GreaterEquals,
     Identifier(String),
+    Integer(i64),
+    /// `}` at the end of an interpolation
+    InterpolationEnd,
+    /// `${` at the start of an interpolation
+    InterpolationStart,
+    LeftParen,
+    Less,
+    LessEquals,
+    Minus,
     Newline,
+    /// `!`
+    Not,
+    NotEquals,
+    /// `||`
+    Or,
     Percent,
+    Plus,
     RawText(String),
+    RightParen,
+    Slash,
+    Star,
+    /// A string literal, or part of one. A string with interpolations in it is
+    /// scanned as `String`, then `InterpolationStart` ... `InterpolationEnd`
+    /// and another `String`, and so on.
     String(String),
 }
 
-pub fn scan(input: &str) -> (Vec<Token>, Vec<String>) {
+/// A problem with the input, and where it is.
+pub type ScanError = (String, Range<usize>);
+
+/// Scan input into tokens, along with the span of input each token came from.
+pub fn scan(input: &str) -> (Vec<(Token, Range<usize>)>, Vec<ScanError>) {
     let mut scanner = Scanner::new(input);
     scanner.scan();
     (scanner.tokens, scanner.errors)
@@ -35,14 +73,18 @@ pub fn scan(input: &str) -> (Vec<Token>, Vec<String>) {
 
 struct Scanner<'a> {
     text: Peekable<Fuse<CharIndices<'a>>>,
-    tokens: Vec<Token>,
-    errors: Vec<String>,
+    len: usize,
+    start: usize,
+    tokens: Vec<(Token, Range<usize>)>,
+    errors: Vec<ScanError>,
 }
 
 impl<'a> Scanner<'a> {
     fn new(input: &'a str) -> Self {
         Scanner {
             text: input.char_indices().fuse().peekable(),
+            len: input.len(),
+            start: 0,
             tokens: vec![],
             errors: vec![],
         }
@@ -50,51 +92,104 @@ impl<'a> Scanner<'a> {
 
     fn scan(&mut self) {
         while let Some(next) = self.peek() {
+            self.start = self.offset();
             match next {
                 '\n' => {
-                    self.tokens.push(Token::Newline);
                     self.advance();
+                    self.push(Token::Newline);
                 }
                 '%' => {
-                    self.tokens.push(Token::Percent);
                     self.advance();
-                    self.scan_macro()
+                    self.push(Token::Percent);
+                    self.scan_expression(None)
                 }
                 _ => self.raw_text(),
             }
         }
     }
 
-    fn scan_macro(&mut self) {
+    /// Scan the tokens of a macro line, or of an interpolation ending in `end`.
+    fn scan_expression(&mut self, end: Option<char>) {
         while let Some(next) = self.peek() {
+            if Some(next) == end {
+                break;
+            }
+            self.start = self.offset();
             match next {
                 '\n' => break,
                 ' ' => {
                     self.advance();
                 }
+                '(' | ')' | ',' | '.' | '=' | '!' | '<' | '>' | '&' | '|' | '+' | '-' | '*'
+                | '/' => {
+                    self.operator(next);
+                }
                 '"' => {
                     self.string();
                 }
+                '0'..='9' => {
+                    self.integer();
+                }
                 'A'..='Z' | 'a'..='z' | '_' => {
                     self.identifier();
                 }
                 _ => {
-                    if let Some((i, c)) = self.text.peek() {
-                        self.errors
-                            .push(format!("Unexpected character {} at offset {}", c, i));
-                    }
                     self.advance();
+                    self.error(self.start, format!("Unexpected character {:?}", next));
                 }
             }
         }
     }
 
+    /// Scan an operator, like `+`, `<=`, or `..=`.
+    fn operator(&mut self, first: char) {
+        self.advance();
+        let pair = match (first, self.peek()) {
+            ('.', Some('.')) => Some(Token::DotDot),
+            ('=', Some('=')) => Some(Token::DoubleEquals),
+            ('!', Some('=')) => Some(Token::NotEquals),
+            ('<', Some('=')) => Some(Token::LessEquals),
+            ('>', Some('=')) => Some(Token::GreaterEquals),
+            ('&', Some('&')) => Some(Token::And),
+            ('|', Some('|')) => Some(Token::Or),
+            _ => None,
+        };
+        if let Some(mut token) = pair {
+            self.advance();
+            if token == Token::DotDot && self.consume('=') {
+                token = Token::DotDotEquals;
+            }
+            self.push(token);
+            return;
+        }
+        let token = match first {
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '!' => Token::Not,
+            '<' => Token::Less,
+            '>' => Token::Greater,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            _ => {
+                self.error(self.start, format!("Unexpected character {:?}", first));
+                return;
+            }
+        };
+        self.push(token);
+    }
+
     fn string(&mut self) {
+        let start = self.start;
         self.advance(); // discard leading quote
         let mut result = String::new();
         while let Some(next) = self.peek() {
             match next {
-                '"' => break,
+                // Strings can't span lines, unless the newline is escaped
+                '"' | '\n' => break,
                 '\\' => {
                     self.advance();
                     let char = self.get_escaped_char();
@@ -102,6 +197,11 @@ impl<'a> Scanner<'a> {
                         result.push(char)
                     }
                 }
+                '$' if self.peek_second() == Some('{') => {
+                    self.push(Token::String(std::mem::take(&mut result)));
+                    self.interpolation();
+                    self.start = self.offset();
+                }
                 _ => {
                     result.push(next);
                     self.advance();
@@ -109,9 +209,38 @@ impl<'a> Scanner<'a> {
             }
         }
         if !self.consume('"') {
-            self.errors.push("string not terminated".into());
+            self.error(start, "string not terminated".into());
         }
-        self.tokens.push(Token::String(result));
+        self.push(Token::String(result));
+    }
+
+    /// Scan an inline expression like `${foo}`.
+    fn interpolation(&mut self) {
+        let start = self.offset();
+        self.start = start;
+        self.advance();
+        self.advance();
+        self.push(Token::InterpolationStart);
+        self.scan_expression(Some('}'));
+        self.start = self.offset();
+        if self.consume('}') {
+            self.push(Token::InterpolationEnd);
+        } else {
+            self.error(start, "interpolation not terminated".into());
+        }
+    }
+
+    fn integer(&mut self) {
+        let mut result = String::new();
+        while let Some(next @ '0'..='9') = self.peek() {
+            result.push(next);
+            self.advance();
+        }
+        let value = result.parse().unwrap_or_else(|_| {
+            self.error(self.start, format!("integer too large: {}", result));
+            0
+        });
+        self.push(Token::Integer(value));
     }
 
     fn identifier(&mut self) {
@@ -125,11 +254,12 @@ impl<'a> Scanner<'a> {
                 _ => break,
             }
         }
-        self.tokens.push(Token::Identifier(result));
+        self.push(Token::Identifier(result));
     }
 
     fn raw_text(&mut self) {
         let mut result = String::new();
+        let mut interpolated = false;
         while let Some(next) = self.peek() {
             match next {
                 '\n' => break,
@@ -140,37 +270,63 @@ impl<'a> Scanner<'a> {
                         result.push(char);
                     }
                 }
+                '$' if self.peek_second() == Some('{') => {
+                    if !result.is_empty() {
+                        self.push(Token::RawText(std::mem::take(&mut result)));
+                    }
+                    self.interpolation();
+                    self.start = self.offset();
+                    interpolated = true;
+                }
                 _ => {
                     result.push(next);
                     self.advance();
                 }
             }
         }
-        self.tokens.push(Token::RawText(result));
+        if !result.is_empty() || !interpolated {
+            self.push(Token::RawText(result));
+        }
+    }
+
+    /// Record an error spanning from `start` to the current position.
+    fn error(&mut self, start: usize, message: String) {
+        let span = start..self.offset();
+        self.errors.push((message, span));
+    }
+
+    /// Add a token spanning from `self.start` to the current position.
+    fn push(&mut self, token: Token) {
+        let span = self.start..self.offset();
+        self.tokens.push((token, span));
+    }
+
+    fn offset(&mut self) -> usize {
+        self.text.peek().map(|(i, _)| *i).unwrap_or(self.len)
     }
 
     fn get_escaped_char(&mut self) -> char {
-        if let Some(next) = self.advance() {
-            next
-        } else {
-            '\\'
-        }
+        self.advance().unwrap_or('\\')
     }
 
     fn peek(&mut self) -> Option<char> {
         self.text.peek().map(|(_, c)| *c)
     }
 
+    fn peek_second(&self) -> Option<char> {
+        self.text.clone().nth(1).map(|(_, c)| c)
+    }
+
     fn advance(&mut self) -> Option<char> {
         self.text.next().map(|(_, c)| c)
     }
 
     fn consume(&mut self, c: char) -> bool {
-        if let Some(next) = self.peek() {
-            if next == c {
-                self.advance();
-                return true;
-            }
+        if let Some(next) = self.peek()
+            && next == c
+        {
+            self.advance();
+            return true;
         }
         false
     }
@@ -194,7 +350,11 @@ mod tests {
     token_helper!(string, String);
 
     fn scan(input: &str) -> Vec<Token> {
-        super::scan(input).0
+        super::scan(input)
+            .0
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
     }
 
     #[test]
@@ -289,15 +449,187 @@ mod tests {
 
     #[test]
     fn scan_premature_end_of_string() {
-        let (tokens, errors) = super::scan("%foo \"bar");
-        assert_eq!(vec![Percent, identifier("foo"), string("bar")], tokens);
-        assert!(errors.len() == 1);
+        let errors = super::scan("%foo \"bar").1;
+        assert_eq!(
+            vec![Percent, identifier("foo"), string("bar")],
+            scan("%foo \"bar")
+        );
+        assert_eq!(errors, vec![("string not terminated".into(), 5..9)]);
     }
 
     #[test]
     fn scan_unexpected_chars() {
-        let (tokens, errors) = super::scan("%foo @bar");
-        assert_eq!(vec![Percent, identifier("foo"), identifier("bar")], tokens);
+        let errors = super::scan("%foo @bar").1;
+        assert_eq!(
+            vec![Percent, identifier("foo"), identifier("bar")],
+            scan("%foo @bar")
+        );
+        assert_eq!(errors, vec![("Unexpected character '@'".into(), 5..6)]);
+    }
+
+    #[test]
+    fn scan_parens() {
+        assert_eq!(
+            vec![
+                Percent,
+                identifier("define"),
+                identifier("foo"),
+                LeftParen,
+                identifier("a"),
+                Comma,
+                identifier("b"),
+                RightParen
+            ],
+            scan("%define foo(a, b)")
+        );
+    }
+
+    #[test]
+    fn scan_spans() {
+        let spans: Vec<_> = super::scan("foo\n%bar \"baz\"")
+            .0
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+        assert_eq!(vec![0..3, 3..4, 4..5, 5..8, 9..14], spans);
+    }
+
+    #[test]
+    fn scan_expression() {
+        assert_eq!(
+            vec![
+                Percent,
+                identifier("let"),
+                identifier("x"),
+                Equals,
+                Integer(12),
+                Plus,
+                Minus,
+                LeftParen,
+                Integer(3),
+                Star,
+                identifier("y"),
+                Slash,
+                Integer(4),
+                RightParen
+            ],
+            scan("%let x = 12 + -(3*y / 4)")
+        );
+    }
+
+    #[test]
+    fn scan_interpolation() {
+        assert_eq!(
+            vec![
+                raw_text("#char "),
+                InterpolationStart,
+                identifier("wall"),
+                Plus,
+                Integer(1),
+                InterpolationEnd,
+                raw_text(" 'comment")
+            ],
+            scan("#char ${wall + 1} 'comment")
+        );
+        assert_eq!(
+            vec![
+                InterpolationStart,
+                identifier("a"),
+                InterpolationEnd,
+                InterpolationStart,
+                identifier("b"),
+                InterpolationEnd
+            ],
+            scan("${a}${b}")
+        );
+        assert_eq!(vec![raw_text("$100 ${x")], scan("$100 \\${x"));
+    }
+
+    #[test]
+    fn scan_string_interpolation() {
+        assert_eq!(
+            vec![
+                Percent,
+                identifier("foo"),
+                string("a"),
+                InterpolationStart,
+                identifier("b"),
+                InterpolationEnd,
+                string("")
+            ],
+            scan("%foo \"a${b}\"")
+        );
+    }
+
+    #[test]
+    fn scan_unterminated_interpolation() {
+        let errors = super::scan("foo ${bar").1;
+        assert_eq!(
+            vec![raw_text("foo "), InterpolationStart, identifier("bar")],
+            scan("foo ${bar")
+        );
         assert!(errors.len() == 1);
     }
+
+    #[test]
+    fn scan_comparison() {
+        assert_eq!(
+            vec![
+                Percent,
+                identifier("if"),
+                Not,
+                identifier("a"),
+                And,
+                identifier("b"),
+                DoubleEquals,
+                Integer(1),
+                Or,
+                identifier("c"),
+                NotEquals,
+                Integer(2),
+                Or,
+                Integer(1),
+                Less,
+                Integer(2),
+                LessEquals,
+                Integer(3),
+                Greater,
+                Integer(4),
+                GreaterEquals,
+                Integer(5)
+            ],
+            scan("%if !a && b == 1 || c != 2 || 1 < 2 <= 3 > 4 >= 5")
+        );
+        let (tokens, errors) = super::scan("%if a & b");
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(errors, vec![("Unexpected character '&'".into(), 6..7)]);
+    }
+
+    #[test]
+    fn scan_range() {
+        assert_eq!(
+            vec![
+                Percent,
+                identifier("for"),
+                identifier("i"),
+                identifier("in"),
+                Integer(1),
+                DotDot,
+                identifier("n"),
+                Newline,
+                Percent,
+                identifier("for"),
+                identifier("i"),
+                identifier("in"),
+                Integer(0),
+                DotDotEquals,
+                Integer(3)
+            ],
+            scan("%for i in 1..n\n%for i in 0..=3")
+        );
+        assert_eq!(
+            super::scan("%foo .").1,
+            vec![("Unexpected character '.'".into(), 5..6)]
+        );
+    }
 }
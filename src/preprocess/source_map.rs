@@ -0,0 +1,253 @@
+//! Track where each part of a stat's expanded code came from.
+//!
+//! Label processing and other later passes only ever see expanded code, so
+//! their diagnostics need to be mapped back to the code the user actually
+//! wrote, which might be in an included file.
+
+use std::{ops::Range, path::PathBuf, rc::Rc};
+
+use rustc_hash::FxHashMap;
+
+use crate::error::{CompileMessage, Origin};
+
+/// Some text that macros get read from: either a stat's code, or a file.
+pub struct Source {
+    pub path: Option<PathBuf>,
+    pub text: Rc<str>,
+    /// The source that included this one, and the span of its `%include`
+    pub included_at: Option<(Rc<Source>, Range<usize>)>,
+}
+
+impl Source {
+    /// Describe a position in this source for error messages, e.g., `foo.txt line 3`.
+    pub fn describe(&self, offset: usize) -> String {
+        let line = self.text[..offset].matches('\n').count() + 1;
+        match &self.path {
+            Some(path) => format!("{} line {line}", path.display()),
+            None => format!("line {line}"),
+        }
+    }
+
+    /// Describe a span of this source for diagnostics.
+    pub fn origin(&self, span: &Range<usize>) -> Origin {
+        let mut included_from = vec![];
+        let mut parent = &self.included_at;
+        while let Some((source, span)) = parent {
+            included_from.push(source.describe(span.start));
+            parent = &source.included_at;
+        }
+        Origin {
+            file_path: self.path.as_ref().map(|x| x.display().to_string()),
+            text: self.text.clone(),
+            span: span.clone(),
+            included_from,
+        }
+    }
+
+    /// Find the span of the stat's own code that a span of this source comes
+    /// from. For included files, that's the span of the first `%include`.
+    pub fn root_span(&self, span: &Range<usize>) -> Range<usize> {
+        match &self.included_at {
+            Some((parent, span)) => parent.root_span(span),
+            None => span.clone(),
+        }
+    }
+}
+
+/// Expanded code, along with where it came from.
+#[derive(Default)]
+pub struct Output {
+    pub text: String,
+    pub map: SourceMap,
+}
+
+impl Output {
+    /// Add text that was generated from a span of a source.
+    pub fn push(&mut self, text: &str, source: &Rc<Source>, span: &Range<usize>) {
+        if text.is_empty() {
+            return;
+        }
+        self.map.segments.push(Segment {
+            start: self.text.len(),
+            len: text.len(),
+            source: source.clone(),
+            span: span.clone(),
+        });
+        self.text.push_str(text);
+    }
+
+    pub fn append(&mut self, other: Output) {
+        let offset = self.text.len();
+        let segments = other.map.segments.into_iter().map(|mut segment| {
+            segment.start += offset;
+            segment
+        });
+        self.map.segments.extend(segments);
+        self.text.push_str(&other.text);
+    }
+}
+
+/// Maps spans of a stat's expanded code back to where they came from.
+#[derive(Default)]
+pub struct SourceMap {
+    /// Segments of expanded code, in order, with no gaps between them
+    segments: Vec<Segment>,
+}
+
+struct Segment {
+    /// Where the segment starts in the expanded code
+    start: usize,
+    len: usize,
+    source: Rc<Source>,
+    /// The span of the source that the segment was generated from. Text that
+    /// was copied as-is has the same length as its span, so offsets within it
+    /// can be mapped exactly. Anything else, like the value of `${...}`, maps
+    /// to the whole span.
+    span: Range<usize>,
+}
+
+impl SourceMap {
+    /// Find where a span of expanded code came from.
+    pub fn origin(&self, span: &Range<usize>) -> Option<Origin> {
+        let (source, span) = self.locate(span)?;
+        Some(source.origin(&span))
+    }
+
+    /// Find the span of the stat's own code that a span of expanded code came
+    /// from. For code from included files, that's the span of the `%include`.
+    pub fn root_span(&self, span: &Range<usize>) -> Option<Range<usize>> {
+        let (source, span) = self.locate(span)?;
+        Some(source.root_span(&span))
+    }
+
+    fn locate(&self, span: &Range<usize>) -> Option<(&Rc<Source>, Range<usize>)> {
+        let i = self
+            .segments
+            .partition_point(|x| x.start + x.len <= span.start);
+        let segment = self.segments.get(i).or(self.segments.last())?;
+        let source_span = if segment.len == segment.span.len() {
+            let offset = span.start.saturating_sub(segment.start).min(segment.len);
+            let start = segment.span.start + offset;
+            start..start + span.len().min(segment.len - offset)
+        } else {
+            segment.span.clone()
+        };
+        Some((&segment.source, source_span))
+    }
+}
+
+/// Point diagnostics at the code the user wrote, rather than the expanded
+/// code. `maps` holds the source map of each stat, by board and stat index.
+pub fn resolve_origins(
+    messages: &mut [CompileMessage],
+    maps: &FxHashMap<(usize, usize), SourceMap>,
+) {
+    for message in messages {
+        let location = &mut message.location;
+        let (Some(board), Some(stat)) = (location.board, location.stat) else {
+            continue;
+        };
+        let Some(map) = maps.get(&(board, stat)) else {
+            continue;
+        };
+        if let (None, Some(span)) = (&location.origin, &location.span) {
+            location.origin = map.origin(span);
+        }
+        for label in &mut message.labels {
+            if label.origin.is_none() {
+                label.origin = map.origin(&label.span);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use insta::assert_snapshot;
+
+    use crate::{
+        error::Context,
+        labels::{Options, process_labels},
+        preprocess::eval::Context as EvalContext,
+        world::{Board, Format, World},
+    };
+
+    use super::*;
+
+    fn source(path: Option<&str>, text: &str) -> Rc<Source> {
+        Rc::new(Source {
+            path: path.map(PathBuf::from),
+            text: text.into(),
+            included_at: None,
+        })
+    }
+
+    #[test]
+    fn map_spans() {
+        let stat = source(None, "@Obj\n%include \"a.txt\"\n${x}!");
+        let file = Rc::new(Source {
+            path: Some("a.txt".into()),
+            text: "#end\n:touch".into(),
+            included_at: Some((stat.clone(), 5..23)),
+        });
+        let mut output = Output::default();
+        output.push("@Obj\n", &stat, &(0..5));
+        let mut included = Output::default();
+        included.push("#end\n:touch", &file, &(0..11));
+        output.append(included);
+        output.push("\n", &stat, &(23..24));
+        output.push("123", &stat, &(24..28));
+        output.push("!", &stat, &(28..29));
+        assert_eq!(output.text, "@Obj\n#end\n:touch\n123!");
+
+        let origin = |span| output.map.origin(&span).unwrap();
+        let touch = origin(11..16);
+        assert_eq!(touch.file_path.as_deref(), Some("a.txt"));
+        assert_eq!(touch.span, 6..11);
+        assert_eq!(touch.included_from, vec!["line 2"]);
+        assert_eq!(output.map.root_span(&(11..16)), Some(5..23));
+
+        // Spans that run past the end of a segment get cut off
+        assert_eq!(origin(3..8).span, 3..5);
+        assert_eq!(origin(3..8).file_path, None);
+
+        // Generated text maps to the whole expression
+        assert_eq!(origin(18..19).span, 24..28);
+        assert_eq!(origin(20..21).span, 28..29);
+        assert_eq!(origin(21..21).span, 29..29);
+        assert!(Output::default().map.origin(&(0..0)).is_none());
+    }
+
+    #[test]
+    fn label_diagnostics_in_included_files() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        board.stats[0].code = "@Guard\n%include \"outer.txt\"\n:bad\n#send nowhere".into();
+        let mut world = World::default();
+        world.boards.push(board);
+
+        let eval_context = EvalContext::new(Path::new("tests/preprocess"));
+        let stat = &mut world.boards[0].stats[0];
+        let output = eval_context
+            .eval_program(&stat.code, &Context::new())
+            .unwrap();
+        stat.code = output.text;
+        let maps = FxHashMap::from_iter([((0, 0), output.map)]);
+
+        let base_ctx = Context::new();
+        process_labels(
+            &world.boards[0],
+            &Options::default(),
+            &base_ctx.with_board(0),
+        );
+        let mut messages = base_ctx.into_messages();
+        resolve_origins(&mut messages, &maps);
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|x| x.rich_format(&world, false))
+            .collect();
+        assert_snapshot!(messages.join("\n\n"));
+    }
+}
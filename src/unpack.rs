@@ -0,0 +1,672 @@
+//! Convert a world to a tree of text files and back, for version control.
+//!
+//! An unpacked world looks like this:
+//!
+//! ```text
+//! world.toml                  (header fields, like ammo and flags)
+//! boards/
+//!     000-title-screen/
+//!         board.toml          (board info, the element legend, and stats)
+//!         elements.txt        (one character per tile, per the legend)
+//!         colors.txt          (two hex digits per tile)
+//!         002-guard.oop       (each stat's code, named after its index and object)
+//!     001-armory/
+//!         ...
+//! ```
+//!
+//! Packing it again gives back the original file, byte for byte. Bytes that
+//! the game doesn't use, like whatever's left over after the end of a string,
+//! are kept as hex in `world.toml` and `board.toml`.
+//! Boards are ordered by the number at the start of their directory's name,
+//! so directories can be renamed as long as the numbers stay the same.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encoding::{decode_oneline, encode_oneline},
+    world::{Board, Format, Stat, World},
+};
+
+const WORLD_FILENAME: &str = "world.toml";
+const BOARDS_DIRNAME: &str = "boards";
+const BOARD_FILENAME: &str = "board.toml";
+const ELEMENTS_FILENAME: &str = "elements.txt";
+const COLORS_FILENAME: &str = "colors.txt";
+
+/// Characters for ZZT's elements in `elements.txt`, indexed by element ID.
+/// A space means the element doesn't get a particular character.
+const ZZT_ELEMENT_CHARS: &[u8; 54] = b".Emn@atgkDSPubes/\\!~f#%:0|-,;WT=*_yrOvqGpLxIHh 1234567";
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldFile {
+    format: Format,
+    world_name: String,
+    ammo: i16,
+    gems: i16,
+    keys: [bool; 7],
+    health: i16,
+    starting_board: i16,
+    torches: i16,
+    torch_cycles: i16,
+    energizer_cycles: i16,
+    score: i16,
+    flags: Vec<String>,
+    time: i16,
+    time_ticks: i16,
+    locked: bool,
+    stones: i16,
+    /// Hex, with trailing zeros left off
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    header_padding: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoardFile {
+    name: String,
+    max_shots: u8,
+    is_dark: bool,
+    board_n: u8,
+    board_s: u8,
+    board_w: u8,
+    board_e: u8,
+    reenter_when_zapped: bool,
+    message: String,
+    enter_x: u8,
+    enter_y: u8,
+    camera_x: i16,
+    camera_y: i16,
+    time_limit: i16,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    padding: String,
+    /// Which element each character in `elements.txt` stands for
+    legend: BTreeMap<String, u8>,
+    #[serde(default)]
+    stats: Vec<StatEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatEntry {
+    x: u8,
+    y: u8,
+    x_step: i16,
+    y_step: i16,
+    cycle: i16,
+    p1: u8,
+    p2: u8,
+    p3: u8,
+    follower: i16,
+    leader: i16,
+    under_element: u8,
+    under_color: u8,
+    instruction_pointer: i16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bound_to: Option<usize>,
+    /// The file with the stat's code, if it has any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    data_pointer: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    padding: String,
+}
+
+/// Write a world file out as a tree of text files. Returns a status message.
+///
+/// The directory can be new, empty, or a previously unpacked world, whose
+/// boards get replaced.
+pub fn unpack(world_path: &Path, dir: &Path) -> Result<String> {
+    let bytes = fs::read(world_path)
+        .map_err(|e| anyhow!("Couldn't read {}: {}", world_path.display(), e))?;
+    let world = World::from_bytes(&bytes)
+        .map_err(|e| anyhow!("Couldn't load {}: {}", world_path.display(), e))?;
+    let files = unpack_world(&world)?;
+    // Like extra data after the last board, or terrain compressed differently
+    let exact = world.to_bytes().is_ok_and(|x| x == bytes);
+
+    // Don't clobber anything that isn't ours
+    let boards_dir = dir.join(BOARDS_DIRNAME);
+    if dir.join(WORLD_FILENAME).exists() {
+        if boards_dir.exists() {
+            fs::remove_dir_all(&boards_dir)?;
+        }
+    } else if dir.read_dir().is_ok_and(|mut x| x.next().is_some()) {
+        return Err(anyhow!(
+            "Couldn't unpack into {}: it isn't empty, and it doesn't have a {WORLD_FILENAME}",
+            dir.display()
+        ));
+    }
+
+    for (path, contents) in &files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, contents)
+            .map_err(|e| anyhow!("Couldn't write {}: {}", path.display(), e))?;
+    }
+    let mut message = format!(
+        "Unpacked {} boards into {}",
+        world.boards.len(),
+        dir.display()
+    );
+    if !exact {
+        message.push_str(&format!(
+            "\nNote: packing it won't give back exactly the same bytes, since {} isn't laid out the way Marzipan writes worlds",
+            world_path.display()
+        ));
+    }
+    Ok(message)
+}
+
+/// Read an unpacked world and write it as a world file. Returns a status
+/// message.
+///
+/// An unpacked world that hasn't been edited gets packed back into the same
+/// bytes it was unpacked from.
+pub fn pack(dir: &Path, output_path: &Path) -> Result<String> {
+    let world = pack_world(dir)?;
+    let bytes = world
+        .to_bytes()
+        .map_err(|e| anyhow!("Couldn't serialize world, {}", e))?;
+    fs::write(output_path, bytes)?;
+    Ok(format!(
+        "Packed {} boards into {}",
+        world.boards.len(),
+        output_path.display()
+    ))
+}
+
+/// Get the path and contents of each file in an unpacked world.
+fn unpack_world(world: &World) -> Result<Vec<(PathBuf, String)>> {
+    let world_file = WorldFile {
+        format: world.format,
+        world_name: decode_oneline(&world.world_name),
+        ammo: world.ammo,
+        gems: world.gems,
+        keys: world.keys,
+        health: world.health,
+        starting_board: world.starting_board,
+        torches: world.torches,
+        torch_cycles: world.torch_cycles,
+        energizer_cycles: world.energizer_cycles,
+        score: world.score,
+        flags: world.flags.iter().map(|x| decode_oneline(x)).collect(),
+        time: world.time,
+        time_ticks: world.time_ticks,
+        locked: world.locked,
+        stones: world.stones,
+        header_padding: to_hex(&world.header_padding),
+    };
+    let mut files = vec![(PathBuf::from(WORLD_FILENAME), toml::to_string(&world_file)?)];
+
+    for (i, board) in world.boards.iter().enumerate() {
+        let board_dir = Path::new(BOARDS_DIRNAME).join(numbered_name(i, &board.name));
+        let legend = make_legend(board);
+        let mut stats = vec![];
+        for (j, stat) in board.stats.iter().enumerate() {
+            let code = (!stat.code.is_empty()).then(|| {
+                let filename = format!("{}.oop", numbered_name(j, stat.name().unwrap_or("")));
+                files.push((board_dir.join(&filename), stat.code.clone()));
+                filename
+            });
+            stats.push(StatEntry {
+                x: stat.x,
+                y: stat.y,
+                x_step: stat.x_step,
+                y_step: stat.y_step,
+                cycle: stat.cycle,
+                p1: stat.p1,
+                p2: stat.p2,
+                p3: stat.p3,
+                follower: stat.follower,
+                leader: stat.leader,
+                under_element: stat.under_element,
+                under_color: stat.under_color,
+                instruction_pointer: stat.instruction_pointer,
+                bound_to: stat.bound_to,
+                code,
+                data_pointer: to_hex(&stat.data_pointer),
+                padding: to_hex(&stat.padding),
+            });
+        }
+        let board_file = BoardFile {
+            name: board.name.clone(),
+            max_shots: board.max_shots,
+            is_dark: board.is_dark,
+            board_n: board.board_n,
+            board_s: board.board_s,
+            board_w: board.board_w,
+            board_e: board.board_e,
+            reenter_when_zapped: board.reenter_when_zapped,
+            message: decode_oneline(&board.message),
+            enter_x: board.enter_x,
+            enter_y: board.enter_y,
+            camera_x: board.camera_x,
+            camera_y: board.camera_y,
+            time_limit: board.time_limit,
+            padding: to_hex(&board.padding),
+            legend: legend
+                .iter()
+                .map(|(&element, &c)| (c.to_string(), element))
+                .collect(),
+            stats,
+        };
+        files.push((
+            board_dir.join(BOARD_FILENAME),
+            toml::to_string(&board_file)?,
+        ));
+
+        // Terrain, one line per row
+        let (width, _) = board.format.board_size();
+        let mut elements = String::new();
+        let mut colors = String::new();
+        for row in board.terrain.chunks(width) {
+            for &[element, color] in row {
+                elements.push(legend[&element]);
+                colors.push_str(&format!("{color:02x}"));
+            }
+            elements.push('\n');
+            colors.push('\n');
+        }
+        files.push((board_dir.join(ELEMENTS_FILENAME), elements));
+        files.push((board_dir.join(COLORS_FILENAME), colors));
+    }
+    Ok(files)
+}
+
+/// Read the files in an unpacked world.
+fn pack_world(dir: &Path) -> Result<World> {
+    let world_file: WorldFile = read_toml(&dir.join(WORLD_FILENAME))?;
+    let format = world_file.format;
+    let mut world = World::new(format);
+    world.world_name = encode_oneline(&world_file.world_name)?;
+    world.ammo = world_file.ammo;
+    world.gems = world_file.gems;
+    world.keys = world_file.keys;
+    world.health = world_file.health;
+    world.starting_board = world_file.starting_board;
+    world.torches = world_file.torches;
+    world.torch_cycles = world_file.torch_cycles;
+    world.energizer_cycles = world_file.energizer_cycles;
+    world.score = world_file.score;
+    world.flags = world_file
+        .flags
+        .iter()
+        .map(|x| encode_oneline(x))
+        .collect::<Result<_>>()?;
+    world.time = world_file.time;
+    world.time_ticks = world_file.time_ticks;
+    world.locked = world_file.locked;
+    world.stones = world_file.stones;
+    world.header_padding = from_hex(&world_file.header_padding)?;
+
+    for board_dir in board_dirs(&dir.join(BOARDS_DIRNAME))? {
+        let board = pack_board(&board_dir, format)
+            .map_err(|e| anyhow!("In {}: {}", board_dir.display(), e))?;
+        world.boards.push(board);
+    }
+    Ok(world)
+}
+
+fn pack_board(dir: &Path, format: Format) -> Result<Board> {
+    let board_file: BoardFile = read_toml(&dir.join(BOARD_FILENAME))?;
+    let mut board = Board::new(format);
+    board.name = board_file.name;
+    board.max_shots = board_file.max_shots;
+    board.is_dark = board_file.is_dark;
+    board.board_n = board_file.board_n;
+    board.board_s = board_file.board_s;
+    board.board_w = board_file.board_w;
+    board.board_e = board_file.board_e;
+    board.reenter_when_zapped = board_file.reenter_when_zapped;
+    board.message = encode_oneline(&board_file.message)?;
+    board.enter_x = board_file.enter_x;
+    board.enter_y = board_file.enter_y;
+    board.camera_x = board_file.camera_x;
+    board.camera_y = board_file.camera_y;
+    board.time_limit = board_file.time_limit;
+    board.padding = from_hex(&board_file.padding)?;
+
+    // Terrain
+    let mut legend = FxHashMap::default();
+    for (key, element) in board_file.legend {
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => legend.insert(c, element),
+            _ => return Err(anyhow!("legend keys must be one character, not {key:?}")),
+        };
+    }
+    let elements = read_grid(&dir.join(ELEMENTS_FILENAME), format, 1)?;
+    let colors = read_grid(&dir.join(COLORS_FILENAME), format, 2)?;
+    for (i, (element, color)) in elements.iter().zip(&colors).enumerate() {
+        let c = element.chars().next().unwrap();
+        let element = *legend
+            .get(&c)
+            .ok_or_else(|| anyhow!("{ELEMENTS_FILENAME} has {c:?}, which isn't in the legend"))?;
+        let color = u8::from_str_radix(color, 16)
+            .map_err(|_| anyhow!("{COLORS_FILENAME} has {color:?}, which isn't a hex color"))?;
+        board.terrain[i] = [element, color];
+    }
+
+    // Stats
+    for entry in board_file.stats {
+        let code = match &entry.code {
+            Some(filename) => read_file(&dir.join(filename))?,
+            None => String::new(),
+        };
+        let data_pointer = from_hex(&entry.data_pointer)?;
+        if data_pointer.len() > 4 {
+            return Err(anyhow!("data_pointer can only be 4 bytes"));
+        }
+        let mut stat = Stat {
+            x: entry.x,
+            y: entry.y,
+            x_step: entry.x_step,
+            y_step: entry.y_step,
+            cycle: entry.cycle,
+            p1: entry.p1,
+            p2: entry.p2,
+            p3: entry.p3,
+            follower: entry.follower,
+            leader: entry.leader,
+            under_element: entry.under_element,
+            under_color: entry.under_color,
+            instruction_pointer: entry.instruction_pointer,
+            bound_to: entry.bound_to,
+            padding: from_hex(&entry.padding)?,
+            code,
+            ..Default::default()
+        };
+        stat.data_pointer[..data_pointer.len()].copy_from_slice(&data_pointer);
+        board.stats.push(stat);
+    }
+    board.check_binds().map_err(|e| anyhow!("{e}"))?;
+    Ok(board)
+}
+
+/// Find the directories for each board, in order. Each name starts with its
+/// board's number, and the numbers have to go from 0 without any gaps.
+fn board_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut numbered = BTreeMap::new();
+    let entries =
+        fs::read_dir(dir).map_err(|e| anyhow!("Couldn't read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy();
+        let digits = name.split('-').next().unwrap();
+        let Ok(number) = digits.parse::<usize>() else {
+            return Err(anyhow!(
+                "Couldn't tell which board {} is: its name should start with the board's number",
+                path.display()
+            ));
+        };
+        if let Some(other) = numbered.insert(number, path.clone()) {
+            return Err(anyhow!(
+                "{} and {} are both board {number}",
+                other.display(),
+                path.display()
+            ));
+        }
+    }
+    if let Some((i, _)) = numbered
+        .keys()
+        .enumerate()
+        .find(|&(i, &number)| i != number)
+    {
+        return Err(anyhow!(
+            "There's no directory for board {i} in {}",
+            dir.display()
+        ));
+    }
+    Ok(numbered.into_values().collect())
+}
+
+/// Read a grid of tiles from `elements.txt` or `colors.txt`, with each tile
+/// taking up `tile_len` characters.
+fn read_grid(path: &Path, format: Format, tile_len: usize) -> Result<Vec<String>> {
+    let text = read_file(path)?;
+    let (width, height) = format.board_size();
+    let lines: Vec<Vec<char>> = text.lines().map(|x| x.chars().collect()).collect();
+    if lines.len() != height || lines.iter().any(|x| x.len() != width * tile_len) {
+        return Err(anyhow!(
+            "{} should have {height} lines of {} characters",
+            path.display(),
+            width * tile_len
+        ));
+    }
+    Ok(lines
+        .iter()
+        .flat_map(|line| line.chunks(tile_len).map(|x| x.iter().collect()))
+        .collect())
+}
+
+/// Pick a character to stand for each element on the board.
+fn make_legend(board: &Board) -> FxHashMap<u8, char> {
+    let mut legend = FxHashMap::default();
+    let mut elements: Vec<_> = board.terrain.iter().map(|[element, _]| *element).collect();
+    elements.sort();
+    elements.dedup();
+
+    // Elements get their usual characters if they have them, and then the
+    // other elements get whatever printable characters are left
+    if board.format == Format::Zzt {
+        for &element in &elements {
+            if let Some(&c) = ZZT_ELEMENT_CHARS.get(element as usize)
+                && c != b' '
+            {
+                legend.insert(element, c as char);
+            }
+        }
+    }
+    let used: Vec<char> = legend.values().copied().collect();
+    let mut spare = ('!'..='~').chain('¡'..).filter(|c| !used.contains(c));
+    for element in elements {
+        legend
+            .entry(element)
+            .or_insert_with(|| spare.next().unwrap());
+    }
+    legend
+}
+
+/// A file or directory name with a number and a name that's safe for any
+/// filesystem, like `003-guard`.
+fn numbered_name(number: usize, name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        format!("{number:03}")
+    } else {
+        format!("{number:03}-{slug}")
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))
+}
+
+fn read_toml<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    toml::from_str(&read_file(path)?)
+        .map_err(|e| anyhow!("Couldn't parse {}: {}", path.display(), e))
+}
+
+/// Write bytes as hex, leaving off trailing zeros. Missing bytes are written
+/// as zeros anyway, so this keeps unused padding from cluttering things up.
+fn to_hex(bytes: &[u8]) -> String {
+    let len = bytes.iter().rposition(|&x| x != 0).map_or(0, |i| i + 1);
+    bytes[..len].iter().map(|x| format!("{x:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(anyhow!("Invalid hex: {text:?}"));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex: {text:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use insta::assert_snapshot;
+
+    use crate::world::test::object;
+
+    use super::*;
+
+    /// A directory to unpack into, which is deleted when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("marzipan-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_files(dir: &Path, world: &World) {
+        for (path, contents) in unpack_world(world).unwrap() {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    fn example_world() -> World {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_brd(&blank).unwrap();
+        board.name = "Armory".into();
+        board.terrain[61] = [22, 0x0e];
+        board
+            .stats
+            .push(object(2, 2, "@Guard\n#end\n:touch\n#send nowhere\n"));
+        board.stats.push(Stat {
+            bound_to: Some(1),
+            ..object(3, 2, "")
+        });
+        board.stats[0].data_pointer = [1, 2, 3, 4];
+        let mut world = World::new(Format::Zzt);
+        world.world_name = b"TOWN".to_vec();
+        world.flags[0] = b"\x01SECRET".to_vec();
+        world.header_padding = vec![0, 7];
+        let mut title = Board::new(Format::Zzt);
+        title.stats.push(object(30, 12, ""));
+        world.boards.push(title);
+        world.boards.push(board);
+        world
+    }
+
+    #[test]
+    fn test_real_worlds() {
+        for path in ["tests/town.zzt", "tests/lobby.szt"] {
+            let dir = TempDir::new("real");
+            let packed = dir.0.join("packed");
+            unpack(Path::new(path), &dir.0).unwrap();
+            pack(&dir.0, &packed).unwrap();
+            assert_eq!(
+                fs::read(&packed).unwrap(),
+                fs::read(path).unwrap(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new("round-trip");
+        let world = example_world();
+        write_files(&dir.0, &world);
+        let packed = pack_world(&dir.0).unwrap();
+        assert_eq!(packed.to_bytes().unwrap(), world.to_bytes().unwrap());
+
+        let mut world = World::new(Format::SuperZzt);
+        let mut board = Board::new(Format::SuperZzt);
+        board.terrain[96 * 80 - 1] = [21, 0x0f];
+        board.camera_x = 30;
+        board.stats.push(object(48, 40, ""));
+        board.stats.push(object(95, 79, "@Guard\n#end\n"));
+        world.boards.push(board);
+        let dir = TempDir::new("round-trip-szt");
+        write_files(&dir.0, &world);
+        let packed = pack_world(&dir.0).unwrap();
+        assert_eq!(packed.to_bytes().unwrap(), world.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_files() {
+        let files = unpack_world(&example_world()).unwrap();
+        let names: Vec<_> = files.iter().map(|(x, _)| x.display().to_string()).collect();
+        assert_snapshot!(names.join("\n"), @r"
+        world.toml
+        boards/000/board.toml
+        boards/000/elements.txt
+        boards/000/colors.txt
+        boards/001-armory/001-guard.oop
+        boards/001-armory/board.toml
+        boards/001-armory/elements.txt
+        boards/001-armory/colors.txt
+        ");
+        let board_file = &files[5].1;
+        assert_snapshot!(board_file);
+        let elements = &files[6].1;
+        assert_eq!(
+            &elements[..122],
+            format!("@{}\n.%{}\n", ".".repeat(59), ".".repeat(58))
+        );
+    }
+
+    #[test]
+    fn test_edits() {
+        let dir = TempDir::new("edits");
+        write_files(&dir.0, &example_world());
+        let board_dir = dir.0.join("boards/001-armory");
+        fs::write(board_dir.join("001-guard.oop"), "@Guard\n#end\n").unwrap();
+        fs::rename(&board_dir, dir.0.join("boards/001-renamed")).unwrap();
+        let packed = pack_world(&dir.0).unwrap();
+        assert_eq!(packed.boards[1].stats[1].code, "@Guard\n#end\n");
+
+        // Boards can't be missing
+        fs::rename(dir.0.join("boards/000"), dir.0.join("boards/002-moved")).unwrap();
+        assert!(pack_world(&dir.0).is_err());
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(numbered_name(3, "Guard #2 (east)"), "003-guard-2-east");
+        assert_eq!(numbered_name(12, "!!"), "012");
+        assert_eq!(to_hex(&[0, 0xab, 0, 0]), "00ab");
+        assert_eq!(from_hex("00ab").unwrap(), vec![0, 0xab]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
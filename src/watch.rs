@@ -0,0 +1,90 @@
+//! Recompile a world whenever it, or any file it includes, changes.
+//!
+//! This polls modification times rather than relying on OS notifications. A
+//! world only depends on a handful of files, so polling them is cheap, and it
+//! works the same everywhere.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    compile::{MessageFormat, Options, compile},
+    config::CONFIG_FILENAME,
+};
+
+/// How often to check for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Compile the world, then recompile it every time one of its files changes.
+/// This runs until the process is killed.
+pub fn watch(options: &Options, output_path: &Path, format: MessageFormat) -> ! {
+    loop {
+        let dependencies = build(options, output_path, format);
+        format.status(&format!(
+            "Watching {} files for changes...",
+            dependencies.len()
+        ));
+
+        let mut times = modified_times(&dependencies);
+        let changed = loop {
+            thread::sleep(POLL_INTERVAL);
+            let new_times = modified_times(&dependencies);
+            if let Some(i) = (0..times.len()).find(|&i| times[i] != new_times[i]) {
+                times = new_times;
+                break &dependencies[i];
+            }
+        };
+
+        // Editors sometimes save a file in several steps, so wait for things
+        // to settle before reading anything
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let new_times = modified_times(&dependencies);
+            if new_times == times {
+                break;
+            }
+            times = new_times;
+        }
+        format.status(&format!(
+            "\n{} changed, recompiling...\n",
+            changed.display()
+        ));
+    }
+}
+
+/// Compile the world once, writing the output if it succeeds. Returns the
+/// files to watch for changes.
+fn build(options: &Options, output_path: &Path, format: MessageFormat) -> Vec<PathBuf> {
+    match compile(options) {
+        Ok(compilation) => {
+            compilation.print_diagnostics(format);
+            if compilation.succeeded() {
+                match compilation.write(output_path) {
+                    Ok(()) => format.status(&format!("Wrote {}", output_path.display())),
+                    Err(e) => format.status(&format!("Error: {e}")),
+                }
+            }
+            compilation.dependencies
+        }
+        Err(e) => {
+            // Maybe the world is halfway through being saved. There's no way to
+            // know what it includes, but it'll get recompiled when it changes.
+            format.status(&format!("Error: {e}"));
+            let world_path = PathBuf::from(&options.input_file);
+            let config_path = world_path.with_file_name(CONFIG_FILENAME);
+            vec![world_path, config_path]
+        }
+    }
+}
+
+/// Get the modification time of each file, or `None` for missing files.
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|x| x.modified()).ok())
+        .collect()
+}
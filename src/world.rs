@@ -2,12 +2,13 @@ use std::{error::Error, fmt::Display};
 
 use nom::{
     Err, IResult, Parser,
-    bytes::complete::{tag, take},
+    bytes::complete::take,
     combinator::fail,
     error::{ErrorKind, ParseError},
     multi::count,
     number::complete::{le_i16, le_u8, le_u16},
 };
+use serde::{Deserialize, Serialize};
 
 use crate::encoding::{decode_multiline, decode_oneline, encode_multiline, encode_oneline};
 
@@ -49,6 +50,12 @@ impl From<&str> for LoadError {
     }
 }
 
+impl From<String> for LoadError {
+    fn from(message: String) -> Self {
+        Self { message }
+    }
+}
+
 impl Display for LoadError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.message.fmt(f)
@@ -69,44 +76,132 @@ impl Error for LoadError {
     }
 }
 
-#[derive(Default)]
+/// Which game a world is for. ZZT and Super ZZT run the same ZZT-OOP, but
+/// they store worlds, boards, and stats differently.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    #[default]
+    Zzt,
+    SuperZzt,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Zzt => "ZZT",
+            Format::SuperZzt => "Super ZZT",
+        }
+    }
+
+    /// The first two bytes of a world file.
+    fn magic(self) -> i16 {
+        match self {
+            Format::Zzt => -1,
+            Format::SuperZzt => -2,
+        }
+    }
+
+    /// Size of the world header, including padding.
+    fn header_size(self) -> usize {
+        match self {
+            Format::Zzt => 512,
+            Format::SuperZzt => 1024,
+        }
+    }
+
+    fn num_flags(self) -> usize {
+        match self {
+            Format::Zzt => 10,
+            Format::SuperZzt => 16,
+        }
+    }
+
+    /// Width and height of a board, in tiles.
+    pub fn board_size(self) -> (usize, usize) {
+        match self {
+            Format::Zzt => (60, 25),
+            Format::SuperZzt => (96, 80),
+        }
+    }
+
+    fn board_name_len(self) -> u8 {
+        match self {
+            Format::Zzt => 50,
+            Format::SuperZzt => 60,
+        }
+    }
+
+    /// Size of the padding at the end of a stat's fixed fields.
+    fn stat_padding(self) -> usize {
+        match self {
+            Format::Zzt => 8,
+            Format::SuperZzt => 0,
+        }
+    }
+}
+
 pub struct World {
+    pub format: Format,
     pub ammo: i16,
     pub gems: i16,
     pub keys: [bool; 7],
     pub health: i16,
     pub starting_board: i16,
+    /// ZZT only
     pub torches: i16,
+    /// ZZT only
     pub torch_cycles: i16,
     pub energizer_cycles: i16,
     pub score: i16,
     pub world_name: Vec<u8>,
-    pub flags: [Vec<u8>; 10],
+    /// 10 flags in ZZT, or 16 in Super ZZT
+    pub flags: Vec<Vec<u8>>,
     pub time: i16,
     pub time_ticks: i16,
     pub locked: bool,
+    /// Stones of Power, Super ZZT only
+    pub stones: i16,
+    /// Header bytes that the game doesn't use, in the order they appear. Some
+    /// editors and ZZT variants store their own data here, so they're kept
+    /// as-is. If there are too few, the rest are written as zeros.
+    ///
+    /// Strings take up a fixed number of bytes, and whatever follows the end
+    /// of a string is kept too: each string field gets a slot here as long as
+    /// the whole field, with the string's own bytes zeroed out.
+    pub header_padding: Vec<u8>,
     pub boards: Vec<Board>,
 }
 
 #[derive(Clone)]
 pub struct Board {
+    pub format: Format,
     pub name: String,
     pub terrain: Vec<[u8; 2]>,
     pub max_shots: u8,
+    /// ZZT only
     pub is_dark: bool,
     pub board_n: u8,
     pub board_s: u8,
     pub board_w: u8,
     pub board_e: u8,
     pub reenter_when_zapped: bool,
+    /// ZZT only
     pub message: Vec<u8>,
     pub enter_x: u8,
     pub enter_y: u8,
+    /// Where the view starts scrolled to, Super ZZT only
+    pub camera_x: i16,
+    /// Where the view starts scrolled to, Super ZZT only
+    pub camera_y: i16,
     pub time_limit: i16,
+    /// Unused bytes after the board's name and message, and at the end of the
+    /// board info, kept like `World::header_padding`
+    pub padding: Vec<u8>,
     pub stats: Vec<Stat>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Stat {
     pub x: u8,
     pub y: u8,
@@ -121,31 +216,111 @@ pub struct Stat {
     pub under_element: u8,
     pub under_color: u8,
     pub instruction_pointer: i16,
-    pub bind_index: i16,
+    /// The stat whose code this one runs, if it's been bound to another
+    /// object's code with `#bind`. Bound stats don't have code of their own.
+    pub bound_to: Option<usize>,
+    /// Where the game kept the stat's code in memory when it saved the world
+    pub data_pointer: [u8; 4],
+    /// Unused bytes at the end of the stat, kept like `World::header_padding`
+    pub padding: Vec<u8>,
     pub code: String,
 }
 
+impl Default for World {
+    /// An empty ZZT world. See `World::new()`.
+    fn default() -> Self {
+        World::new(Format::Zzt)
+    }
+}
+
 impl World {
+    /// An empty world with no boards.
+    pub fn new(format: Format) -> Self {
+        World {
+            format,
+            ammo: 0,
+            gems: 0,
+            keys: [false; 7],
+            health: 0,
+            starting_board: 0,
+            torches: 0,
+            torch_cycles: 0,
+            energizer_cycles: 0,
+            score: 0,
+            world_name: vec![],
+            flags: vec![vec![]; format.num_flags()],
+            time: 0,
+            time_ticks: 0,
+            locked: false,
+            stones: 0,
+            header_padding: vec![],
+            boards: vec![],
+        }
+    }
+
+    /// Read a ZZT or Super ZZT world, depending on its magic number.
     pub fn from_bytes(bytes: &[u8]) -> Result<World, LoadError> {
-        let (input, (_, num_boards)) = (tag(&[0xff, 0xff][..]), le_i16).parse(bytes)?;
+        let (input, magic) = le_i16.parse(bytes)?;
+        let format = [Format::Zzt, Format::SuperZzt]
+            .into_iter()
+            .find(|x| x.magic() == magic)
+            .ok_or("not a ZZT or Super ZZT world")?;
+        let mut world = World::new(format);
+        let mut padding = vec![];
+
+        let (input, num_boards) = le_i16.parse(input)?;
         let (input, (ammo, gems, keys)) = (le_i16, le_i16, count(bool_u8, 7)).parse(input)?;
-        let (input, (health, starting_board, torches, torch_cycles, energizer_cycles)) =
-            (le_i16, le_i16, le_i16, le_i16, le_i16).parse(input)?;
-        let (input, (_, score, world_name)) = (take(2usize), le_i16, pstring(20)).parse(input)?;
-        let (input, flags) = count(pstring(20), 10).parse(input)?;
-        let (_input, (time, time_ticks, locked)) = (le_i16, le_i16, bool_u8).parse(input)?;
+        let (input, (health, starting_board)) = (le_i16, le_i16).parse(input)?;
+        let input = match format {
+            Format::Zzt => {
+                let (input, (torches, torch_cycles, energizer_cycles, unused, score)) =
+                    (le_i16, le_i16, le_i16, take(2usize), le_i16).parse(input)?;
+                padding.extend_from_slice(unused);
+                world.torches = torches;
+                world.torch_cycles = torch_cycles;
+                world.energizer_cycles = energizer_cycles;
+                world.score = score;
+                input
+            }
+            Format::SuperZzt => {
+                let (input, (unused1, score, unused2, energizer_cycles)) =
+                    (take(2usize), le_i16, take(2usize), le_i16).parse(input)?;
+                padding.extend_from_slice(unused1);
+                padding.extend_from_slice(unused2);
+                world.score = score;
+                world.energizer_cycles = energizer_cycles;
+                input
+            }
+        };
+        let (input, (world_name, unused)) = pstring(20).parse(input)?;
+        padding.extend(unused);
+        let (input, flags) = count(pstring(20), format.num_flags()).parse(input)?;
+        let (flags, unused): (Vec<_>, Vec<_>) = flags.into_iter().unzip();
+        padding.extend(unused.concat());
+        let (input, (time, time_ticks, locked)) = (le_i16, le_i16, bool_u8).parse(input)?;
+        let input = match format {
+            Format::Zzt => input,
+            Format::SuperZzt => {
+                let (input, stones) = le_i16.parse(input)?;
+                world.stones = stones;
+                input
+            }
+        };
 
-        // Rest of header is padding; fast-forward starting from original input
-        let (input, _) = take(512usize).parse(bytes)?;
+        // Rest of header is padding
+        let header_len = bytes.len() - input.len();
+        let remaining = format.header_size().saturating_sub(header_len);
+        let (input, unused) = take(remaining).parse(input)?;
+        padding.extend_from_slice(unused);
+        world.header_padding = padding;
 
         // Load boards
         let num_boards = num_boards as usize + 1;
         let (_input, chunks) = count(board_slice, num_boards).parse(input)?;
         let boards: Result<Vec<Board>, LoadError> = chunks
             .iter()
-            .map(|bytes: &&[u8]| Board::from_bytes(bytes))
+            .map(|bytes: &&[u8]| Board::from_bytes(bytes, format))
             .collect();
-        let boards = boards?;
 
         Ok(World {
             ammo,
@@ -153,22 +328,20 @@ impl World {
             keys: keys.try_into().unwrap(),
             health,
             starting_board,
-            torches,
-            torch_cycles,
-            energizer_cycles,
-            score,
             world_name,
-            flags: flags.try_into().unwrap(),
+            flags,
             time,
             time_ticks,
             locked,
-            boards,
+            boards: boards?,
+            ..world
         })
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut result = Vec::with_capacity(512);
-        result.push_i16(-1); // file magic: ZZT world
+        let format = self.format;
+        let mut result = Vec::with_capacity(format.header_size());
+        result.push_i16(format.magic());
         result.push_i16(self.boards.len() as i16 - 1);
         result.push_i16(self.ammo);
         result.push_i16(self.gems);
@@ -177,59 +350,156 @@ impl World {
         }
         result.push_i16(self.health);
         result.push_i16(self.starting_board);
-        result.push_i16(self.torches);
-        result.push_i16(self.torch_cycles);
-        result.push_i16(self.energizer_cycles);
-        result.push_padding(2);
-        result.push_i16(self.score);
-        result.push_string(20, &self.world_name)?;
+        let mut padding = &self.header_padding[..];
+        match format {
+            Format::Zzt => {
+                result.push_i16(self.torches);
+                result.push_i16(self.torch_cycles);
+                result.push_i16(self.energizer_cycles);
+                result.push_preserved(&mut padding, 2);
+                result.push_i16(self.score);
+            }
+            Format::SuperZzt => {
+                result.push_preserved(&mut padding, 2);
+                result.push_i16(self.score);
+                result.push_preserved(&mut padding, 2);
+                result.push_i16(self.energizer_cycles);
+            }
+        }
+        result.push_string(20, &self.world_name, &mut padding)?;
+        if self.flags.len() != format.num_flags() {
+            return Err(format!(
+                "{} worlds must have {} flags",
+                format.name(),
+                format.num_flags()
+            )
+            .into());
+        }
         for flag in &self.flags {
-            result.push_string(20, flag)?;
+            result.push_string(20, flag, &mut padding)?;
         }
         result.push_i16(self.time);
         result.push_i16(self.time_ticks);
         result.push_bool(self.locked);
-        result.push_padding(512 - result.len());
+        if format == Format::SuperZzt {
+            result.push_i16(self.stones);
+        }
+        result.push_preserved(&mut padding, format.header_size() - result.len());
 
         for board in &self.boards {
+            if board.format != format {
+                return Err(format!(
+                    "{} world has a {} board",
+                    format.name(),
+                    board.format.name()
+                )
+                .into());
+            }
             result.extend_from_slice(&board.to_bytes()?);
         }
         Ok(result)
     }
 }
 
+impl Default for Board {
+    /// An empty ZZT board. See `Board::new()`.
+    fn default() -> Self {
+        Board::new(Format::Zzt)
+    }
+}
+
 impl Board {
-    pub fn from_bytes(bytes: &[u8]) -> Result<Board, LoadError> {
+    /// An empty, untitled board with no stats, not even the player.
+    pub fn new(format: Format) -> Self {
+        let (width, height) = format.board_size();
+        Board {
+            format,
+            name: String::new(),
+            terrain: vec![[0, 0]; width * height],
+            max_shots: 255,
+            is_dark: false,
+            board_n: 0,
+            board_s: 0,
+            board_w: 0,
+            board_e: 0,
+            reenter_when_zapped: false,
+            message: vec![],
+            enter_x: 0,
+            enter_y: 0,
+            camera_x: 0,
+            camera_y: 0,
+            time_limit: 0,
+            padding: vec![],
+            stats: vec![],
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8], format: Format) -> Result<Board, LoadError> {
+        let mut board = Board::new(format);
+
         // Ignore length bytes
         let (input, _) = le_u16.parse(bytes)?;
 
         // Read board name
-        let (input, name_bytes) = pstring(50)(input)?;
-        let name = decode_oneline(&name_bytes);
+        let (input, (name_bytes, unused)) = pstring(format.board_name_len())(input)?;
+        board.name = decode_oneline(&name_bytes);
+        board.padding = unused;
 
         // Read terrain
-        const NUM_TILES: usize = 60 * 25;
+        let num_tiles = board.terrain.len();
         let mut input = input;
-        let mut terrain = Vec::with_capacity(NUM_TILES);
-        while terrain.len() < NUM_TILES {
+        let mut terrain = Vec::with_capacity(num_tiles);
+        while terrain.len() < num_tiles {
             let (next_input, (count, element, color)) = (le_u8, le_u8, le_u8).parse(input)?;
             input = next_input;
             let count: u32 = if count == 0 { 256 } else { count.into() };
             for _ in 0..count {
                 terrain.push([element, color]);
-                if terrain.len() > NUM_TILES {
+                if terrain.len() > num_tiles {
                     return Err("too many tiles of board terrain".into());
                 }
             }
         }
+        board.terrain = terrain;
 
         // Read board info
-        let (input, (max_shots, is_dark)) = (le_u8, bool_u8).parse(input)?;
+        let (input, max_shots) = le_u8(input)?;
+        board.max_shots = max_shots;
+        let input = match format {
+            Format::Zzt => {
+                let (input, is_dark) = bool_u8(input)?;
+                board.is_dark = is_dark;
+                input
+            }
+            Format::SuperZzt => input,
+        };
         let (input, (board_n, board_s, board_w, board_e)) =
             (le_u8, le_u8, le_u8, le_u8).parse(input)?;
-        let (input, (reenter_when_zapped, message)) = (bool_u8, pstring(58)).parse(input)?;
-        let (input, (enter_x, enter_y, time_limit)) = (le_u8, le_u8, le_i16).parse(input)?;
-        let (input, _) = take(16usize)(input)?;
+        (board.board_n, board.board_s) = (board_n, board_s);
+        (board.board_w, board.board_e) = (board_w, board_e);
+        let (input, reenter_when_zapped) = bool_u8(input)?;
+        board.reenter_when_zapped = reenter_when_zapped;
+        let input = match format {
+            Format::Zzt => {
+                let (input, ((message, unused), enter_x, enter_y, time_limit, padding)) =
+                    (pstring(58), le_u8, le_u8, le_i16, take(16usize)).parse(input)?;
+                board.message = message;
+                (board.enter_x, board.enter_y) = (enter_x, enter_y);
+                board.time_limit = time_limit;
+                board.padding.extend(unused);
+                board.padding.extend_from_slice(padding);
+                input
+            }
+            Format::SuperZzt => {
+                let (input, (enter_x, enter_y, camera_x, camera_y, time_limit, padding)) =
+                    (le_u8, le_u8, le_i16, le_i16, le_i16, take(14usize)).parse(input)?;
+                (board.enter_x, board.enter_y) = (enter_x, enter_y);
+                (board.camera_x, board.camera_y) = (camera_x, camera_y);
+                board.time_limit = time_limit;
+                board.padding.extend_from_slice(padding);
+                input
+            }
+        };
 
         // Read stats
         let (input, num_stats) = le_i16(input)?;
@@ -237,60 +507,56 @@ impl Board {
         if num_stats < 0 {
             return Err("cannot have a negative number of stats".into());
         }
-        let (_input, stats) = count(Stat::from_bytes, num_stats as usize).parse(input)?;
-
-        Ok(Board {
-            name,
-            terrain,
-            max_shots,
-            is_dark,
-            board_n,
-            board_s,
-            board_e,
-            board_w,
-            reenter_when_zapped,
-            message,
-            enter_x,
-            enter_y,
-            time_limit,
-            stats,
-        })
+        let (_input, stats) =
+            count(|input| Stat::from_bytes(input, format), num_stats as usize).parse(input)?;
+        board.stats = stats;
+
+        Ok(board)
     }
 
-    pub fn to_bytes(&self) -> Result<Vec<u8>, &'static str> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        self.check_binds()?;
+        let format = self.format;
         let mut result = vec![];
         result.push_padding(2); // reserve space for board size
+        let mut padding = &self.padding[..];
         let name_bytes = encode_oneline(&self.name).unwrap();
-        result.push_string(50, &name_bytes)?;
+        result.push_string(format.board_name_len(), &name_bytes, &mut padding)?;
 
         // Encode terrain
-        if self.terrain.len() != 1500 {
-            return Err("invalid number of tiles for board terrain");
-        }
-        let mut iter = self.terrain.iter().peekable();
-        while let Some(tile) = iter.next() {
-            let mut count = 1;
-            while count < 255 && iter.peek().map_or(false, |&next_tile| next_tile == tile) {
-                count += 1;
-                iter.next();
-            }
-            result.push(count);
-            result.extend_from_slice(tile);
+        let (width, height) = format.board_size();
+        if self.terrain.len() != width * height {
+            return Err("invalid number of tiles for board terrain".into());
         }
+        result.extend_from_slice(&self.encode_terrain());
 
         // Board info
         result.push(self.max_shots);
-        result.push_bool(self.is_dark);
+        if format == Format::Zzt {
+            result.push_bool(self.is_dark);
+        }
         result.push(self.board_n);
         result.push(self.board_s);
         result.push(self.board_w);
         result.push(self.board_e);
         result.push_bool(self.reenter_when_zapped);
-        result.push_string(58, &self.message)?;
-        result.push(self.enter_x);
-        result.push(self.enter_y);
-        result.push_i16(self.time_limit);
-        result.push_padding(16);
+        match format {
+            Format::Zzt => {
+                result.push_string(58, &self.message, &mut padding)?;
+                result.push(self.enter_x);
+                result.push(self.enter_y);
+                result.push_i16(self.time_limit);
+                result.push_preserved(&mut padding, 16);
+            }
+            Format::SuperZzt => {
+                result.push(self.enter_x);
+                result.push(self.enter_y);
+                result.push_i16(self.camera_x);
+                result.push_i16(self.camera_y);
+                result.push_i16(self.time_limit);
+                result.push_preserved(&mut padding, 14);
+            }
+        }
 
         // Stats
         let num_stats: i16 = (self.stats.len() - 1)
@@ -298,7 +564,7 @@ impl Board {
             .map_err(|_| "invalid length for stats")?;
         result.push_i16(num_stats);
         for stat in &self.stats {
-            result.extend_from_slice(&stat.to_bytes());
+            result.extend_from_slice(&stat.to_bytes(format));
         }
 
         // Fix up board size
@@ -309,19 +575,114 @@ impl Board {
 
         Ok(result)
     }
+
+    /// Read a standalone board file (`.BRD`), like the ones KevEdit saves.
+    ///
+    /// A board file holds one ZZT board, stored exactly the way it would be in
+    /// a world, size and all.
+    pub fn from_brd(bytes: &[u8]) -> Result<Board, LoadError> {
+        let (rest, chunk) = board_slice(bytes)?;
+        if !rest.is_empty() {
+            return Err("extra data after the end of the board".into());
+        }
+        Board::from_bytes(chunk, Format::Zzt)
+    }
+
+    /// Write the board as a standalone board file (`.BRD`).
+    pub fn to_brd(&self) -> Result<Vec<u8>, String> {
+        if self.format != Format::Zzt {
+            return Err(format!(
+                "board files hold ZZT boards, not {} boards",
+                self.format.name()
+            ));
+        }
+        self.to_bytes()
+    }
+
+    /// Check that bound stats can be written to a world file.
+    ///
+    /// A bind to stat 0 would look like a stat with no code, so stats can't be
+    /// bound to the player. Bound stats share their code, so they can't have
+    /// any of their own.
+    ///
+    /// ZZT loads stats in order, and a bound stat gets its code from the stat
+    /// it's bound to, so that stat ought to come first. Worlds that bind to a
+    /// later stat are still written as they are, though; Marzipan's own binds
+    /// (see `minify::bind_duplicates()`) always go to an earlier stat.
+    pub fn check_binds(&self) -> Result<(), String> {
+        for (i, stat) in self.stats.iter().enumerate() {
+            let Some(target) = stat.bound_to else {
+                continue;
+            };
+            if target == 0 {
+                return Err(format!(
+                    "stat {i} is bound to the player, but stats can only be bound to objects"
+                ));
+            }
+            if !stat.code.is_empty() {
+                return Err(format!(
+                    "stat {i} is bound to stat {target}, so it can't have code of its own"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the index of the stat whose code runs for the given stat.
+    ///
+    /// This is usually the stat itself, unless it's been bound to another
+    /// object's code with `#bind`.
+    pub fn code_index(&self, index: usize) -> usize {
+        let mut index = index;
+        while let Some(target) = self.stats[index].bound_to
+            && target < index
+        {
+            index = target;
+        }
+        index
+    }
+
+    /// Run-length encode the terrain, the way it's stored in a world file.
+    pub fn encode_terrain(&self) -> Vec<u8> {
+        let mut result = vec![];
+        let mut iter = self.terrain.iter().peekable();
+        while let Some(tile) = iter.next() {
+            let mut count = 1;
+            while count < 255 && iter.peek().is_some_and(|&next_tile| next_tile == tile) {
+                count += 1;
+                iter.next();
+            }
+            result.push(count);
+            result.extend_from_slice(tile);
+        }
+        result
+    }
 }
 
 impl Stat {
-    pub fn from_bytes(input: &[u8]) -> IResult<&[u8], Self, LoadError> {
+    /// Describe this stat for the user, e.g., `@Guard (12,5)`.
+    pub fn describe(&self) -> String {
+        let first_line = self.code.lines().next();
+        let name = first_line.filter(|x| x.starts_with("@")).unwrap_or("stat");
+        let (x, y) = (self.x, self.y);
+        format!("{name} ({x},{y})")
+    }
+
+    /// Get the object's name from its `@` line, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.code.lines().next()?.strip_prefix('@')
+    }
+
+    pub fn from_bytes(input: &[u8], format: Format) -> IResult<&[u8], Self, LoadError> {
         let (input, (x, y, x_step, y_step)) = (le_u8, le_u8, le_i16, le_i16).parse(input)?;
         let (input, (cycle, p1, p2, p3)) = (le_i16, le_u8, le_u8, le_u8).parse(input)?;
         let (input, (follower, leader)) = (le_i16, le_i16).parse(input)?;
         let (input, (under_element, under_color)) = (le_u8, le_u8).parse(input)?;
-        let (input, _) = take(4usize)(input)?;
+        let (input, data_pointer) = take(4usize)(input)?;
         let (input, (instruction_pointer, length)) = (le_i16, le_i16).parse(input)?;
-        let (input, _) = take(8usize)(input)?;
+        let (input, padding) = take(format.stat_padding())(input)?;
         let (input, code_bytes) = take(0.max(length) as usize)(input)?;
-        let code = decode_multiline(&code_bytes);
+        let code = decode_multiline(code_bytes);
         Ok((
             input,
             Stat {
@@ -338,13 +699,15 @@ impl Stat {
                 under_element,
                 under_color,
                 instruction_pointer,
-                bind_index: 0.min(length),
+                bound_to: (length < 0).then_some(length.unsigned_abs() as usize),
+                data_pointer: data_pointer.try_into().unwrap(),
+                padding: padding.to_vec(),
                 code,
             },
         ))
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self, format: Format) -> Vec<u8> {
         let mut result = vec![];
         result.push(self.x);
         result.push(self.y);
@@ -358,20 +721,21 @@ impl Stat {
         result.push_i16(self.leader);
         result.push(self.under_element);
         result.push(self.under_color);
-        result.push_padding(4);
+        result.extend_from_slice(&self.data_pointer);
         result.push_i16(self.instruction_pointer);
-        // TODO: more safety around valid bind-indexes (positive? negative?)
-        let code_bytes = encode_multiline(&self.code).unwrap();
-        result.push_i16(if self.bind_index < 0 {
-            self.bind_index
-        } else {
-            code_bytes.len() as i16
+
+        // Bound stats store the negated index of the stat they're bound to,
+        // instead of the length of their code. See `Board::check_binds()`.
+        let code_bytes = match self.bound_to {
+            Some(_) => vec![],
+            None => encode_multiline(&self.code).unwrap(),
+        };
+        result.push_i16(match self.bound_to {
+            Some(target) => -(target as i16),
+            None => code_bytes.len() as i16,
         });
-        result.push_padding(8);
-        if self.bind_index >= 0 {
-            // TODO: more safety around bind-index XOR code
-            result.extend_from_slice(&code_bytes);
-        }
+        result.push_preserved(&mut &self.padding[..], format.stat_padding());
+        result.extend_from_slice(&code_bytes);
         result
     }
 }
@@ -381,15 +745,22 @@ fn bool_u8(input: &[u8]) -> IResult<&[u8], bool, LoadError> {
     Ok((input, byte != 0))
 }
 
-fn pstring(cap: u8) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<u8>, LoadError> {
-    move |input: &[u8]| -> IResult<&[u8], Vec<u8>, LoadError> {
+/// A string, and the unused bytes of the field it was stored in, with the
+/// string's own bytes zeroed. See `World::header_padding`.
+type Pstring = (Vec<u8>, Vec<u8>);
+
+/// Read a string that takes up `cap` bytes after its length.
+fn pstring(cap: u8) -> impl Fn(&[u8]) -> IResult<&[u8], Pstring, LoadError> {
+    move |input: &[u8]| -> IResult<&[u8], Pstring, LoadError> {
         let (input, len) = le_u8(input)?;
         if len >= cap {
             return fail().parse(input);
         }
         let (input, data) = take(len)(input)?;
-        let (input, _) = take(cap - len)(input)?;
-        Ok((input, data.to_vec()))
+        let (input, rest) = take(cap - len)(input)?;
+        let mut unused = vec![0; len as usize];
+        unused.extend_from_slice(rest);
+        Ok((input, (data.to_vec(), unused)))
     }
 }
 
@@ -401,8 +772,10 @@ fn board_slice(bytes: &[u8]) -> IResult<&[u8], &[u8], LoadError> {
 trait SerializationHelpers {
     fn push_bool(&mut self, value: bool);
     fn push_i16(&mut self, value: i16);
-    fn push_string(&mut self, cap: u8, value: &[u8]) -> Result<(), &'static str>;
+    fn push_string(&mut self, cap: u8, value: &[u8], saved: &mut &[u8])
+    -> Result<(), &'static str>;
     fn push_padding(&mut self, size: usize);
+    fn push_preserved(&mut self, saved: &mut &[u8], size: usize);
 }
 
 impl SerializationHelpers for Vec<u8> {
@@ -414,17 +787,254 @@ impl SerializationHelpers for Vec<u8> {
         self.extend(value.to_le_bytes());
     }
 
-    fn push_string(&mut self, cap: u8, value: &[u8]) -> Result<(), &'static str> {
+    /// Write a string, followed by the unused bytes of its field from the
+    /// original file. See `pstring()`.
+    fn push_string(
+        &mut self,
+        cap: u8,
+        value: &[u8],
+        saved: &mut &[u8],
+    ) -> Result<(), &'static str> {
         if value.len() > cap as usize {
             return Err("string too long");
         }
         self.push(value.len() as u8);
         self.extend_from_slice(value);
-        self.push_padding(cap as usize - value.len());
+        let mut slot = vec![];
+        slot.push_preserved(saved, cap as usize);
+        self.extend_from_slice(&slot[value.len()..]);
         Ok(())
     }
 
     fn push_padding(&mut self, size: usize) {
         self.resize(self.len() + size, 0);
     }
+
+    /// Write the next `size` bytes that were saved from the original file,
+    /// padding with zeros if there aren't enough.
+    fn push_preserved(&mut self, saved: &mut &[u8], size: usize) {
+        let (bytes, rest) = saved.split_at(size.min(saved.len()));
+        self.extend_from_slice(bytes);
+        self.push_padding(size - bytes.len());
+        *saved = rest;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::fs;
+
+    use super::*;
+
+    /// An object at the given position, with the given code.
+    pub(crate) fn object(x: u8, y: u8, code: &str) -> Stat {
+        Stat {
+            x,
+            y,
+            cycle: 3,
+            follower: -1,
+            leader: -1,
+            code: code.into(),
+            ..Default::default()
+        }
+    }
+    /// A ZZT board with a player, and an object for each piece of code.
+    pub(crate) fn board_with_objects<S: AsRef<str>>(codes: &[S]) -> Board {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        for (i, code) in codes.iter().enumerate() {
+            let (x, y) = (i % 60 + 1, i / 60 + 1);
+            board.stats.push(object(x as u8, y as u8, code.as_ref()));
+        }
+        board
+    }
+
+    #[test]
+    fn test_zzt_round_trip() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let mut world = World::new(Format::Zzt);
+        world
+            .boards
+            .push(Board::from_bytes(&blank, Format::Zzt).unwrap());
+        world.boards[0].stats.push(object(5, 6, "@Guard\n#end\n"));
+        world.torches = 3;
+        world.flags[9] = b"SECRET".to_vec();
+
+        let bytes = world.to_bytes().unwrap();
+        assert_eq!(&bytes[..2], &[0xff, 0xff]);
+        let loaded = World::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.format, Format::Zzt);
+        assert_eq!(loaded.torches, 3);
+        assert_eq!(loaded.flags.len(), 10);
+        assert_eq!(loaded.boards[0].stats[1].code, "@Guard\n#end\n");
+        assert_eq!(loaded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_super_zzt_round_trip() {
+        let mut board = Board::new(Format::SuperZzt);
+        board.name = "Lobby".into();
+        board.terrain[96 * 80 - 1] = [21, 0x0f];
+        board.camera_x = 30;
+        board.camera_y = 40;
+        board.stats.push(object(48, 40, ""));
+        board.stats.push(object(95, 79, "@Guard\n#end\n"));
+        let mut world = World::new(Format::SuperZzt);
+        world.boards.push(board);
+        world.stones = 2;
+        world.flags[15] = b"SECRET".to_vec();
+
+        let bytes = world.to_bytes().unwrap();
+        assert_eq!(&bytes[..2], &[0xfe, 0xff]);
+        assert_eq!(&bytes[389..391], &[2, 0]);
+        let loaded = World::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.format, Format::SuperZzt);
+        assert_eq!(loaded.stones, 2);
+        assert_eq!(loaded.flags[15], b"SECRET");
+        let board = &loaded.boards[0];
+        assert_eq!(board.format, Format::SuperZzt);
+        assert_eq!(board.name, "Lobby");
+        assert_eq!(board.terrain.len(), 96 * 80);
+        assert_eq!(board.terrain[96 * 80 - 1], [21, 0x0f]);
+        assert_eq!((board.camera_x, board.camera_y), (30, 40));
+        assert_eq!((board.stats[1].x, board.stats[1].y), (95, 79));
+        assert_eq!(board.stats[1].code, "@Guard\n#end\n");
+        assert_eq!(loaded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_real_board_round_trip() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let board = Board::from_bytes(&blank, Format::Zzt).unwrap();
+        assert_eq!(board.to_bytes().unwrap(), blank);
+    }
+
+    #[test]
+    fn test_real_world_round_trip() {
+        for path in ["tests/town.zzt", "tests/lobby.szt"] {
+            let bytes = fs::read(path).unwrap();
+            let world = World::from_bytes(&bytes).unwrap();
+            assert_eq!(world.to_bytes().unwrap(), bytes, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_string_padding() {
+        // ZZT leaves old characters behind when a string gets shorter
+        let bytes = fs::read("tests/town.zzt").unwrap();
+        let mut world = World::from_bytes(&bytes).unwrap();
+        assert_eq!(world.world_name, b"TOWN");
+        assert_eq!(
+            &world.header_padding[2..22],
+            b"\0\0\0\0SHIP\0\0\0\0\0\0\0\0\0\0\0\0"
+        );
+
+        // Changing one string doesn't disturb the bytes kept for the others
+        world.world_name = b"CITY OF GOLD".to_vec();
+        world.flags[0].clear();
+        let loaded = World::from_bytes(&world.to_bytes().unwrap()).unwrap();
+        assert_eq!(loaded.world_name, b"CITY OF GOLD");
+        assert_eq!(loaded.flags[0], b"");
+        assert_eq!(
+            &loaded.header_padding[2..22],
+            b"\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"
+        );
+        assert_eq!(loaded.header_padding[22..], world.header_padding[22..]);
+        assert_eq!(loaded.boards[1].message, b"");
+        assert_eq!(loaded.boards[1].padding, world.boards[1].padding);
+    }
+
+    #[test]
+    fn test_brd() {
+        let blank = fs::read("tests/blank.brd").unwrap();
+        let board = Board::from_brd(&blank).unwrap();
+        assert_eq!(board.to_brd().unwrap(), blank);
+
+        // The size has to match the file
+        assert!(Board::from_brd(&blank[..blank.len() - 1]).is_err());
+        let mut extra = blank.clone();
+        extra.push(0);
+        assert!(Board::from_brd(&extra).is_err());
+        assert!(Board::new(Format::SuperZzt).to_brd().is_err());
+    }
+
+    #[test]
+    fn test_padding_round_trip() {
+        for format in [Format::Zzt, Format::SuperZzt] {
+            let mut board = Board::new(format);
+            board.padding = vec![0xaa; 200];
+            let mut player = object(1, 1, "");
+            player.data_pointer = [1, 2, 3, 4];
+            player.padding = vec![0xbb; 8];
+            board.stats.push(player);
+            let mut world = World::new(format);
+            world.header_padding = (1..=100).collect();
+            world.boards.push(board);
+
+            // Missing padding is written as zeros
+            let bytes = world.to_bytes().unwrap();
+            let loaded = World::from_bytes(&bytes).unwrap();
+            // The unused words in the middle, a slot for each string, plus the rest
+            // of the header
+            let padding_len = match format {
+                Format::Zzt => 2 + 20 * 11 + 247,
+                Format::SuperZzt => 4 + 20 * 17 + 633,
+            };
+            assert_eq!(loaded.header_padding.len(), padding_len);
+            assert_eq!(loaded.header_padding[..100], world.header_padding);
+            assert_eq!(loaded.header_padding[100..], vec![0; padding_len - 100]);
+            let board = &loaded.boards[0];
+            assert!(board.padding.iter().all(|&x| x == 0xaa));
+            assert_eq!(board.stats[0].data_pointer, [1, 2, 3, 4]);
+            assert_eq!(board.stats[0].padding, vec![0xbb; format.stat_padding()]);
+            assert_eq!(loaded.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_binds() {
+        let mut world = World::new(Format::Zzt);
+        let mut board = Board::default();
+        board.stats.push(object(1, 1, ""));
+        board.stats.push(object(2, 1, "@Guard\n#end\n"));
+        board.stats.push(Stat {
+            bound_to: Some(1),
+            ..object(3, 1, "")
+        });
+        world.boards.push(board);
+        let bytes = world.to_bytes().unwrap();
+        let loaded = World::from_bytes(&bytes).unwrap();
+        let board = &loaded.boards[0];
+        assert_eq!(board.stats[2].bound_to, Some(1));
+        assert_eq!(board.code_index(2), 1);
+        assert_eq!(board.code_index(1), 1);
+
+        // Bound stats can't diverge from the code they're bound to
+        let mut board = loaded.boards[0].clone();
+        board.stats[2].code = "@Other\n".into();
+        assert!(board.to_bytes().is_err());
+
+        // Binds to later stats are kept as they are
+        let mut board = loaded.boards[0].clone();
+        board.stats[1].bound_to = Some(2);
+        board.stats[1].code.clear();
+        let bytes = board.to_bytes().unwrap();
+        let loaded = Board::from_bytes(&bytes, Format::Zzt).unwrap();
+        assert_eq!(loaded.stats[1].bound_to, Some(2));
+        assert_eq!(loaded.code_index(1), 1);
+        assert_eq!(loaded.to_bytes().unwrap(), bytes);
+
+        // A bind to the player would look like a stat without code
+        let mut board = loaded.clone();
+        board.stats[2].bound_to = Some(0);
+        assert!(board.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_mismatched_formats() {
+        let mut world = World::new(Format::SuperZzt);
+        world.boards.push(Board::new(Format::Zzt));
+        assert!(world.to_bytes().is_err());
+        assert!(World::from_bytes(&[0x00, 0xff, 0x00, 0x00]).is_err());
+    }
 }
@@ -0,0 +1,167 @@
+//! Run the `marzipan` binary the way a user would, for the commands that are
+//! mostly about which files get read and written.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// A fresh, empty directory for one test's files.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The path of a file in a scratch directory, as an arg.
+fn file(dir: &Path, name: &str) -> String {
+    dir.join(name).to_str().unwrap().into()
+}
+
+/// Copy the town world into a scratch directory, replacing some of its text
+/// with text of the same length.
+fn patched_town(dir: &Path, from: &str, to: &str) -> String {
+    assert_eq!(from.len(), to.len());
+    let mut bytes = fs::read("tests/town.zzt").unwrap();
+    let at = bytes
+        .windows(from.len())
+        .position(|x| x == from.as_bytes())
+        .unwrap();
+    bytes[at..at + to.len()].copy_from_slice(to.as_bytes());
+    let path = file(dir, "TOWN.ZZT");
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn marzipan(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_marzipan"))
+        .args(args)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into()
+}
+
+#[test]
+fn extract_and_insert() {
+    let dir = scratch_dir("extract-and-insert");
+    let board = file(&dir, "TOWN.BRD");
+    let output = marzipan(&["extract", "tests/town.zzt", "Town", "-o", &board]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("Extracted board 1 (\"Town\")"));
+
+    let world = file(&dir, "NEW.ZZT");
+    let output = marzipan(&["insert", "tests/town.zzt", &board, "-o", &world]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("Added board 3 (\"Town\")"));
+
+    // The inserted board is the same as the one that was extracted
+    let again = file(&dir, "AGAIN.BRD");
+    let output = marzipan(&["extract", &world, "3", "-o", &again]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(fs::read(&again).unwrap(), fs::read(&board).unwrap());
+}
+
+#[test]
+fn output_kind_must_match_input() {
+    let dir = scratch_dir("output-kind");
+    let output = marzipan(&["tests/blank.brd", "-o", &file(&dir, "OUT.ZZT")]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("tests/blank.brd is a board file"));
+
+    let output = marzipan(&["tests/town.zzt", "-o", &file(&dir, "OUT.BRD")]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("tests/town.zzt is a world"));
+    assert!(fs::read_dir(&dir).unwrap().next().is_none());
+}
+
+#[test]
+fn compile_worlds() {
+    let dir = scratch_dir("compile-worlds");
+    for (input, name) in [
+        ("tests/town.zzt", "OUT.ZZT"),
+        ("tests/lobby.szt", "OUT.SZT"),
+    ] {
+        let output = marzipan(&["check", input]);
+        assert!(output.status.success(), "{input}: {}", stdout(&output));
+
+        let path = file(&dir, name);
+        let output = marzipan(&[input, "-o", &path, "--minify"]);
+        assert!(output.status.success(), "{input}: {}", stdout(&output));
+        // Written in the same format as the input, going by the magic number
+        assert_eq!(fs::read(&path).unwrap()[..2], fs::read(input).unwrap()[..2]);
+    }
+}
+
+#[test]
+fn check_fails_on_errors() {
+    let dir = scratch_dir("check-fails");
+    let world = patched_town(&dir, "door:open", "door:oops");
+    let output = marzipan(&["check", &world]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("object `door` has no label `oops`"));
+
+    let output = marzipan(&["check", "tests/town.zzt", "-o", &file(&dir, "OUT.ZZT")]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("doesn't take -o"));
+
+    // Neither run wrote anything
+    let names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["TOWN.ZZT"]);
+}
+
+#[test]
+fn watch_recompiles_on_change() {
+    let dir = scratch_dir("watch");
+    let world = file(&dir, "TOWN.ZZT");
+    fs::copy("tests/town.zzt", &world).unwrap();
+    let output = file(&dir, "OUT.ZZT");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_marzipan"))
+        .args(["watch", &world, "-o", &output])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // Wait for the first build, then keep saving an edit until it shows up.
+    // Saving more than once means it doesn't matter if the first save happens
+    // before watch starts looking for changes.
+    let poll = || thread::sleep(Duration::from_millis(100));
+    let mut first = None;
+    for _ in 0..100 {
+        first = fs::read(&output).ok();
+        if first.is_some() {
+            break;
+        }
+        poll();
+    }
+    let first = first.expect("no output from the first build");
+    let mut rebuilt = false;
+    for i in 0..100 {
+        if i % 5 == 0 {
+            patched_town(&dir, "Halt!", "Stop!");
+        }
+        poll();
+        if fs::read(&output).is_ok_and(|x| x != first) {
+            rebuilt = true;
+            break;
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(rebuilt, "output didn't change after editing the world");
+}